@@ -0,0 +1,15 @@
+-- Outgoing ActivityPub activities, queued until the remote inbox accepts them.
+create table activity_delivery (
+    id                  uuid primary key,
+    sender_id           uuid                not null,
+    inbox_url           text                not null,
+    payload             text                not null,
+    -- `pending` until delivered (`succeeded`) or out of attempts (`dead`).
+    status              text                not null        default 'pending',
+    attempts            integer             not null        default 0,
+    next_attempt_at     timestamp           not null        default current_timestamp,
+    last_error          text,
+    created_at          timestamp           not null        default current_timestamp
+);
+
+create index activity_delivery_pending on activity_delivery (next_attempt_at) where status = 'pending';
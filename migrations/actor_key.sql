@@ -0,0 +1,7 @@
+-- Keys local users sign their ActivityPub requests with, generated the first time they are needed.
+create table actor_key (
+    user_id             uuid primary key,
+    public_key_pem      text                not null,
+    private_key_pem     text                not null,
+    created_at          timestamp           not null        default current_timestamp
+);
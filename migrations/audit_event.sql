@@ -0,0 +1,24 @@
+create table audit_event (
+    id              uuid primary key not null,
+    user_id         uuid,
+    actor_id        uuid,
+    event_type      text                  not null,
+    ip              text                  not null,
+    user_agent      text,
+    request_id      text,
+    details         text                  not null default '{}',
+    created_at      timestamp             not null default current_timestamp
+);
+
+create index audit_event_user_id on audit_event (user_id, created_at);
+
+-- The audit log is append-only.
+create trigger audit_event_no_update before update on audit_event
+begin
+    select raise(abort, 'audit_event is append-only');
+end;
+
+create trigger audit_event_no_delete before delete on audit_event
+begin
+    select raise(abort, 'audit_event is append-only');
+end;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- Bookmarks are private, only their owner ever sees them.
+create table bookmark (
+    user_id     uuid                not null,
+    message_id  uuid                not null,
+    created_at  timestamp           not null        default current_timestamp,
+    primary key (user_id, message_id)
+);
+
+create index bookmark_user_id on bookmark (user_id, created_at);
\ No newline at end of file
@@ -0,0 +1,37 @@
+-- Direct messages live apart from `message` so they can never leak into public timelines.
+create table conversation (
+    id                  uuid primary key,
+    -- `direct` for 1:1 conversations, `group` otherwise.
+    kind                text                not null,
+    title               text,
+    -- Both participant ids sorted and joined with `:`, so there is a single direct
+    -- conversation per pair of users. Null for groups.
+    direct_key          text                unique,
+    created_by          uuid                not null,
+    created_at          timestamp           not null        default current_timestamp,
+    last_message_at     timestamp
+);
+
+create table conversation_participant (
+    conversation_id         uuid            not null,
+    user_id                 uuid            not null,
+    joined_at               timestamp       not null        default current_timestamp,
+    -- The `rowid` of the last direct message sent before joining, only later ones are visible.
+    joined_after_rowid      integer         not null        default 0,
+    -- Everything up to this message has been read.
+    last_read_message_id    uuid,
+    muted                   boolean         not null        default false,
+    primary key (conversation_id, user_id)
+);
+
+create index conversation_participant_user_id on conversation_participant (user_id);
+
+create table direct_message (
+    id                  uuid primary key,
+    conversation_id     uuid                not null,
+    sender_id           uuid                not null,
+    body                text                not null,
+    created_at          timestamp           not null        default current_timestamp
+);
+
+create index direct_message_conversation_id on direct_message (conversation_id, created_at);
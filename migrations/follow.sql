@@ -0,0 +1,13 @@
+create table follow (
+    follower_id     uuid                not null,
+    followee_id     uuid                not null,
+    -- Id of the ActivityPub `Follow` activity, which the `Accept` and `Undo` refer to.
+    activity_url    text                not null,
+    -- Follows of remote actors wait for their server to accept them.
+    accepted        boolean             not null        default false,
+    created_at      timestamp           not null        default current_timestamp,
+    primary key (follower_id, followee_id)
+);
+
+create index follow_followee_id on follow (followee_id);
+create index follow_activity_url on follow (activity_url);
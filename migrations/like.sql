@@ -1,5 +1,8 @@
 create table like (
-    id          uuid primary key,
-    message_id  uuid,
-    created_at  timestamp       not null        default current_timestamp
-);
\ No newline at end of file
+    user_id     uuid                not null,
+    message_id  uuid                not null,
+    created_at  timestamp           not null        default current_timestamp,
+    primary key (user_id, message_id)
+);
+
+create index like_message_id on like (message_id);
\ No newline at end of file
@@ -0,0 +1,10 @@
+create table lockout_event (
+    id              uuid primary key not null,
+    user_id         uuid,
+    key             text                  not null,
+    ip              text                  not null,
+    locked_until    timestamp             not null,
+    created_at      timestamp             not null default current_timestamp,
+    unlocked_at     timestamp,
+    unlocked_by     uuid
+);
\ No newline at end of file
@@ -0,0 +1,6 @@
+create table login_failure (
+    key             text primary key not null,
+    failed_count    integer               not null default 0,
+    last_failed_at  timestamp             not null default current_timestamp,
+    locked_until    timestamp
+);
\ No newline at end of file
@@ -3,5 +3,21 @@ create table message (
     author_id           uuid                not null,
     created_at          timestamp           not null        default current_timestamp,
     message             text                not null,
-    message_parent_id   uuid
-);
\ No newline at end of file
+    message_parent_id   uuid,
+    edited_at           timestamp,
+    -- Set on quote posts.
+    quoted_message_id   uuid,
+    -- Deleted messages are kept as tombstones, their content is erased once purged.
+    deleted_at          timestamp,
+    purged_at           timestamp,
+    -- Kept up to date by `counter::counters` in the same transactions as the rows they count.
+    like_count          integer             not null        default 0,
+    -- Replies that aren't deleted.
+    reply_count         integer             not null        default 0,
+    repost_count        integer             not null        default 0
+);
+
+create index message_author_id on message (author_id, created_at);
+create index message_parent_id on message (message_parent_id);
+create index message_quoted_message_id on message (quoted_message_id);
+create index message_deleted_at on message (deleted_at) where purged_at is null;
\ No newline at end of file
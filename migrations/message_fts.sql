@@ -0,0 +1,20 @@
+-- Full-text index of message texts, kept in sync with `message` by the triggers below.
+create virtual table message_fts using fts5(
+    message,
+    content = 'message',
+    content_rowid = 'rowid',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+create trigger message_fts_insert after insert on message begin
+    insert into message_fts (rowid, message) values (new.rowid, new.message);
+end;
+
+create trigger message_fts_delete after delete on message begin
+    insert into message_fts (message_fts, rowid, message) values ('delete', old.rowid, old.message);
+end;
+
+create trigger message_fts_update after update of message on message begin
+    insert into message_fts (message_fts, rowid, message) values ('delete', old.rowid, old.message);
+    insert into message_fts (rowid, message) values (new.rowid, new.message);
+end;
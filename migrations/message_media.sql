@@ -0,0 +1,23 @@
+create table message_media (
+    id              uuid primary key not null,
+    owner_id        uuid                  not null,
+    -- Set once the attachment is referenced by a message.
+    message_id      uuid,
+    position        integer               not null default 0,
+    kind            text                  not null,
+    content_type    text                  not null,
+    storage_key     text                  not null,
+    url             text                  not null,
+    alt_text        text                  not null default '',
+    width           integer,
+    height          integer,
+    blurhash        text,
+    -- JSON array of `{ width, height, url }`.
+    thumbnails      text                  not null default '[]',
+    -- SHA-256 of the uploaded bytes, identical uploads share the same stored files.
+    content_hash    text                  not null,
+    created_at      timestamp             not null default current_timestamp
+);
+
+create index message_media_message_id on message_media (message_id, position);
+create index message_media_content_hash on message_media (content_hash);
\ No newline at end of file
@@ -0,0 +1,11 @@
+create table message_mention (
+    message_id  uuid                not null,
+    -- Not a foreign key, mentions of deleted users are kept and rendered as plain text.
+    user_id     uuid                not null,
+    -- Character offsets of the mention in the message text, `@` included.
+    start_offset    integer         not null,
+    end_offset      integer         not null,
+    primary key (message_id, start_offset)
+);
+
+create index message_mention_user_id on message_mention (user_id);
@@ -0,0 +1,10 @@
+-- Previous versions of edited messages.
+create table message_revision (
+    id              uuid primary key,
+    message_id      uuid                not null,
+    message         text                not null,
+    created_at      timestamp           not null,
+    replaced_at     timestamp           not null        default current_timestamp
+);
+
+create index message_revision_message_id on message_revision (message_id, replaced_at);
@@ -0,0 +1,17 @@
+create table notification (
+    id          uuid primary key,
+    -- The user being notified.
+    user_id     uuid                not null,
+    kind        text                not null,
+    actor_id    uuid                not null,
+    -- The liked message, or the reply or message mentioning the user.
+    message_id  uuid,
+    -- Notifications sharing a key are shown as one, e.g. every like of a message.
+    group_key   text                not null,
+    created_at  timestamp           not null        default current_timestamp,
+    read_at     timestamp
+);
+
+create index notification_user_id on notification (user_id, group_key, created_at);
+create index notification_unread on notification (user_id) where read_at is null;
+create index notification_message_id on notification (message_id);
@@ -0,0 +1,12 @@
+-- Client applications registered through the Mastodon API (`POST /api/v1/apps`).
+create table oauth_app (
+    id              uuid primary key,
+    name            text                not null,
+    website         text,
+    -- Whitespace separated, as sent by the client.
+    redirect_uris   text                not null,
+    scopes          text                not null        default 'read write follow',
+    client_id       text                unique not null,
+    client_secret   text                not null,
+    created_at      timestamp           not null        default current_timestamp
+);
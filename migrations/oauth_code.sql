@@ -0,0 +1,9 @@
+-- Authorization codes waiting to be exchanged for a token, each can only be used once.
+create table oauth_code (
+    code            text primary key,
+    app_id          uuid                not null,
+    user_id         uuid                not null,
+    redirect_uri    text                not null,
+    scopes          text                not null,
+    expires_at      timestamp           not null
+);
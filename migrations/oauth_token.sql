@@ -0,0 +1,7 @@
+-- Access tokens issued to client applications, deleting a row revokes its token.
+create table oauth_token (
+    id              uuid primary key,
+    app_id          uuid                not null,
+    user_id         uuid                not null,
+    created_at      timestamp           not null        default current_timestamp
+);
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- ActivityPub actors from other servers. Each one also has a `user` row, whose `username`
+-- is the actor's `name@domain` handle, so that their messages live in `message` like any other.
+create table remote_actor (
+    user_id             uuid primary key,
+    actor_url           text                unique not null,
+    inbox_url           text                not null,
+    shared_inbox_url    text,
+    public_key_pem      text                not null,
+    fetched_at          timestamp           not null        default current_timestamp
+);
@@ -0,0 +1,5 @@
+-- ActivityPub ids of the messages received from other servers.
+create table remote_object (
+    message_id      uuid primary key,
+    object_url      text                unique not null
+);
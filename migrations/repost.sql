@@ -0,0 +1,9 @@
+create table repost (
+    user_id     uuid                not null,
+    message_id  uuid                not null,
+    created_at  timestamp           not null        default current_timestamp,
+    primary key (user_id, message_id)
+);
+
+create index repost_message_id on repost (message_id, created_at);
+create index repost_created_at on repost (created_at);
@@ -0,0 +1,16 @@
+create table tag (
+    id          uuid primary key,
+    -- Lowercased, without the leading `#`.
+    name        text                not null        unique,
+    created_at  timestamp           not null        default current_timestamp
+);
+
+create table message_tag (
+    message_id  uuid                not null,
+    tag_id      uuid                not null,
+    created_at  timestamp           not null        default current_timestamp,
+    primary key (message_id, tag_id)
+);
+
+create index message_tag_tag_id on message_tag (tag_id);
+create index message_tag_created_at on message_tag (created_at);
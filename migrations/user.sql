@@ -1,10 +1,21 @@
 create table user (
     id              uuid primary key not null,
     username        text                  unique not null,
+    display_name    text                  not null default '',
     email           text                  unique not null,
     bio             text                  not null default '',
     image           text,
+    banner          text,
     password_hash   text                  not null,
+    is_admin        boolean               not null default false,
+    token_version   integer               not null default 0,
     created_at      timestamp             not null default current_timestamp,
-    updated_at      timestamp
-);
\ No newline at end of file
+    updated_at      timestamp,
+    -- Maintained by `counter::counters`, messages that aren't deleted and accepted follows.
+    message_count   integer               not null default 0,
+    follower_count  integer               not null default 0
+);
+
+-- Speeds up the prefix matching of user search.
+create index user_username_nocase on user (username collate nocase);
+create index user_display_name_nocase on user (display_name collate nocase);
\ No newline at end of file
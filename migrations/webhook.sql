@@ -0,0 +1,33 @@
+create table webhook (
+    id          uuid primary key,
+    owner_id    uuid                not null,
+    url         text                not null,
+    -- Key of the HMAC-SHA256 signature sent with every delivery.
+    secret      text                not null,
+    -- JSON array of the event types the endpoint wants.
+    events      text                not null,
+    -- Global webhooks, which only admins can register, receive the events of every user.
+    is_global   boolean             not null        default false,
+    created_at  timestamp           not null        default current_timestamp
+);
+
+create index webhook_owner_id on webhook (owner_id);
+
+-- Doubles as the delivery queue and the delivery log.
+create table webhook_delivery (
+    id                  uuid primary key,
+    webhook_id          uuid                not null,
+    event_type          text                not null,
+    payload             text                not null,
+    -- `pending` until delivered (`succeeded`) or out of attempts (`dead`).
+    status              text                not null        default 'pending',
+    attempts            integer             not null        default 0,
+    next_attempt_at     timestamp           not null        default current_timestamp,
+    last_status_code    integer,
+    last_error          text,
+    created_at          timestamp           not null        default current_timestamp,
+    delivered_at        timestamp
+);
+
+create index webhook_delivery_webhook_id on webhook_delivery (webhook_id, created_at);
+create index webhook_delivery_pending on webhook_delivery (next_attempt_at) where status = 'pending';
@@ -0,0 +1,189 @@
+use crate::{
+    Result,
+    router::{
+        server::ApiContext,
+        extractor::{AdminUser, AuthUser, RequestMeta},
+        pagination::Pagination,
+    },
+};
+use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use axum::{
+    Json,
+    extract::{Extension, Query},
+};
+use sqlx::{Executor, Sqlite};
+use time::PrimitiveDateTime;
+
+/// A security relevant event.
+///
+/// The variant name is stored as the `event_type` of the row and its fields as `details`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event_type", rename_all = "snake_case")]
+pub enum Event {
+    LoginSucceeded,
+    LoginFailed { email: String },
+    PasswordChanged,
+    EmailChanged { old: String, new: String },
+    UsernameChanged { old: String, new: String },
+    TokenCreated,
+    TokensRevoked,
+    AccountUnlocked,
+    MessageDeleted { message_id: Uuid },
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEvent {
+    id: Uuid,
+    user_id: Option<Uuid>,
+    actor_id: Option<Uuid>,
+    event_type: String,
+    ip: String,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+    details: serde_json::Value,
+    created_at: PrimitiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct AuditEventFilter {
+    user_id: Option<Uuid>,
+    actor_id: Option<Uuid>,
+    event_type: Option<String>,
+    ip: Option<String>,
+    // Any datetime understood by SQLite, e.g. `2023-03-01` or `2023-03-01T12:00:00Z`.
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Appends an event to the audit log.
+///
+/// `user_id` is the account the event is about and `actor_id` the user who caused it,
+/// which are only different for admin actions, or `None` for anonymous requests.
+///
+/// Takes any executor so the event can be recorded in the same transaction as the change itself.
+pub async fn record<'c, E>(
+    db: E,
+    meta: &RequestMeta,
+    event: Event,
+    user_id: Option<Uuid>,
+    actor_id: Option<Uuid>,
+) -> Result<()>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let mut details = serde_json::to_value(&event)
+        .expect("BUG: audit events should always serialize");
+
+    let event_type = details
+        .as_object_mut()
+        .and_then(|details| details.remove("event_type"))
+        .and_then(|event_type| event_type.as_str().map(str::to_string))
+        .expect("BUG: audit events should be tagged with their type");
+
+    let id = Uuid::new_v4();
+    let ip = meta.ip.to_string();
+    let details = details.to_string();
+
+    sqlx::query!(
+        r#"
+            insert into audit_event (id, user_id, actor_id, event_type, ip, user_agent, request_id, details)
+            values ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        id,
+        user_id,
+        actor_id,
+        event_type,
+        ip,
+        meta.user_agent,
+        meta.request_id,
+        details
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_security_log(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Query(page): Query<Pagination>
+) -> Result<Json<Vec<AuditEvent>>> {
+    let filter = AuditEventFilter {
+        user_id: Some(auth_user.user_id),
+        limit: Some(page.limit()),
+        offset: Some(page.offset()),
+        ..AuditEventFilter::default()
+    };
+
+    Ok(Json(fetch_events(&ctx, filter).await?))
+}
+
+pub async fn get_audit_events(
+    _: AdminUser,
+    ctx: Extension<ApiContext>,
+    Query(filter): Query<AuditEventFilter>
+) -> Result<Json<Vec<AuditEvent>>> {
+    Ok(Json(fetch_events(&ctx, filter).await?))
+}
+
+async fn fetch_events(ctx: &ApiContext, filter: AuditEventFilter) -> Result<Vec<AuditEvent>> {
+    let page = Pagination {
+        limit: filter.limit,
+        offset: filter.offset,
+    };
+    let (limit, offset) = (page.limit(), page.offset());
+
+    let rows = sqlx::query!(
+        r#"
+            select
+                id as "id!: Uuid",
+                user_id as "user_id: Uuid",
+                actor_id as "actor_id: Uuid",
+                event_type as "event_type!",
+                ip as "ip!",
+                user_agent,
+                request_id,
+                details as "details!",
+                created_at as "created_at!: PrimitiveDateTime"
+            from audit_event
+            where ($1 is null or user_id = $1)
+                and ($2 is null or actor_id = $2)
+                and ($3 is null or event_type = $3)
+                and ($4 is null or ip = $4)
+                and ($5 is null or created_at >= datetime($5))
+                and ($6 is null or created_at < datetime($6))
+            order by created_at desc, rowid desc
+            limit $7 offset $8
+        "#,
+        filter.user_id,
+        filter.actor_id,
+        filter.event_type,
+        filter.ip,
+        filter.since,
+        filter.until,
+        limit,
+        offset
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| AuditEvent {
+            id: row.id,
+            user_id: row.user_id,
+            actor_id: row.actor_id,
+            event_type: row.event_type,
+            ip: row.ip,
+            user_agent: row.user_agent,
+            request_id: row.request_id,
+            details: serde_json::from_str(&row.details).unwrap_or_default(),
+            created_at: row.created_at,
+        })
+        .collect())
+}
@@ -0,0 +1,17 @@
+use crate::audit::events;
+use axum::{
+    routing::get,
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/user/security-log",
+            get(events::get_security_log)
+        )
+        .route(
+            "/api/admin/audit-events",
+            get(events::get_audit_events)
+        )
+}
\ No newline at end of file
@@ -0,0 +1,115 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+    message::{
+        messages::{self, Message, MessageRow},
+        expand::Expand,
+    },
+};
+use uuid::Uuid;
+use time::PrimitiveDateTime;
+use serde::Serialize;
+use axum::{
+    Json,
+    extract::{Path, Extension, Query},
+};
+
+// Unlike likes, bookmarks don't notify anyone, aren't counted and aren't federated.
+
+#[derive(Debug, Serialize)]
+pub struct Bookmark {
+    message_id: Uuid,
+    created_at: PrimitiveDateTime,
+}
+
+/// Bookmarks the message `id` for the current user. Bookmarking a message twice keeps the first bookmark.
+pub async fn create_bookmark(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Bookmark>> {
+    let exists = sqlx::query_scalar!(
+        r#"select exists(select 1 from message where id = $1 and deleted_at is null) as "exists!: bool""#,
+        id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if !exists {
+        return Err(Error::NotFound);
+    }
+
+    let bookmark = sqlx::query_as!(
+        Bookmark,
+        r#"
+            insert into bookmark (user_id, message_id)
+            values ($1, $2)
+            on conflict (user_id, message_id) do update set user_id = user_id
+            returning
+                message_id as "message_id!: Uuid",
+                created_at as "created_at!: PrimitiveDateTime"
+        "#,
+        auth_user.user_id,
+        id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(Json(bookmark))
+}
+
+/// Removes the current user's bookmark of the message `id`.
+pub async fn delete_bookmark(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<()> {
+    let deleted = sqlx::query!(
+        "delete from bookmark where user_id = $1 and message_id = $2",
+        auth_user.user_id,
+        id
+    )
+    .execute(&ctx.db)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(())
+}
+
+/// Lists the messages bookmarked by the current user, most recently bookmarked first.
+pub async fn get_bookmarks(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Query(page): Query<Pagination>,
+    Query(expansions): Query<Expand>
+) -> Result<Json<Vec<Message>>> {
+    let rows = sqlx::query_as::<_, MessageRow>(&format!(
+        r#"
+            select {}
+            from bookmark
+            inner join message on message.id = bookmark.message_id
+            where bookmark.user_id = $1 and message.deleted_at is null
+            order by bookmark.created_at desc, bookmark.rowid desc
+            limit $2 offset $3
+        "#,
+        messages::MESSAGE_COLUMNS
+    ))
+    .bind(auth_user.user_id)
+    .bind(page.limit())
+    .bind(page.offset())
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let messages = messages::hydrate(&ctx.db, rows).await?;
+
+    Ok(Json(messages::embed(&ctx.db, messages, expansions, Some(auth_user.user_id)).await?))
+}
@@ -0,0 +1,2 @@
+pub mod bookmarks;
+pub mod routes;
\ No newline at end of file
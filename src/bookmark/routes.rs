@@ -0,0 +1,18 @@
+use crate::bookmark::bookmarks;
+use axum::{
+    routing::{get, post},
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/message/:id/bookmark",
+            post(bookmarks::create_bookmark)
+            .delete(bookmarks::delete_bookmark)
+        )
+        .route(
+            "/api/user/bookmarks",
+            get(bookmarks::get_bookmarks)
+        )
+}
\ No newline at end of file
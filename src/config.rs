@@ -8,4 +8,114 @@ pub struct Config {
 
     #[clap(long, env)]
     pub hmac_key: String,
+
+    // Address the HTTP server listens on.
+    #[clap(long, env, default_value = "0.0.0.0:3000")]
+    pub bind_address: std::net::SocketAddr,
+
+    // The URL this instance is reachable at, without a trailing slash.
+    // ActivityPub ids and WebFinger handles are derived from it, so it must not change
+    // once other servers have seen them.
+    #[clap(long, env, default_value = "http://localhost:3000")]
+    pub public_url: String,
+
+    // Argon2 memory cost in KiB used when hashing passwords.
+    #[clap(long, env, default_value_t = argon2::Params::DEFAULT_M_COST)]
+    pub argon2_memory_cost: u32,
+
+    // Argon2 number of iterations used when hashing passwords.
+    #[clap(long, env, default_value_t = argon2::Params::DEFAULT_T_COST)]
+    pub argon2_time_cost: u32,
+
+    // Argon2 degree of parallelism used when hashing passwords.
+    #[clap(long, env, default_value_t = argon2::Params::DEFAULT_P_COST)]
+    pub argon2_parallelism: u32,
+
+    // Maximum number of passwords being hashed or verified at the same time.
+    // Hashing is memory and CPU heavy, so this keeps a burst of logins from
+    // exhausting the blocking thread pool.
+    #[clap(long, env, default_value_t = 4)]
+    pub password_hashing_concurrency: usize,
+
+    // How long after posting a message its author can still edit it, in minutes.
+    #[clap(long, env, default_value_t = 60)]
+    pub message_edit_window_minutes: i64,
+
+    // How long deleted messages are kept before their content is purged, in days.
+    #[clap(long, env, default_value_t = 30)]
+    pub message_retention_days: i64,
+
+    // Where uploaded files are stored, either `local` or `s3`.
+    #[clap(long, env, value_enum, default_value_t = StorageBackend::Local)]
+    pub storage_backend: StorageBackend,
+
+    // Directory where uploaded files are stored with the `local` backend.
+    #[clap(long, env, default_value = "uploads")]
+    pub storage_path: String,
+
+    // Lets webhooks call loopback, private and link-local addresses, only meant for local development.
+    #[clap(long, env)]
+    pub webhook_allow_private_addresses: bool,
+
+    // Lets federation reach loopback, private and link-local addresses, only meant for running
+    // several instances on one machine.
+    #[clap(long, env)]
+    pub federation_allow_private_addresses: bool,
+
+    // Signs the presigned upload URLs of the `local` backend, presigned uploads are disabled without it.
+    // Must differ from `hmac_key`, so a leaked upload URL says nothing about the session tokens.
+    #[clap(long, env)]
+    pub storage_signing_key: Option<String>,
+
+    // Prefix of the URLs uploaded files are served from, `/media` by default.
+    // With the `s3` backend, `/media` redirects to presigned URLs so the bucket can stay private,
+    // set this to a CDN or to the URL of a public bucket to serve files straight from there.
+    #[clap(long, env)]
+    pub media_url: Option<String>,
+
+    // Bucket uploaded files are stored in with the `s3` backend.
+    #[clap(long, env)]
+    pub s3_bucket: Option<String>,
+
+    #[clap(long, env, default_value = "us-east-1")]
+    pub s3_region: String,
+
+    // Endpoint of an S3-compatible service like MinIO, e.g. `http://localhost:9000`.
+    // Leave unset to use AWS S3 in `s3_region`.
+    #[clap(long, env)]
+    pub s3_endpoint: Option<String>,
+
+    // Credentials are read from the usual `AWS_*` variables when these are not set.
+    #[clap(long, env)]
+    pub s3_access_key_id: Option<String>,
+
+    #[clap(long, env)]
+    pub s3_secret_access_key: Option<String>,
+
+    // Addresses the bucket as `<endpoint>/<bucket>` instead of `<bucket>.<endpoint>`,
+    // which MinIO requires.
+    #[clap(long, env)]
+    pub s3_path_style: bool,
+
+    // Runs a one-off maintenance command instead of serving the application.
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    // Grants admin rights to the registered user with the given email,
+    // or takes them away with `--revoke`.
+    SetAdmin {
+        email: String,
+
+        #[clap(long)]
+        revoke: bool,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum StorageBackend {
+    Local,
+    S3,
 }
\ No newline at end of file
@@ -0,0 +1,635 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+};
+use uuid::Uuid;
+use time::PrimitiveDateTime;
+use serde::{Deserialize, Serialize};
+use axum::{
+    Json,
+    extract::{Path, Extension, Query},
+};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool, Transaction};
+use std::collections::HashMap;
+
+// Group conversations can't grow past this many participants, the creator included.
+pub const MAX_PARTICIPANTS: usize = 50;
+
+const MAX_BODY_LENGTH: usize = 10_000;
+const MAX_TITLE_LENGTH: usize = 100;
+
+#[derive(Debug, Serialize)]
+pub struct Conversation {
+    id: Uuid,
+    kind: String,
+    title: Option<String>,
+    created_by: Uuid,
+    created_at: PrimitiveDateTime,
+    last_message_at: Option<PrimitiveDateTime>,
+    participants: Vec<Uuid>,
+    // The following are specific to the current user.
+    unread_count: i64,
+    muted: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct ConversationRow {
+    id: Uuid,
+    kind: String,
+    title: Option<String>,
+    created_by: Uuid,
+    created_at: PrimitiveDateTime,
+    last_message_at: Option<PrimitiveDateTime>,
+    muted: bool,
+    unread_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectMessage {
+    id: Uuid,
+    conversation_id: Uuid,
+    sender_id: Uuid,
+    body: String,
+    created_at: PrimitiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateConversation {
+    // Everyone in the conversation except the current user.
+    participant_ids: Vec<Uuid>,
+    title: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DirectMessageRequest {
+    body: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReadMarker {
+    message_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MuteRequest {
+    muted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddParticipants {
+    user_ids: Vec<Uuid>,
+}
+
+/// Starts a conversation between the current user and `participant_ids`.
+///
+/// A single participant makes a direct conversation, and since there is only one per pair
+/// of users, the existing one is returned if they already talked. More participants make
+/// a new group conversation.
+pub async fn create_conversation(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<CreateConversation>
+) -> Result<Json<Conversation>> {
+    let mut participant_ids = req.participant_ids;
+    participant_ids.retain(|id| *id != auth_user.user_id);
+    participant_ids.sort();
+    participant_ids.dedup();
+
+    if participant_ids.is_empty() {
+        return Err(Error::unprocessable_entity([("participant_ids", "must contain another user")]));
+    }
+
+    if participant_ids.len() + 1 > MAX_PARTICIPANTS {
+        return Err(Error::unprocessable_entity([(
+            "participant_ids",
+            format!("cannot contain more than {} users", MAX_PARTICIPANTS - 1),
+        )]));
+    }
+
+    if let Some(title) = &req.title {
+        if title.chars().count() > MAX_TITLE_LENGTH {
+            return Err(Error::unprocessable_entity([("title", "is too long")]));
+        }
+    }
+
+    let mut tx = ctx.db.begin().await?;
+
+    ensure_users_exist(&mut tx, &participant_ids).await?;
+
+    let (kind, direct_key) = match participant_ids.as_slice() {
+        [other] => {
+            let mut pair = [auth_user.user_id, *other];
+            pair.sort();
+            ("direct", Some(format!("{}:{}", pair[0], pair[1])))
+        }
+        _ => ("group", None),
+    };
+
+    if let Some(direct_key) = &direct_key {
+        let existing = sqlx::query_scalar!(
+            r#"select id as "id!: Uuid" from conversation where direct_key = $1"#,
+            direct_key
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        if let Some(id) = existing {
+            tx.commit().await?;
+            return Ok(Json(load_conversation(&ctx.db, id, auth_user.user_id).await?));
+        }
+    }
+
+    // Direct conversations are between two people, a title makes no sense there.
+    let title = if direct_key.is_some() { None } else { req.title };
+
+    let id = Uuid::new_v4();
+
+    sqlx::query!(
+        r#"
+            insert into conversation (id, kind, title, direct_key, created_by)
+            values ($1, $2, $3, $4, $5)
+        "#,
+        id,
+        kind,
+        title,
+        direct_key,
+        auth_user.user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    for user_id in std::iter::once(auth_user.user_id).chain(participant_ids) {
+        sqlx::query!(
+            "insert into conversation_participant (conversation_id, user_id) values ($1, $2)",
+            id,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(load_conversation(&ctx.db, id, auth_user.user_id).await?))
+}
+
+/// Lists the conversations of the current user, most recently active first.
+pub async fn get_conversations(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Query(page): Query<Pagination>
+) -> Result<Json<Vec<Conversation>>> {
+    let (limit, offset) = (page.limit(), page.offset());
+
+    let ids = sqlx::query_scalar!(
+        r#"
+            select conversation.id as "id!: Uuid"
+            from conversation
+            inner join conversation_participant
+                on conversation_participant.conversation_id = conversation.id
+            where conversation_participant.user_id = $1
+            order by coalesce(conversation.last_message_at, conversation.created_at) desc
+            limit $2 offset $3
+        "#,
+        auth_user.user_id,
+        limit,
+        offset
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(load_conversations(&ctx.db, &ids, auth_user.user_id).await?))
+}
+
+pub async fn get_conversation(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Conversation>> {
+    ensure_participant(&ctx.db, id, auth_user.user_id).await?;
+
+    Ok(Json(load_conversation(&ctx.db, id, auth_user.user_id).await?))
+}
+
+/// Lists the messages of a conversation, most recent first.
+///
+/// Participants added to a group only see the messages sent since they joined.
+pub async fn get_direct_messages(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Query(page): Query<Pagination>
+) -> Result<Json<Vec<DirectMessage>>> {
+    ensure_participant(&ctx.db, id, auth_user.user_id).await?;
+
+    let (limit, offset) = (page.limit(), page.offset());
+
+    let messages = sqlx::query_as!(
+        DirectMessage,
+        r#"
+            select
+                id as "id!: Uuid",
+                conversation_id as "conversation_id!: Uuid",
+                sender_id as "sender_id!: Uuid",
+                body as "body!",
+                created_at as "created_at!: PrimitiveDateTime"
+            from direct_message
+            where conversation_id = $1
+                and rowid > (
+                    select joined_after_rowid from conversation_participant
+                    where conversation_id = $1 and user_id = $2
+                )
+            order by rowid desc
+            limit $3 offset $4
+        "#,
+        id,
+        auth_user.user_id,
+        limit,
+        offset
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(messages))
+}
+
+/// Sends a message to a conversation, which also marks the conversation as read for the sender.
+pub async fn create_direct_message(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<DirectMessageRequest>
+) -> Result<Json<DirectMessage>> {
+    if req.body.trim().is_empty() {
+        return Err(Error::unprocessable_entity([("body", "must not be empty")]));
+    }
+
+    if req.body.chars().count() > MAX_BODY_LENGTH {
+        return Err(Error::unprocessable_entity([("body", "is too long")]));
+    }
+
+    let mut tx = ctx.db.begin().await?;
+
+    ensure_participant(&mut tx, id, auth_user.user_id).await?;
+
+    let message_id = Uuid::new_v4();
+
+    let message = sqlx::query_as!(
+        DirectMessage,
+        r#"
+            insert into direct_message (id, conversation_id, sender_id, body)
+            values ($1, $2, $3, $4)
+            returning
+                id as "id!: Uuid",
+                conversation_id as "conversation_id!: Uuid",
+                sender_id as "sender_id!: Uuid",
+                body as "body!",
+                created_at as "created_at!: PrimitiveDateTime"
+        "#,
+        message_id,
+        id,
+        auth_user.user_id,
+        req.body
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        "update conversation set last_message_at = $1 where id = $2",
+        message.created_at,
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            update conversation_participant
+            set last_read_message_id = $1
+            where conversation_id = $2 and user_id = $3
+        "#,
+        message_id,
+        id,
+        auth_user.user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(message))
+}
+
+/// Marks every message of the conversation up to `message_id` as read.
+///
+/// Read markers only move forward, marking an older message as read does nothing.
+pub async fn mark_read(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<ReadMarker>
+) -> Result<Json<Conversation>> {
+    let mut tx = ctx.db.begin().await?;
+
+    ensure_participant(&mut tx, id, auth_user.user_id).await?;
+
+    let updated = sqlx::query!(
+        r#"
+            update conversation_participant
+            set last_read_message_id = $1
+            where conversation_id = $2 and user_id = $3
+                and exists (select 1 from direct_message where id = $1 and conversation_id = $2)
+                and (
+                    last_read_message_id is null
+                    or (select rowid from direct_message where id = $1)
+                        > (select rowid from direct_message where id = last_read_message_id)
+                )
+        "#,
+        req.message_id,
+        id,
+        auth_user.user_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        let exists = sqlx::query_scalar!(
+            r#"select exists(select 1 from direct_message where id = $1 and conversation_id = $2) as "exists!: bool""#,
+            req.message_id,
+            id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        if !exists {
+            return Err(Error::unprocessable_entity([("message_id", "is not part of this conversation")]));
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(load_conversation(&ctx.db, id, auth_user.user_id).await?))
+}
+
+/// Mutes or unmutes a conversation for the current user.
+///
+/// Messages of a muted conversation don't count as unread.
+pub async fn set_muted(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<MuteRequest>
+) -> Result<Json<Conversation>> {
+    let updated = sqlx::query!(
+        r#"
+            update conversation_participant
+            set muted = $1
+            where conversation_id = $2 and user_id = $3
+        "#,
+        req.muted,
+        id,
+        auth_user.user_id
+    )
+    .execute(&ctx.db)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(Json(load_conversation(&ctx.db, id, auth_user.user_id).await?))
+}
+
+/// Adds users to a group conversation. Any participant can add people.
+pub async fn add_participants(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<AddParticipants>
+) -> Result<Json<Conversation>> {
+    let mut tx = ctx.db.begin().await?;
+
+    ensure_participant(&mut tx, id, auth_user.user_id).await?;
+
+    let kind = sqlx::query_scalar!(
+        r#"select kind as "kind!" from conversation where id = $1"#,
+        id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    // Adding someone to a direct conversation would expose its history to them.
+    if kind != "group" {
+        return Err(Error::unprocessable_entity([("user_ids", "can only be added to group conversations")]));
+    }
+
+    ensure_users_exist(&mut tx, &req.user_ids).await?;
+
+    for user_id in &req.user_ids {
+        sqlx::query!(
+            r#"
+                insert into conversation_participant (conversation_id, user_id, joined_after_rowid)
+                values ($1, $2, coalesce((select max(rowid) from direct_message), 0))
+                on conflict (conversation_id, user_id) do nothing
+            "#,
+            id,
+            user_id
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let participants = sqlx::query_scalar!(
+        r#"select count(*) as "count!: i64" from conversation_participant where conversation_id = $1"#,
+        id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    if participants as usize > MAX_PARTICIPANTS {
+        return Err(Error::unprocessable_entity([(
+            "user_ids",
+            format!("a conversation cannot have more than {} participants", MAX_PARTICIPANTS),
+        )]));
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(load_conversation(&ctx.db, id, auth_user.user_id).await?))
+}
+
+/// Leaves a group conversation. Direct conversations can only be muted.
+pub async fn leave_conversation(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<()> {
+    let mut tx = ctx.db.begin().await?;
+
+    ensure_participant(&mut tx, id, auth_user.user_id).await?;
+
+    let left = sqlx::query!(
+        r#"
+            delete from conversation_participant
+            where conversation_id = $1 and user_id = $2
+                and (select kind from conversation where id = $1) = 'group'
+        "#,
+        id,
+        auth_user.user_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if left == 0 {
+        return Err(Error::unprocessable_entity([("conversation", "direct conversations cannot be left")]));
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Only participants may see a conversation, anyone else gets a `404 Not Found`
+/// so that conversation ids can't be probed.
+async fn ensure_participant<'c, E>(db: E, conversation_id: Uuid, user_id: Uuid) -> Result<()>
+where
+    E: sqlx::Executor<'c, Database = Sqlite>,
+{
+    let is_participant = sqlx::query_scalar!(
+        r#"
+            select exists(
+                select 1 from conversation_participant
+                where conversation_id = $1 and user_id = $2
+            ) as "exists!: bool"
+        "#,
+        conversation_id,
+        user_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    if !is_participant {
+        return Err(Error::NotFound);
+    }
+
+    Ok(())
+}
+
+async fn ensure_users_exist(tx: &mut Transaction<'_, Sqlite>, user_ids: &[Uuid]) -> Result<()> {
+    for user_id in user_ids {
+        let exists = sqlx::query_scalar!(
+            r#"select exists(select 1 from user where id = $1) as "exists!: bool""#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if !exists {
+            return Err(Error::unprocessable_entity([("participant_ids", format!("{} does not exist", user_id))]));
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads a conversation as seen by `user_id`, who must be one of its participants.
+async fn load_conversation(db: &SqlitePool, id: Uuid, user_id: Uuid) -> Result<Conversation> {
+    load_conversations(db, &[id], user_id)
+        .await?
+        .pop()
+        .ok_or(Error::NotFound)
+}
+
+/// Loads several conversations as seen by `user_id`, in the order of `ids`.
+///
+/// Conversations `user_id` isn't part of are left out.
+async fn load_conversations(db: &SqlitePool, ids: &[Uuid], user_id: Uuid) -> Result<Vec<Conversation>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut query = QueryBuilder::new(
+        r#"
+            select
+                conversation.id,
+                conversation.kind,
+                conversation.title,
+                conversation.created_by,
+                conversation.created_at,
+                conversation.last_message_at,
+                conversation_participant.muted,
+                case when conversation_participant.muted then 0 else (
+                    select count(*)
+                    from direct_message
+                    where direct_message.conversation_id = conversation.id
+                        and direct_message.sender_id != conversation_participant.user_id
+                        and direct_message.rowid > conversation_participant.joined_after_rowid
+                        and direct_message.rowid > coalesce(
+                            (
+                                select rowid from direct_message
+                                where id = conversation_participant.last_read_message_id
+                            ),
+                            0
+                        )
+                ) end as unread_count
+            from conversation
+            inner join conversation_participant
+                on conversation_participant.conversation_id = conversation.id
+                and conversation_participant.user_id =
+        "#,
+    );
+    query.push_bind(user_id);
+    query.push(" where conversation.id in (");
+
+    let mut separated = query.separated(", ");
+    for id in ids {
+        separated.push_bind(*id);
+    }
+    query.push(")");
+
+    let rows = query
+        .build_query_as::<ConversationRow>()
+        .fetch_all(db)
+        .await?;
+
+    let mut query = QueryBuilder::new(
+        "select conversation_id, user_id from conversation_participant where conversation_id in (",
+    );
+
+    let mut separated = query.separated(", ");
+    for id in ids {
+        separated.push_bind(*id);
+    }
+    query.push(") order by joined_at, rowid");
+
+    let mut participants: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for (conversation_id, user_id) in query.build_query_as::<(Uuid, Uuid)>().fetch_all(db).await? {
+        participants.entry(conversation_id).or_default().push(user_id);
+    }
+
+    let mut rows: HashMap<Uuid, ConversationRow> = rows.into_iter().map(|row| (row.id, row)).collect();
+
+    Ok(ids
+        .iter()
+        .filter_map(|id| rows.remove(id))
+        .map(|row| Conversation {
+            participants: participants.remove(&row.id).unwrap_or_default(),
+            id: row.id,
+            kind: row.kind,
+            title: row.title,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            last_message_at: row.last_message_at,
+            unread_count: row.unread_count,
+            muted: row.muted,
+        })
+        .collect())
+}
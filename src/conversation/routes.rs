@@ -0,0 +1,36 @@
+use crate::conversation::conversations;
+use axum::{
+    routing::{get, post, put},
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/conversations",
+            get(conversations::get_conversations)
+            .post(conversations::create_conversation)
+        )
+        .route(
+            "/api/conversations/:id",
+            get(conversations::get_conversation)
+        )
+        .route(
+            "/api/conversations/:id/messages",
+            get(conversations::get_direct_messages)
+            .post(conversations::create_direct_message)
+        )
+        .route(
+            "/api/conversations/:id/read",
+            post(conversations::mark_read)
+        )
+        .route(
+            "/api/conversations/:id/mute",
+            put(conversations::set_muted)
+        )
+        .route(
+            "/api/conversations/:id/participants",
+            post(conversations::add_participants)
+            .delete(conversations::leave_conversation)
+        )
+}
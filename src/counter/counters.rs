@@ -0,0 +1,248 @@
+use crate::{
+    Result,
+    router::{
+        server::ApiContext,
+        extractor::AdminUser,
+    },
+};
+use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use axum::{
+    Json,
+    extract::{Extension, Query},
+};
+use sqlx::{QueryBuilder, Sqlite, Transaction};
+
+// Keeps the id lists of `recount_*` well under SQLite's limit on bound parameters.
+const RECOUNT_BATCH_SIZE: usize = 500;
+
+/// A counter column updated by increments, in the same transaction as the row it counts.
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    /// `message.like_count`
+    Likes,
+    /// `message.reply_count`, replies that aren't deleted.
+    Replies,
+    /// `message.repost_count`
+    Reposts,
+    /// `user.message_count`, messages that aren't deleted.
+    Messages,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct RecountQuery {
+    // Only reports the drift without fixing it.
+    dry_run: bool,
+}
+
+/// A counter that doesn't match the rows it counts.
+#[derive(Debug, Serialize)]
+pub struct Drift {
+    table: &'static str,
+    id: Uuid,
+    column: &'static str,
+    stored: i64,
+    actual: i64,
+}
+
+/// Adds `delta` to `counter` of the message or user `id`.
+pub async fn add(tx: &mut Transaction<'_, Sqlite>, counter: Counter, id: Uuid, delta: i64) -> Result<()> {
+    match counter {
+        Counter::Likes => {
+            sqlx::query!("update message set like_count = like_count + $1 where id = $2", delta, id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Counter::Replies => {
+            sqlx::query!("update message set reply_count = reply_count + $1 where id = $2", delta, id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Counter::Reposts => {
+            sqlx::query!("update message set repost_count = repost_count + $1 where id = $2", delta, id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Counter::Messages => {
+            sqlx::query!("update user set message_count = message_count + $1 where id = $2", delta, id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Recounts every counter of the users `user_ids` from the rows they count.
+///
+/// Follows change state in too many ways for increments to stay right (pending, accepted,
+/// upserted again by other servers), so `follower_count` is only ever maintained this way.
+pub async fn recount_users(tx: &mut Transaction<'_, Sqlite>, user_ids: &[Uuid]) -> Result<()> {
+    for user_ids in user_ids.chunks(RECOUNT_BATCH_SIZE) {
+        let mut query = QueryBuilder::new(
+            r#"
+                update user set
+                    message_count = (
+                        select count(*) from message
+                        where message.author_id = user.id and message.deleted_at is null
+                    ),
+                    follower_count = (
+                        select count(*) from follow
+                        where follow.followee_id = user.id and follow.accepted
+                    )
+                where id in (
+            "#,
+        );
+
+        let mut ids = query.separated(", ");
+        for id in user_ids {
+            ids.push_bind(*id);
+        }
+        query.push(")");
+
+        query.build().execute(&mut *tx).await?;
+    }
+
+    Ok(())
+}
+
+/// Recounts every counter of the messages `message_ids` from the rows they count.
+pub async fn recount_messages(tx: &mut Transaction<'_, Sqlite>, message_ids: &[Uuid]) -> Result<()> {
+    for message_ids in message_ids.chunks(RECOUNT_BATCH_SIZE) {
+        let mut query = QueryBuilder::new(
+            r#"
+                update message set
+                    like_count = (select count(*) from "like" where "like".message_id = message.id),
+                    reply_count = (
+                        select count(*) from message reply
+                        where reply.message_parent_id = message.id and reply.deleted_at is null
+                    ),
+                    repost_count = (select count(*) from repost where repost.message_id = message.id)
+                where id in (
+            "#,
+        );
+
+        let mut ids = query.separated(", ");
+        for id in message_ids {
+            ids.push_bind(*id);
+        }
+        query.push(")");
+
+        query.build().execute(&mut *tx).await?;
+    }
+
+    Ok(())
+}
+
+/// Compares every counter with the rows it counts and fixes the ones that drifted.
+///
+/// Drift means a write path forgot a counter, so the report is worth keeping an eye on.
+/// Scans the whole `message` and `user` tables, meant to be run by hand or on a schedule.
+pub async fn recount(
+    _: AdminUser,
+    ctx: Extension<ApiContext>,
+    Query(query): Query<RecountQuery>
+) -> Result<Json<Vec<Drift>>> {
+    let mut tx = ctx.db.begin().await?;
+
+    let messages = sqlx::query!(
+        r#"
+            select
+                id as "id!: Uuid",
+                like_count as "like_count!: i64",
+                reply_count as "reply_count!: i64",
+                repost_count as "repost_count!: i64",
+                likes as "likes!: i64",
+                replies as "replies!: i64",
+                reposts as "reposts!: i64"
+            from (
+                select
+                    message.id,
+                    message.like_count,
+                    message.reply_count,
+                    message.repost_count,
+                    (select count(*) from "like" where "like".message_id = message.id) as likes,
+                    (
+                        select count(*) from message reply
+                        where reply.message_parent_id = message.id and reply.deleted_at is null
+                    ) as replies,
+                    (select count(*) from repost where repost.message_id = message.id) as reposts
+                from message
+            )
+            where like_count != likes or reply_count != replies or repost_count != reposts
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let users = sqlx::query!(
+        r#"
+            select
+                id as "id!: Uuid",
+                message_count as "message_count!: i64",
+                follower_count as "follower_count!: i64",
+                messages as "messages!: i64",
+                followers as "followers!: i64"
+            from (
+                select
+                    user.id,
+                    user.message_count,
+                    user.follower_count,
+                    (
+                        select count(*) from message
+                        where message.author_id = user.id and message.deleted_at is null
+                    ) as messages,
+                    (
+                        select count(*) from follow
+                        where follow.followee_id = user.id and follow.accepted
+                    ) as followers
+                from user
+            )
+            where message_count != messages or follower_count != followers
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let mut drift = Vec::new();
+
+    for message in &messages {
+        let columns = [
+            ("like_count", message.like_count, message.likes),
+            ("reply_count", message.reply_count, message.replies),
+            ("repost_count", message.repost_count, message.reposts),
+        ];
+
+        drift.extend(columns.into_iter().filter(|(_, stored, actual)| stored != actual).map(
+            |(column, stored, actual)| Drift { table: "message", id: message.id, column, stored, actual },
+        ));
+    }
+
+    for user in &users {
+        let columns = [
+            ("message_count", user.message_count, user.messages),
+            ("follower_count", user.follower_count, user.followers),
+        ];
+
+        drift.extend(columns.into_iter().filter(|(_, stored, actual)| stored != actual).map(
+            |(column, stored, actual)| Drift { table: "user", id: user.id, column, stored, actual },
+        ));
+    }
+
+    if !drift.is_empty() {
+        log::warn!("found {} drifted counters", drift.len());
+    }
+
+    if !query.dry_run {
+        let message_ids: Vec<Uuid> = messages.iter().map(|message| message.id).collect();
+        let user_ids: Vec<Uuid> = users.iter().map(|user| user.id).collect();
+
+        recount_messages(&mut tx, &message_ids).await?;
+        recount_users(&mut tx, &user_ids).await?;
+
+        tx.commit().await?;
+    }
+
+    Ok(Json(drift))
+}
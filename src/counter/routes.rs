@@ -0,0 +1,13 @@
+use crate::counter::counters;
+use axum::{
+    routing::post,
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/admin/counters/recount",
+            post(counters::recount)
+        )
+}
\ No newline at end of file
@@ -1,4 +1,4 @@
-use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::header::{RETRY_AFTER, WWW_AUTHENTICATE};
 use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
@@ -27,6 +27,10 @@ pub enum Error {
     #[error("request path not found")]
     NotFound,
 
+    /// Return `409 Conflict`
+    #[error("that already exists")]
+    Conflict,
+
     /// Return `422 Unprocessable Entity`
     ///
     /// This also serializes the `errors` map to JSON to satisfy the requirement for
@@ -42,6 +46,13 @@ pub enum Error {
         errors: HashMap<Cow<'static, str>, Vec<Cow<'static, str>>>,
     },
 
+    /// Return `429 Too Many Requests`
+    ///
+    /// `retry_after` is the number of seconds the client should wait before trying again,
+    /// and is sent back in the `Retry-After` header.
+    #[error("too many requests, try again later")]
+    TooManyRequests { retry_after: i64 },
+
     /// Automatically return `500 Internal Server Error` on a `sqlx::Error`.
     ///
     /// Via the generated `From<sqlx::Error> for Error` impl,
@@ -104,7 +115,9 @@ impl Error {
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
             Self::Forbidden => StatusCode::FORBIDDEN,
             Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict => StatusCode::CONFLICT,
             Self::UnprocessableEntity { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
             Self::Sqlx(_) | Self::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -147,6 +160,17 @@ impl IntoResponse for Error {
                     .into_response();
             }
 
+            Self::TooManyRequests { retry_after } => {
+                return (
+                    self.status_code(),
+                    [(RETRY_AFTER, HeaderValue::from(retry_after.max(1)))]
+                        .into_iter()
+                        .collect::<HeaderMap>(),
+                    self.to_string(),
+                )
+                    .into_response();
+            }
+
             Self::Sqlx(ref e) => {
                 // TODO: we probably want to use `tracing` instead
                 // so that this gets linked to the HTTP request by `TraceLayer`.
@@ -192,6 +216,9 @@ pub trait ResultExt<T> {
     /// If `self` contains a SQLx database constraint error with the given name,
     /// transform the error.
     ///
+    /// SQLite doesn't report constraint names, for its `UNIQUE` and `PRIMARY KEY` constraints
+    /// `name` is the table instead.
+    ///
     /// Otherwise, the result is passed through unchanged.
     fn on_constraint(
         self,
@@ -210,10 +237,19 @@ where
         map_err: impl FnOnce(Box<dyn DatabaseError>) -> Error,
     ) -> Result<T, Error> {
         self.map_err(|e| match e.into() {
-            Error::Sqlx(sqlx::Error::Database(dbe)) if dbe.constraint() == Some(name) => {
+            Error::Sqlx(sqlx::Error::Database(dbe))
+                if dbe.constraint().or_else(|| sqlite_constraint_table(&*dbe)) == Some(name) =>
+            {
                 map_err(dbe)
             }
             e => e,
         })
     }
+}
+
+/// The table of a failed SQLite uniqueness constraint,
+/// from messages like `UNIQUE constraint failed: like.user_id, like.message_id`.
+fn sqlite_constraint_table(dbe: &dyn DatabaseError) -> Option<&str> {
+    let columns = dbe.message().strip_prefix("UNIQUE constraint failed: ")?;
+    columns.split_once('.').map(|(table, _)| table)
 }
\ No newline at end of file
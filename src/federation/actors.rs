@@ -0,0 +1,400 @@
+use crate::{
+    Result,
+    Error,
+    router::server::ApiContext,
+    federation::{self, keys, ACTIVITY_JSON},
+};
+use anyhow::Context;
+use axum::{
+    extract::{Extension, Path},
+    http::header::ACCEPT,
+    response::Response,
+};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+// Cached actors are fetched again after this long, which also picks up rotated keys.
+const REFRESH_AFTER: &str = "-1 day";
+
+// Longest bio kept from a remote actor.
+const MAX_BIO_LEN: usize = 2000;
+
+// Largest document read from another server, actors and WebFinger responses take a few KiB.
+const MAX_DOCUMENT_SIZE: usize = 1024 * 1024;
+
+/// An actor on another server, cached in `remote_actor`.
+#[derive(Debug, Clone)]
+pub struct RemoteActor {
+    pub user_id: Uuid,
+    pub actor_url: String,
+    pub inbox_url: String,
+    pub shared_inbox_url: Option<String>,
+    pub public_key_pem: String,
+}
+
+impl RemoteActor {
+    /// Where to deliver activities, preferring the shared inbox which takes
+    /// one request per server instead of one per follower.
+    pub fn delivery_inbox(&self) -> &str {
+        self.shared_inbox_url.as_deref().unwrap_or(&self.inbox_url)
+    }
+}
+
+/// A local user, looked up by username or id.
+pub struct LocalActor {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+/// `GET /users/:id`, the ActivityPub actor of a local user.
+pub async fn get_actor(
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Response> {
+    let actor = get_local(&ctx.db, id).await?.ok_or(Error::NotFound)?;
+
+    let profile = sqlx::query!(
+        "select display_name, bio, image from user where id = $1",
+        actor.user_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let key = keys::get_or_create(&ctx.db, actor.user_id).await?;
+
+    let public_url = &ctx.config.public_url;
+    let id = federation::actor_url(public_url, actor.user_id);
+
+    let mut document = json!({
+        "id": id,
+        "type": "Person",
+        "preferredUsername": actor.username,
+        "name": profile.display_name,
+        "summary": profile.bio,
+        "url": id,
+        "inbox": format!("{}/inbox", id),
+        "outbox": format!("{}/outbox", id),
+        "followers": format!("{}/followers", id),
+        "following": format!("{}/following", id),
+        "endpoints": { "sharedInbox": format!("{}/inbox", public_url) },
+        "publicKey": {
+            "id": format!("{}#main-key", id),
+            "owner": id,
+            "publicKeyPem": key.public_key_pem,
+        },
+    });
+
+    if let Some(image) = profile.image {
+        document["icon"] = json!({ "type": "Image", "url": image });
+    }
+
+    Ok(federation::activity_json(document))
+}
+
+/// `GET /users/:id/followers`. Only the total is public, like on most servers.
+pub async fn get_followers(
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Response> {
+    collection(&ctx, id, "followers").await
+}
+
+/// `GET /users/:id/following`.
+pub async fn get_following(
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Response> {
+    collection(&ctx, id, "following").await
+}
+
+async fn collection(ctx: &ApiContext, user_id: Uuid, name: &str) -> Result<Response> {
+    let actor = get_local(&ctx.db, user_id).await?.ok_or(Error::NotFound)?;
+
+    let total = if name == "followers" {
+        sqlx::query_scalar!(
+            r#"select follower_count as "count!: i64" from user where id = $1"#,
+            actor.user_id
+        )
+        .fetch_one(&ctx.db)
+        .await?
+    } else {
+        sqlx::query_scalar!(
+            r#"select count(*) as "count!: i64" from follow where follower_id = $1 and accepted"#,
+            actor.user_id
+        )
+        .fetch_one(&ctx.db)
+        .await?
+    };
+
+    Ok(federation::activity_json(json!({
+        "id": format!("{}/{}", federation::actor_url(&ctx.config.public_url, actor.user_id), name),
+        "type": "OrderedCollection",
+        "totalItems": total,
+    })))
+}
+
+/// Finds a local user by username. Remote users, whose usernames are `name@domain`
+/// handles, are never returned.
+pub async fn find_local(db: &SqlitePool, username: &str) -> Result<Option<LocalActor>> {
+    let actor = sqlx::query_as!(
+        LocalActor,
+        r#"
+            select id as "user_id!: Uuid", username
+            from user
+            where username = $1
+            and not exists(select 1 from remote_actor where remote_actor.user_id = user.id)
+        "#,
+        username
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(actor)
+}
+
+/// Finds a local user by id, like `find_local`.
+pub async fn get_local(db: &SqlitePool, user_id: Uuid) -> Result<Option<LocalActor>> {
+    let actor = sqlx::query_as!(
+        LocalActor,
+        r#"
+            select id as "user_id!: Uuid", username
+            from user
+            where id = $1
+            and not exists(select 1 from remote_actor where remote_actor.user_id = user.id)
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(actor)
+}
+
+/// Finds the local user an actor URL of this server points to.
+pub async fn find_local_by_url(ctx: &ApiContext, actor_url: &str) -> Result<Option<LocalActor>> {
+    let prefix = format!("{}/users/", ctx.config.public_url);
+
+    match actor_url.strip_prefix(&prefix).and_then(|id| id.parse::<Uuid>().ok()) {
+        Some(user_id) => get_local(&ctx.db, user_id).await,
+        None => Ok(None),
+    }
+}
+
+/// Whether `user_id` is a remote user, and if so which actor.
+pub async fn find_remote(db: &SqlitePool, user_id: Uuid) -> Result<Option<RemoteActor>> {
+    let actor = sqlx::query_as!(
+        RemoteActor,
+        r#"
+            select
+                user_id as "user_id!: Uuid",
+                actor_url,
+                inbox_url,
+                shared_inbox_url,
+                public_key_pem
+            from remote_actor
+            where user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(actor)
+}
+
+/// Loads an actor of another server, from the cache if it was fetched recently.
+pub async fn fetch(ctx: &ApiContext, actor_url: &str) -> Result<RemoteActor> {
+    let cached = sqlx::query_as!(
+        RemoteActor,
+        r#"
+            select
+                user_id as "user_id!: Uuid",
+                actor_url,
+                inbox_url,
+                shared_inbox_url,
+                public_key_pem
+            from remote_actor
+            where actor_url = $1 and fetched_at > datetime('now', $2)
+        "#,
+        actor_url,
+        REFRESH_AFTER
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    if let Some(actor) = cached {
+        return Ok(actor);
+    }
+
+    // Our own actors aren't remote, and shouldn't end up in `remote_actor`.
+    if actor_url.starts_with(&ctx.config.public_url) {
+        return Err(Error::NotFound);
+    }
+
+    let document = get_json(ctx, actor_url).await?;
+
+    store(ctx, actor_url, &document).await
+}
+
+/// Looks up a `name@domain` handle with WebFinger and fetches the actor it points to.
+pub async fn resolve(ctx: &ApiContext, handle: &str) -> Result<RemoteActor> {
+    let handle = handle.trim_start_matches('@');
+
+    let Some((_, domain)) = handle.split_once('@') else {
+        return Err(Error::unprocessable_entity([("account", "must be a name@domain handle")]));
+    };
+
+    // Other servers are assumed to use the same scheme as this one,
+    // so that two instances can federate over plain HTTP in development.
+    let scheme = if ctx.config.public_url.starts_with("http://") { "http" } else { "https" };
+    let url = format!("{}://{}/.well-known/webfinger?resource=acct:{}", scheme, domain, handle);
+
+    let document = get_json(ctx, &url).await?;
+
+    let actor_url = document["links"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|link| link["rel"] == "self" && link["type"] == ACTIVITY_JSON)
+        .and_then(|link| link["href"].as_str())
+        .ok_or(Error::NotFound)?;
+
+    fetch(ctx, actor_url).await
+}
+
+async fn get_json(ctx: &ApiContext, url: &str) -> Result<Value> {
+    federation::check_destination(url, ctx.config.federation_allow_private_addresses)?;
+
+    let response = ctx.http
+        .get(url)
+        .header(ACCEPT, ACTIVITY_JSON)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("failed to fetch {}", url))?;
+
+    let body = federation::read_body(response, MAX_DOCUMENT_SIZE)
+        .await
+        .with_context(|| format!("failed to read {}", url))?
+        .with_context(|| format!("{} is larger than {} bytes", url, MAX_DOCUMENT_SIZE))?;
+
+    let document = serde_json::from_slice(&body).with_context(|| format!("invalid JSON at {}", url))?;
+
+    Ok(document)
+}
+
+/// Creates or updates the `user` and `remote_actor` rows of a fetched actor.
+async fn store(ctx: &ApiContext, actor_url: &str, document: &Value) -> Result<RemoteActor> {
+    if document["id"].as_str() != Some(actor_url) {
+        return Err(anyhow::anyhow!("actor {} has a different id", actor_url).into());
+    }
+
+    let (Some(name), Some(inbox_url), Some(public_key_pem)) = (
+        document["preferredUsername"].as_str(),
+        document["inbox"].as_str(),
+        document["publicKey"]["publicKeyPem"].as_str(),
+    ) else {
+        return Err(anyhow::anyhow!("actor {} is missing required properties", actor_url).into());
+    };
+
+    let domain = reqwest::Url::parse(actor_url)
+        .ok()
+        .map(|url| federation::signatures::host(&url))
+        .with_context(|| format!("invalid actor URL {}", actor_url))?;
+
+    let username = format!("{}@{}", name, domain);
+    let display_name = document["name"].as_str().unwrap_or_default();
+    let bio: String = document["summary"].as_str().unwrap_or_default().chars().take(MAX_BIO_LEN).collect();
+    let image = document["icon"]["url"].as_str();
+    let shared_inbox_url = document["endpoints"]["sharedInbox"].as_str();
+
+    let mut tx = ctx.db.begin().await?;
+
+    let existing = sqlx::query_scalar!(
+        r#"select user_id as "user_id!: Uuid" from remote_actor where actor_url = $1"#,
+        actor_url
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let user_id = match existing {
+        Some(user_id) => {
+            sqlx::query!(
+                r#"
+                    update user
+                    set display_name = $1, bio = $2, image = $3, updated_at = current_timestamp
+                    where id = $4
+                "#,
+                display_name,
+                bio,
+                image,
+                user_id
+            )
+            .execute(&mut tx)
+            .await?;
+
+            sqlx::query!(
+                r#"
+                    update remote_actor
+                    set inbox_url = $1, shared_inbox_url = $2, public_key_pem = $3, fetched_at = current_timestamp
+                    where user_id = $4
+                "#,
+                inbox_url,
+                shared_inbox_url,
+                public_key_pem,
+                user_id
+            )
+            .execute(&mut tx)
+            .await?;
+
+            user_id
+        }
+        None => {
+            let user_id = Uuid::new_v4();
+
+            // The actor URL stands in for the email, which is unique but unknown.
+            // `login_user` leaves remote users out, so the empty password hash is never checked.
+            sqlx::query!(
+                r#"
+                    insert into user (id, username, display_name, email, bio, image, password_hash)
+                    values ($1, $2, $3, $4, $5, $6, '')
+                "#,
+                user_id,
+                username,
+                display_name,
+                actor_url,
+                bio,
+                image
+            )
+            .execute(&mut tx)
+            .await?;
+
+            sqlx::query!(
+                r#"
+                    insert into remote_actor (user_id, actor_url, inbox_url, shared_inbox_url, public_key_pem)
+                    values ($1, $2, $3, $4, $5)
+                "#,
+                user_id,
+                actor_url,
+                inbox_url,
+                shared_inbox_url,
+                public_key_pem
+            )
+            .execute(&mut tx)
+            .await?;
+
+            user_id
+        }
+    };
+
+    tx.commit().await?;
+
+    Ok(RemoteActor {
+        user_id,
+        actor_url: actor_url.to_string(),
+        inbox_url: inbox_url.to_string(),
+        shared_inbox_url: shared_inbox_url.map(str::to_string),
+        public_key_pem: public_key_pem.to_string(),
+    })
+}
@@ -0,0 +1,204 @@
+use crate::{
+    Result,
+    federation::{self, keys, signatures, ACTIVITY_JSON},
+};
+use axum::http::{header::CONTENT_TYPE, Method};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+// How often the queue is checked for deliveries that are due.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+// How many due deliveries are sent per poll.
+const BATCH_SIZE: i64 = 50;
+
+// Servers that stay unreachable for about a day are given up on.
+const MAX_ATTEMPTS: i64 = 10;
+
+// The first retry waits this long, every following one twice as long as the one before.
+const RETRY_BASE_SECONDS: i64 = 60;
+
+// Longest response body kept in `last_error`.
+const MAX_ERROR_LEN: usize = 512;
+
+/// Queues `activity` for every inbox in `inboxes`, signed by the local user `sender_id`.
+pub async fn enqueue(db: &SqlitePool, sender_id: Uuid, inboxes: &[String], activity: &Value) -> Result<()> {
+    if inboxes.is_empty() {
+        return Ok(());
+    }
+
+    // Creates the key now, rather than in the worker for every delivery.
+    keys::get_or_create(db, sender_id).await?;
+
+    let payload = federation::with_context(activity.clone()).to_string();
+
+    let mut tx = db.begin().await?;
+
+    for inbox_url in inboxes {
+        let id = Uuid::new_v4();
+
+        sqlx::query!(
+            "insert into activity_delivery (id, sender_id, inbox_url, payload) values ($1, $2, $3, $4)",
+            id,
+            sender_id,
+            inbox_url,
+            payload
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// The inboxes of the remote followers of `user_id`, one per server where possible.
+pub async fn follower_inboxes(db: &SqlitePool, user_id: Uuid) -> Result<Vec<String>> {
+    let inboxes = sqlx::query_scalar!(
+        r#"
+            select distinct coalesce(remote_actor.shared_inbox_url, remote_actor.inbox_url) as "inbox!"
+            from follow
+            join remote_actor on remote_actor.user_id = follow.follower_id
+            where follow.followee_id = $1 and follow.accepted
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(inboxes)
+}
+
+/// Spawns the task that sends queued activities every `POLL_INTERVAL`.
+pub fn spawn(db: SqlitePool, client: reqwest::Client, public_url: String, allow_private_addresses: bool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = deliver_due(&db, &client, &public_url, allow_private_addresses).await {
+                log::error!("failed to deliver activities: {:?}", e);
+            }
+        }
+    });
+}
+
+struct Due {
+    id: Uuid,
+    inbox_url: String,
+    payload: String,
+    attempts: i64,
+    sender_id: Uuid,
+    private_key_pem: String,
+}
+
+async fn deliver_due(
+    db: &SqlitePool,
+    client: &reqwest::Client,
+    public_url: &str,
+    allow_private_addresses: bool,
+) -> anyhow::Result<()> {
+    let due = sqlx::query_as!(
+        Due,
+        r#"
+            select
+                activity_delivery.id as "id!: Uuid",
+                activity_delivery.inbox_url as "inbox_url!",
+                activity_delivery.payload as "payload!",
+                activity_delivery.attempts as "attempts!: i64",
+                activity_delivery.sender_id as "sender_id!: Uuid",
+                actor_key.private_key_pem as "private_key_pem!"
+            from activity_delivery
+            join actor_key on actor_key.user_id = activity_delivery.sender_id
+            where activity_delivery.status = 'pending'
+            and activity_delivery.next_attempt_at <= current_timestamp
+            order by activity_delivery.next_attempt_at
+            limit $1
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(db)
+    .await?;
+
+    for delivery in due {
+        let attempts = delivery.attempts + 1;
+
+        match send(client, public_url, &delivery, allow_private_addresses).await {
+            Ok(()) => {
+                sqlx::query!(
+                    "update activity_delivery set status = 'succeeded', attempts = $1, last_error = null where id = $2",
+                    attempts,
+                    delivery.id
+                )
+                .execute(db)
+                .await?;
+            }
+            Err(error) => {
+                let status = if attempts >= MAX_ATTEMPTS { "dead" } else { "pending" };
+                let retry_in = format!("+{} seconds", RETRY_BASE_SECONDS << (attempts - 1).min(20));
+
+                sqlx::query!(
+                    r#"
+                        update activity_delivery
+                        set status = $1, attempts = $2, last_error = $3, next_attempt_at = datetime('now', $4)
+                        where id = $5
+                    "#,
+                    status,
+                    attempts,
+                    error,
+                    retry_in,
+                    delivery.id
+                )
+                .execute(db)
+                .await?;
+
+                if status == "dead" {
+                    log::warn!("giving up on delivering {} to {}: {}", delivery.id, delivery.inbox_url, error);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn send(
+    client: &reqwest::Client,
+    public_url: &str,
+    delivery: &Due,
+    allow_private_addresses: bool,
+) -> std::result::Result<(), String> {
+    // Inboxes come from other servers, which could point them anywhere.
+    federation::check_destination(&delivery.inbox_url, allow_private_addresses).map_err(|e| e.to_string())?;
+
+    let url = reqwest::Url::parse(&delivery.inbox_url).map_err(|e| e.to_string())?;
+    let key_id = format!("{}#main-key", federation::actor_url(public_url, delivery.sender_id));
+
+    let headers = signatures::sign(&key_id, &delivery.private_key_pem, &Method::POST, &url, delivery.payload.as_bytes())
+        .map_err(|e| format!("{:?}", e))?;
+
+    let mut request = client
+        .post(url)
+        .header(CONTENT_TYPE, ACTIVITY_JSON)
+        .body(delivery.payload.clone());
+
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+
+    let mut response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(());
+    }
+
+    // Only the start of the body is kept, so reading its first chunk is enough.
+    let body = response.chunk().await.ok().flatten().unwrap_or_default();
+    let body: String = String::from_utf8_lossy(&body).chars().take(MAX_ERROR_LEN).collect();
+
+    Err(format!("{}: {}", status, body))
+}
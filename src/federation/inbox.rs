@@ -0,0 +1,665 @@
+use crate::{
+    Result,
+    Error,
+    router::server::ApiContext,
+    federation::{self, actors::{self, RemoteActor}, delivery, signatures, id_of, PUBLIC},
+    message::mentions,
+    notification::notifications::{self, Kind},
+    tag::tags,
+    counter::counters::{self, Counter},
+    stream::bus::{Audience, EventKind},
+};
+use axum::{
+    body::Bytes,
+    extract::{Extension, OriginalUri},
+    http::{HeaderMap, Method, StatusCode},
+};
+use serde_json::{json, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+/// `POST /inbox` and `POST /users/:id/inbox`.
+///
+/// Both inboxes are handled the same way, activities addressed to a user are
+/// recognized by their `object`. Unsupported activities are accepted and ignored.
+pub async fn post_inbox(
+    ctx: Extension<ApiContext>,
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    body: Bytes
+) -> Result<StatusCode> {
+    let activity: Value = serde_json::from_slice(&body)
+        .map_err(|_| Error::unprocessable_entity([("activity", "invalid JSON")]))?;
+
+    let actor_url = activity["actor"].as_str().unwrap_or_default();
+
+    // Servers announce deleted accounts to everyone, but the key of an account
+    // that's gone can't be fetched anymore. There's nothing to clean up for actors
+    // we never heard of, so those are dropped before checking the signature.
+    if activity["type"] == "Delete" && id_of(&activity["object"]) == Some(actor_url) {
+        let known = sqlx::query_scalar!(
+            r#"select exists(select 1 from remote_actor where actor_url = $1) as "exists!: bool""#,
+            actor_url
+        )
+        .fetch_one(&ctx.db)
+        .await?;
+
+        if !known {
+            return Ok(StatusCode::ACCEPTED);
+        }
+    }
+
+    let path_and_query = uri.path_and_query().map_or("/", |path| path.as_str());
+    let actor = signatures::verify(&ctx, &method, path_and_query, &headers, &body).await?;
+
+    // The signer can only act on their own behalf.
+    if actor.actor_url != actor_url {
+        return Err(Error::Forbidden);
+    }
+
+    match activity["type"].as_str() {
+        Some("Follow") => on_follow(&ctx, &actor, &activity).await?,
+        Some("Accept") => on_accept(&ctx, &actor, &activity).await?,
+        Some("Reject") => on_reject(&ctx, &actor, &activity).await?,
+        Some("Create") => on_create(&ctx, &actor, &activity["object"]).await?,
+        Some("Update") => on_update(&ctx, &actor, &activity["object"]).await?,
+        Some("Like") => on_like(&ctx, &actor, &activity).await?,
+        Some("Announce") => on_announce(&ctx, &actor, &activity).await?,
+        Some("Undo") => on_undo(&ctx, &actor, &activity["object"]).await?,
+        Some("Delete") => on_delete(&ctx, &actor, &activity).await?,
+        other => log::debug!("ignoring {:?} activity from {}", other, actor.actor_url),
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Remote follows of local users are accepted right away.
+async fn on_follow(ctx: &ApiContext, actor: &RemoteActor, activity: &Value) -> Result<()> {
+    let (Some(activity_url), Some(object)) = (id_of(activity), id_of(&activity["object"])) else {
+        return Ok(());
+    };
+
+    let Some(followee) = actors::find_local_by_url(ctx, object).await? else {
+        return Err(Error::NotFound);
+    };
+
+    let mut tx = ctx.db.begin().await?;
+
+    sqlx::query!(
+        r#"
+            insert into follow (follower_id, followee_id, activity_url, accepted)
+            values ($1, $2, $3, true)
+            on conflict (follower_id, followee_id) do update set activity_url = excluded.activity_url, accepted = true
+        "#,
+        actor.user_id,
+        followee.user_id,
+        activity_url
+    )
+    .execute(&mut tx)
+    .await?;
+
+    counters::recount_users(&mut tx, &[followee.user_id]).await?;
+
+    tx.commit().await?;
+
+    let notified = notifications::notify(&ctx.db, followee.user_id, Kind::Follow, actor.user_id, None).await?;
+    notifications::publish(&ctx.events, notified);
+
+    let followee_url = federation::actor_url(&ctx.config.public_url, followee.user_id);
+
+    let accept = json!({
+        "id": format!("{}/accepts/{}", followee_url, Uuid::new_v4()),
+        "type": "Accept",
+        "actor": followee_url,
+        "object": activity,
+    });
+
+    delivery::enqueue(&ctx.db, followee.user_id, std::slice::from_ref(&actor.inbox_url), &accept).await
+}
+
+async fn on_accept(ctx: &ApiContext, actor: &RemoteActor, activity: &Value) -> Result<()> {
+    let Some(follow_url) = id_of(&activity["object"]) else {
+        return Ok(());
+    };
+
+    let mut tx = ctx.db.begin().await?;
+
+    sqlx::query!(
+        "update follow set accepted = true where activity_url = $1 and followee_id = $2",
+        follow_url,
+        actor.user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    counters::recount_users(&mut tx, &[actor.user_id]).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn on_reject(ctx: &ApiContext, actor: &RemoteActor, activity: &Value) -> Result<()> {
+    let Some(follow_url) = id_of(&activity["object"]) else {
+        return Ok(());
+    };
+
+    let mut tx = ctx.db.begin().await?;
+
+    sqlx::query!(
+        "delete from follow where activity_url = $1 and followee_id = $2",
+        follow_url,
+        actor.user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    counters::recount_users(&mut tx, &[actor.user_id]).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Stores notes from actors someone here follows, and replies to messages we know about.
+///
+/// Only public notes are kept, since every message here is public.
+async fn on_create(ctx: &ApiContext, actor: &RemoteActor, object: &Value) -> Result<()> {
+    let Some(object_url) = object["id"].as_str() else {
+        return Ok(());
+    };
+
+    if object["type"] != "Note" || object["attributedTo"].as_str() != Some(actor.actor_url.as_str()) {
+        return Ok(());
+    }
+
+    if !is_public(object) {
+        return Ok(());
+    }
+
+    let already_stored = sqlx::query_scalar!(
+        r#"select exists(select 1 from remote_object where object_url = $1) as "exists!: bool""#,
+        object_url
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if already_stored {
+        return Ok(());
+    }
+
+    let parent_id = match object["inReplyTo"].as_str() {
+        Some(parent_url) => find_message(ctx, parent_url).await?,
+        None => None,
+    };
+
+    let followed = sqlx::query_scalar!(
+        r#"select exists(select 1 from follow where followee_id = $1 and accepted) as "exists!: bool""#,
+        actor.user_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if parent_id.is_none() && !followed {
+        return Ok(());
+    }
+
+    let text = strip_html(object["content"].as_str().unwrap_or_default());
+
+    let created_at = object["published"]
+        .as_str()
+        .and_then(|published| OffsetDateTime::parse(published, &Rfc3339).ok())
+        .unwrap_or_else(OffsetDateTime::now_utc)
+        .to_offset(time::UtcOffset::UTC);
+    let created_at = PrimitiveDateTime::new(created_at.date(), created_at.time());
+
+    let message_id = Uuid::new_v4();
+
+    let mut tx = ctx.db.begin().await?;
+
+    sqlx::query!(
+        r#"
+            insert into message (id, author_id, message, message_parent_id, created_at)
+            values ($1, $2, $3, $4, $5)
+        "#,
+        message_id,
+        actor.user_id,
+        text,
+        parent_id,
+        created_at
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        "insert into remote_object (message_id, object_url) values ($1, $2)",
+        message_id,
+        object_url
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tags::index(&mut tx, message_id, &text).await?;
+
+    counters::add(&mut tx, Counter::Messages, actor.user_id, 1).await?;
+
+    if let Some(parent_id) = parent_id {
+        counters::add(&mut tx, Counter::Replies, parent_id, 1).await?;
+    }
+
+    let parent_author_id = match parent_id {
+        Some(parent_id) => {
+            sqlx::query_scalar!(
+                r#"
+                    select author_id as "author_id!: Uuid" from message
+                    where id = $1
+                    and not exists(select 1 from remote_actor where remote_actor.user_id = message.author_id)
+                "#,
+                parent_id
+            )
+            .fetch_optional(&mut tx)
+            .await?
+        }
+        None => None,
+    };
+
+    let mut notified = Vec::new();
+
+    if let Some(parent_author_id) = parent_author_id {
+        notified.extend(notifications::notify(&mut tx, parent_author_id, Kind::Reply, actor.user_id, Some(message_id)).await?);
+    }
+
+    // Mentions of local users end up as `@name` once the HTML is stripped.
+    for user_id in mentions::index(&mut tx, message_id, &text).await? {
+        if Some(user_id) != parent_author_id {
+            notified.extend(notifications::notify(&mut tx, user_id, Kind::Mention, actor.user_id, Some(message_id)).await?);
+        }
+    }
+
+    tx.commit().await?;
+
+    notifications::publish(&ctx.events, notified);
+
+    Ok(())
+}
+
+/// Applies edits of notes we stored, keeping the previous text in the history like local edits do.
+async fn on_update(ctx: &ApiContext, actor: &RemoteActor, object: &Value) -> Result<()> {
+    let Some(object_url) = object["id"].as_str() else {
+        return Ok(());
+    };
+
+    if object["type"] != "Note" || object["attributedTo"].as_str() != Some(actor.actor_url.as_str()) {
+        return Ok(());
+    }
+
+    let mut tx = ctx.db.begin().await?;
+
+    let current = sqlx::query!(
+        r#"
+            select
+                message.id as "id!: Uuid",
+                message.message as "message!",
+                message.created_at as "created_at!: PrimitiveDateTime",
+                message.edited_at as "edited_at: PrimitiveDateTime"
+            from remote_object
+            join message on message.id = remote_object.message_id
+            where remote_object.object_url = $1
+            and message.author_id = $2 and message.deleted_at is null
+        "#,
+        object_url,
+        actor.user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let Some(current) = current else {
+        return Ok(());
+    };
+
+    let text = strip_html(object["content"].as_str().unwrap_or_default());
+
+    // Servers also send an `Update` when only a poll or an attachment changed.
+    if text == current.message {
+        return Ok(());
+    }
+
+    let revision_id = Uuid::new_v4();
+    let version_created_at = current.edited_at.unwrap_or(current.created_at);
+
+    sqlx::query!(
+        r#"
+            insert into message_revision (id, message_id, message, created_at)
+            values ($1, $2, $3, $4)
+        "#,
+        revision_id,
+        current.id,
+        current.message,
+        version_created_at
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        "update message set message = $1, edited_at = current_timestamp where id = $2",
+        text,
+        current.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tags::index(&mut tx, current.id, &text).await?;
+
+    let mut notified = Vec::new();
+
+    for user_id in mentions::index(&mut tx, current.id, &text).await? {
+        notified.extend(notifications::notify(&mut tx, user_id, Kind::Mention, actor.user_id, Some(current.id)).await?);
+    }
+
+    tx.commit().await?;
+
+    notifications::publish(&ctx.events, notified);
+
+    Ok(())
+}
+
+async fn on_like(ctx: &ApiContext, actor: &RemoteActor, activity: &Value) -> Result<()> {
+    let Some(message_id) = find_object(ctx, &activity["object"]).await? else {
+        return Ok(());
+    };
+
+    let mut tx = ctx.db.begin().await?;
+
+    let inserted = sqlx::query!(
+        "insert into like (user_id, message_id) values ($1, $2) on conflict do nothing",
+        actor.user_id,
+        message_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if inserted > 0 {
+        counters::add(&mut tx, Counter::Likes, message_id, 1).await?;
+    }
+
+    tx.commit().await?;
+
+    if inserted > 0 {
+        let author_id = sqlx::query_scalar!(
+            r#"select author_id as "author_id!: Uuid" from message where id = $1"#,
+            message_id
+        )
+        .fetch_one(&ctx.db)
+        .await?;
+
+        let notified = notifications::notify(&ctx.db, author_id, Kind::Like, actor.user_id, Some(message_id)).await?;
+        notifications::publish(&ctx.events, notified);
+    }
+
+    Ok(())
+}
+
+async fn on_announce(ctx: &ApiContext, actor: &RemoteActor, activity: &Value) -> Result<()> {
+    let Some(message_id) = find_object(ctx, &activity["object"]).await? else {
+        return Ok(());
+    };
+
+    let mut tx = ctx.db.begin().await?;
+
+    let inserted = sqlx::query!(
+        "insert into repost (user_id, message_id) values ($1, $2) on conflict do nothing",
+        actor.user_id,
+        message_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if inserted > 0 {
+        counters::add(&mut tx, Counter::Reposts, message_id, 1).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Only undoes activities embedded in full, a bare id doesn't say what it was.
+async fn on_undo(ctx: &ApiContext, actor: &RemoteActor, object: &Value) -> Result<()> {
+    match object["type"].as_str() {
+        Some("Follow") => {
+            let followee = match id_of(&object["object"]) {
+                Some(followee_url) => actors::find_local_by_url(ctx, followee_url).await?,
+                None => None,
+            };
+
+            if let Some(followee) = followee {
+                let mut tx = ctx.db.begin().await?;
+
+                sqlx::query!(
+                    "delete from follow where follower_id = $1 and followee_id = $2",
+                    actor.user_id,
+                    followee.user_id
+                )
+                .execute(&mut tx)
+                .await?;
+
+                counters::recount_users(&mut tx, &[followee.user_id]).await?;
+
+                tx.commit().await?;
+            }
+        }
+        Some("Like") => {
+            if let Some(message_id) = find_object(ctx, &object["object"]).await? {
+                let mut tx = ctx.db.begin().await?;
+
+                let deleted = sqlx::query!(
+                    "delete from like where user_id = $1 and message_id = $2",
+                    actor.user_id,
+                    message_id
+                )
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+
+                if deleted > 0 {
+                    counters::add(&mut tx, Counter::Likes, message_id, -1).await?;
+                }
+
+                tx.commit().await?;
+            }
+        }
+        Some("Announce") => {
+            if let Some(message_id) = find_object(ctx, &object["object"]).await? {
+                let mut tx = ctx.db.begin().await?;
+
+                let deleted = sqlx::query!(
+                    "delete from repost where user_id = $1 and message_id = $2",
+                    actor.user_id,
+                    message_id
+                )
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+
+                if deleted > 0 {
+                    counters::add(&mut tx, Counter::Reposts, message_id, -1).await?;
+                }
+
+                tx.commit().await?;
+            }
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Deletes a note, or when the object is the actor itself, their account:
+/// their follows are dropped and their messages deleted.
+async fn on_delete(ctx: &ApiContext, actor: &RemoteActor, activity: &Value) -> Result<()> {
+    let Some(object_url) = id_of(&activity["object"]) else {
+        return Ok(());
+    };
+
+    if object_url == actor.actor_url {
+        let mut tx = ctx.db.begin().await?;
+
+        let followee_ids = sqlx::query_scalar!(
+            r#"delete from follow where follower_id = $1 or followee_id = $1 returning followee_id as "followee_id!: Uuid""#,
+            actor.user_id
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        let parent_ids = sqlx::query_scalar!(
+            r#"
+                update message set deleted_at = current_timestamp
+                where author_id = $1 and deleted_at is null
+                returning message_parent_id as "message_parent_id: Uuid"
+            "#,
+            actor.user_id
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        // The users the actor followed lose a follower, the actor loses everything.
+        let mut user_ids = followee_ids;
+        user_ids.push(actor.user_id);
+        user_ids.sort();
+        user_ids.dedup();
+
+        let mut parent_ids: Vec<Uuid> = parent_ids.into_iter().flatten().collect();
+        parent_ids.sort();
+        parent_ids.dedup();
+
+        counters::recount_users(&mut tx, &user_ids).await?;
+        counters::recount_messages(&mut tx, &parent_ids).await?;
+
+        tx.commit().await?;
+
+        return Ok(());
+    }
+
+    let mut tx = ctx.db.begin().await?;
+
+    let deleted = sqlx::query!(
+        r#"
+            update message set deleted_at = current_timestamp
+            where id = (select message_id from remote_object where object_url = $1)
+            and author_id = $2 and deleted_at is null
+            returning id as "id!: Uuid", message_parent_id as "message_parent_id: Uuid"
+        "#,
+        object_url,
+        actor.user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    if let Some(deleted) = &deleted {
+        counters::add(&mut tx, Counter::Messages, actor.user_id, -1).await?;
+
+        if let Some(parent_id) = deleted.message_parent_id {
+            counters::add(&mut tx, Counter::Replies, parent_id, -1).await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    if let Some(deleted) = deleted {
+        ctx.events.publish(EventKind::Delete, Audience::Everyone, json!({ "id": deleted.id }));
+    }
+
+    Ok(())
+}
+
+async fn find_object(ctx: &ApiContext, object: &Value) -> Result<Option<Uuid>> {
+    match id_of(object) {
+        Some(object_url) => find_message(ctx, object_url).await,
+        None => Ok(None),
+    }
+}
+
+/// Finds the message behind an ActivityPub id, either one of our notes or one we received.
+async fn find_message(ctx: &ApiContext, object_url: &str) -> Result<Option<Uuid>> {
+    let local_prefix = format!("{}/notes/", ctx.config.public_url);
+
+    let message_id = match object_url.strip_prefix(&local_prefix) {
+        Some(id) => {
+            let Ok(id) = id.parse::<Uuid>() else {
+                return Ok(None);
+            };
+
+            sqlx::query_scalar!(
+                r#"select id as "id!: Uuid" from message where id = $1 and deleted_at is null"#,
+                id
+            )
+            .fetch_optional(&ctx.db)
+            .await?
+        }
+        None => {
+            sqlx::query_scalar!(
+                r#"
+                    select message_id as "message_id!: Uuid" from remote_object
+                    join message on message.id = remote_object.message_id
+                    where remote_object.object_url = $1 and message.deleted_at is null
+                "#,
+                object_url
+            )
+            .fetch_optional(&ctx.db)
+            .await?
+        }
+    };
+
+    Ok(message_id)
+}
+
+/// Whether `PUBLIC` is in the `to` or `cc` of an object, rather than only followers or
+/// mentioned users. Unlisted notes have it in `cc`, and are public too.
+fn is_public(object: &Value) -> bool {
+    // JSON-LD allows the compacted forms as well.
+    let public = |audience: &Value| matches!(audience.as_str(), Some(PUBLIC | "as:Public" | "Public"));
+
+    [&object["to"], &object["cc"]].into_iter().any(|audience| match audience {
+        Value::Array(audiences) => audiences.iter().any(public),
+        audience => public(audience),
+    })
+}
+
+/// Turns the HTML content of a note into plain text, keeping line and paragraph breaks.
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+
+        let tag = rest[start + 1..start + end].trim().to_lowercase();
+
+        if tag.starts_with("br") {
+            text.push('\n');
+        } else if tag == "/p" {
+            text.push_str("\n\n");
+        }
+
+        rest = &rest[start + end + 1..];
+    }
+
+    text.push_str(rest);
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,101 @@
+use crate::Result;
+use anyhow::Context;
+use rsa::{
+    pkcs1::DecodeRsaPublicKey,
+    pkcs1v15::{Signature, SigningKey, VerifyingKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    signature::{SignatureEncoding, Signer, Verifier},
+    RsaPrivateKey, RsaPublicKey,
+};
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+const KEY_BITS: usize = 2048;
+
+pub struct ActorKey {
+    pub public_key_pem: String,
+    pub private_key_pem: String,
+}
+
+/// Loads the signing key of a local user, generating one the first time.
+pub async fn get_or_create(db: &SqlitePool, user_id: Uuid) -> Result<ActorKey> {
+    let key = sqlx::query_as!(
+        ActorKey,
+        "select public_key_pem, private_key_pem from actor_key where user_id = $1",
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    if let Some(key) = key {
+        return Ok(key);
+    }
+
+    // Generating a key takes a while, keep it off the async threads.
+    let generated = tokio::task::spawn_blocking(generate)
+        .await
+        .context("panic in generating actor key")??;
+
+    // Two requests can race to create the key, whichever inserts first wins.
+    sqlx::query!(
+        r#"
+            insert into actor_key (user_id, public_key_pem, private_key_pem)
+            values ($1, $2, $3)
+            on conflict (user_id) do nothing
+        "#,
+        user_id,
+        generated.public_key_pem,
+        generated.private_key_pem
+    )
+    .execute(db)
+    .await?;
+
+    let key = sqlx::query_as!(
+        ActorKey,
+        "select public_key_pem, private_key_pem from actor_key where user_id = $1",
+        user_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(key)
+}
+
+fn generate() -> anyhow::Result<ActorKey> {
+    let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+        .context("failed to generate RSA key")?;
+
+    let public_key_pem = private_key
+        .to_public_key()
+        .to_public_key_pem(LineEnding::LF)
+        .context("failed to encode public key")?;
+
+    let private_key_pem = private_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .context("failed to encode private key")?
+        .to_string();
+
+    Ok(ActorKey { public_key_pem, private_key_pem })
+}
+
+/// Signs `data` with RSASSA-PKCS1-v1_5 and SHA-256, the `rsa-sha256` of HTTP signatures.
+pub fn sign(private_key_pem: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+        .context("invalid private key")?;
+
+    Ok(SigningKey::<Sha256>::new(private_key).sign(data).to_vec())
+}
+
+/// Verifies a signature made by `sign`. Accepts both SPKI and PKCS#1 encoded public keys,
+/// since other servers publish either.
+pub fn verify(public_key_pem: &str, data: &[u8], signature: &[u8]) -> bool {
+    let public_key = RsaPublicKey::from_public_key_pem(public_key_pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(public_key_pem));
+
+    let (Ok(public_key), Ok(signature)) = (public_key, Signature::try_from(signature)) else {
+        return false;
+    };
+
+    VerifyingKey::<Sha256>::new(public_key).verify(data, &signature).is_ok()
+}
@@ -0,0 +1,120 @@
+pub mod keys;
+pub mod signatures;
+pub mod actors;
+pub mod inbox;
+pub mod outbox;
+pub mod delivery;
+pub mod wellknown;
+pub mod routes;
+
+use crate::{
+    config::Config,
+    webhook::addresses::{self, PublicResolver},
+};
+use axum::{
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+    Json,
+};
+use anyhow::Context;
+use reqwest::redirect;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Media type of ActivityPub documents.
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+
+/// The audience of public posts.
+pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+const CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Same as reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// The HTTP client used to talk to other servers.
+///
+/// Anyone can make us send requests, with the key id of a signature or the inbox of a follow,
+/// so unless `Config::federation_allow_private_addresses` is set they only go to public addresses.
+/// IP addresses in URLs aren't resolved and are checked with `check_destination` instead.
+pub fn client(config: &Config) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(format!("kiwi/{} (+{})", env!("CARGO_PKG_VERSION"), config.public_url))
+        .timeout(REQUEST_TIMEOUT);
+
+    if !config.federation_allow_private_addresses {
+        builder = builder
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if let Err(message) = addresses::check_destination(attempt.url().as_str()) {
+                    attempt.error(message)
+                } else {
+                    attempt.follow()
+                }
+            }));
+    }
+
+    builder.build().context("failed to build the federation HTTP client")
+}
+
+/// Checks `url` before sending a request to it, see `client`.
+pub fn check_destination(url: &str, allow_private_addresses: bool) -> anyhow::Result<()> {
+    if allow_private_addresses {
+        return Ok(());
+    }
+
+    addresses::check_destination(url).map_err(|message| anyhow::anyhow!("{}: {}", url, message))
+}
+
+/// Reads the body of a response up to `limit` bytes, so that another server can't make us
+/// buffer an endless one. Returns `None` if the body is longer.
+pub async fn read_body(mut response: reqwest::Response, limit: usize) -> reqwest::Result<Option<Vec<u8>>> {
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(body))
+}
+
+/// The ActivityPub id of a local user. It is based on the user id since other servers keep it
+/// for good, while usernames can change.
+pub fn actor_url(public_url: &str, user_id: Uuid) -> String {
+    format!("{}/users/{}", public_url, user_id)
+}
+
+pub fn note_url(public_url: &str, message_id: Uuid) -> String {
+    format!("{}/notes/{}", public_url, message_id)
+}
+
+/// Adds the JSON-LD context to a top-level ActivityPub document.
+pub fn with_context(mut document: serde_json::Value) -> serde_json::Value {
+    if let Some(object) = document.as_object_mut() {
+        object.insert("@context".to_string(), CONTEXT.into());
+    }
+
+    document
+}
+
+/// Serializes an ActivityPub document as a response.
+pub fn activity_json(document: serde_json::Value) -> Response {
+    ([(CONTENT_TYPE, ACTIVITY_JSON)], Json(with_context(document))).into_response()
+}
+
+/// The id of a property that can either be a link or an embedded object.
+pub fn id_of(value: &serde_json::Value) -> Option<&str> {
+    match value {
+        serde_json::Value::String(id) => Some(id),
+        serde_json::Value::Object(object) => object.get("id").and_then(|id| id.as_str()),
+        _ => None,
+    }
+}
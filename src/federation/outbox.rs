@@ -0,0 +1,444 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+    federation::{self, actors::{self, RemoteActor}, delivery, PUBLIC},
+    counter::counters,
+};
+use axum::{
+    extract::{Extension, Path, Query},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use time::{format_description::well_known::Rfc3339, PrimitiveDateTime};
+use uuid::Uuid;
+
+struct NoteRow {
+    id: Uuid,
+    author_id: Uuid,
+    message: String,
+    created_at: PrimitiveDateTime,
+    edited_at: Option<PrimitiveDateTime>,
+    message_parent_id: Option<Uuid>,
+    // Set when replying to a message from another server.
+    parent_object_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FollowRequest {
+    // A `name@domain` handle.
+    account: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Following {
+    user_id: Uuid,
+    username: String,
+    // Becomes true once the other server accepts the follow.
+    accepted: bool,
+}
+
+/// `GET /notes/:id`, a message of a local user as an ActivityPub `Note`.
+pub async fn get_note(
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Response> {
+    let note = fetch_note(&ctx, id).await?.ok_or(Error::NotFound)?;
+
+    Ok(federation::activity_json(to_note(&ctx, &note)))
+}
+
+/// `GET /users/:id/outbox`, the latest messages of a local user as `Create` activities.
+pub async fn get_outbox(
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Query(page): Query<Pagination>
+) -> Result<Response> {
+    let actor = actors::get_local(&ctx.db, id).await?.ok_or(Error::NotFound)?;
+
+    let (limit, offset) = (page.limit(), page.offset());
+
+    let total = sqlx::query_scalar!(
+        r#"select message_count as "count!: i64" from user where id = $1"#,
+        actor.user_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let notes = sqlx::query_as!(
+        NoteRow,
+        r#"
+            select
+                message.id as "id!: Uuid",
+                message.author_id as "author_id!: Uuid",
+                message.message as "message!",
+                message.created_at as "created_at!: PrimitiveDateTime",
+                message.edited_at as "edited_at: PrimitiveDateTime",
+                message.message_parent_id as "message_parent_id: Uuid",
+                remote_object.object_url as "parent_object_url?"
+            from message
+            left join remote_object on remote_object.message_id = message.message_parent_id
+            where message.author_id = $1 and message.deleted_at is null
+            order by message.created_at desc
+            limit $2 offset $3
+        "#,
+        actor.user_id,
+        limit,
+        offset
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let items: Vec<Value> = notes.iter().map(|note| create_activity(&ctx, note)).collect();
+
+    Ok(federation::activity_json(json!({
+        "id": format!("{}/outbox", federation::actor_url(&ctx.config.public_url, actor.user_id)),
+        "type": "OrderedCollection",
+        "totalItems": total,
+        "orderedItems": items,
+    })))
+}
+
+/// Follows a user of another server, looked up by their `name@domain` handle.
+pub async fn follow(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<FollowRequest>
+) -> Result<Json<Following>> {
+    let followee = actors::resolve(&ctx, &req.account).await?;
+
+    let accepted = follow_remote(&ctx, auth_user.user_id, &followee).await?;
+
+    let username = sqlx::query_scalar!("select username from user where id = $1", followee.user_id)
+        .fetch_one(&ctx.db)
+        .await?;
+
+    Ok(Json(Following { user_id: followee.user_id, username, accepted }))
+}
+
+/// Stops following a user of another server.
+pub async fn unfollow(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(user_id): Path<Uuid>
+) -> Result<()> {
+    if !unfollow_user(&ctx, auth_user.user_id, user_id).await? {
+        return Err(Error::NotFound);
+    }
+
+    Ok(())
+}
+
+/// Records a follow of a remote actor and sends the `Follow`, returning whether
+/// their server already accepted an earlier one.
+pub async fn follow_remote(ctx: &ApiContext, follower_id: Uuid, followee: &RemoteActor) -> Result<bool> {
+    let activity_url = format!("{}/follows/{}", ctx.config.public_url, Uuid::new_v4());
+
+    let accepted = sqlx::query_scalar!(
+        r#"
+            insert into follow (follower_id, followee_id, activity_url)
+            values ($1, $2, $3)
+            on conflict (follower_id, followee_id) do update set activity_url = excluded.activity_url
+            returning accepted as "accepted!: bool"
+        "#,
+        follower_id,
+        followee.user_id,
+        activity_url
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let actor_url = federation::actor_url(&ctx.config.public_url, follower_id);
+
+    let activity = json!({
+        "id": activity_url,
+        "type": "Follow",
+        "actor": actor_url,
+        "object": followee.actor_url,
+    });
+
+    delivery::enqueue(&ctx.db, follower_id, std::slice::from_ref(&followee.inbox_url), &activity).await?;
+
+    Ok(accepted)
+}
+
+/// Removes a follow, sending an `Undo` if the followee is on another server.
+/// Returns false if there was no such follow.
+pub async fn unfollow_user(ctx: &ApiContext, follower_id: Uuid, followee_id: Uuid) -> Result<bool> {
+    let mut tx = ctx.db.begin().await?;
+
+    let activity_url = sqlx::query_scalar!(
+        r#"delete from follow where follower_id = $1 and followee_id = $2 returning activity_url as "activity_url!""#,
+        follower_id,
+        followee_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let Some(activity_url) = activity_url else {
+        return Ok(false);
+    };
+
+    counters::recount_users(&mut tx, &[followee_id]).await?;
+
+    tx.commit().await?;
+
+    let Some(followee) = actors::find_remote(&ctx.db, followee_id).await? else {
+        return Ok(true);
+    };
+
+    let actor_url = federation::actor_url(&ctx.config.public_url, follower_id);
+
+    let activity = json!({
+        "id": format!("{}/undo", activity_url),
+        "type": "Undo",
+        "actor": actor_url,
+        "object": {
+            "id": activity_url,
+            "type": "Follow",
+            "actor": actor_url,
+            "object": followee.actor_url,
+        },
+    });
+
+    delivery::enqueue(&ctx.db, follower_id, &[followee.inbox_url], &activity).await?;
+
+    Ok(true)
+}
+
+/// Sends a new message of a local user to their followers on other servers,
+/// and to the author of the message it replies to if they are on another server.
+pub async fn publish_create(ctx: &ApiContext, message_id: Uuid) -> Result<()> {
+    let Some(note) = fetch_note(ctx, message_id).await? else {
+        return Ok(());
+    };
+
+    let inboxes = note_inboxes(ctx, &note).await?;
+    let activity = create_activity(ctx, &note);
+
+    delivery::enqueue(&ctx.db, note.author_id, &inboxes, &activity).await
+}
+
+/// Sends the new text of an edited message wherever `publish_create` sent the message.
+pub async fn publish_update(ctx: &ApiContext, message_id: Uuid) -> Result<()> {
+    let Some(note) = fetch_note(ctx, message_id).await? else {
+        return Ok(());
+    };
+
+    let inboxes = note_inboxes(ctx, &note).await?;
+
+    if inboxes.is_empty() {
+        return Ok(());
+    }
+
+    let object = to_note(ctx, &note);
+    let edited_at = note.edited_at.unwrap_or(note.created_at).assume_utc().unix_timestamp();
+
+    let activity = json!({
+        // Each edit is its own activity.
+        "id": format!("{}#updates/{}", object["id"].as_str().unwrap_or_default(), edited_at),
+        "type": "Update",
+        "actor": object["attributedTo"],
+        "to": object["to"],
+        "cc": object["cc"],
+        "object": object,
+    });
+
+    delivery::enqueue(&ctx.db, note.author_id, &inboxes, &activity).await
+}
+
+/// The followers of the author on other servers, and the author of the parent message
+/// if they are on another server.
+async fn note_inboxes(ctx: &ApiContext, note: &NoteRow) -> Result<Vec<String>> {
+    let mut inboxes = delivery::follower_inboxes(&ctx.db, note.author_id).await?;
+
+    if let Some(parent_id) = note.message_parent_id {
+        let parent_author = sqlx::query_scalar!(
+            r#"select author_id as "author_id!: Uuid" from message where id = $1"#,
+            parent_id
+        )
+        .fetch_optional(&ctx.db)
+        .await?;
+
+        if let Some(parent_author) = parent_author {
+            if let Some(actor) = actors::find_remote(&ctx.db, parent_author).await? {
+                inboxes.push(actor.delivery_inbox().to_string());
+            }
+        }
+    }
+
+    inboxes.sort();
+    inboxes.dedup();
+
+    Ok(inboxes)
+}
+
+/// Tells the followers of `author_id` on other servers that a message was deleted.
+pub async fn publish_delete(ctx: &ApiContext, author_id: Uuid, message_id: Uuid) -> Result<()> {
+    let inboxes = delivery::follower_inboxes(&ctx.db, author_id).await?;
+
+    if inboxes.is_empty() {
+        return Ok(());
+    }
+
+    let actor_url = federation::actor_url(&ctx.config.public_url, author_id);
+    let note_url = federation::note_url(&ctx.config.public_url, message_id);
+
+    let activity = json!({
+        "id": format!("{}/delete", note_url),
+        "type": "Delete",
+        "actor": actor_url,
+        "to": [PUBLIC],
+        "object": { "id": note_url, "type": "Tombstone" },
+    });
+
+    delivery::enqueue(&ctx.db, author_id, &inboxes, &activity).await
+}
+
+/// Sends a `Like` to the server of the author when a local user likes a message from another server.
+pub async fn publish_like(ctx: &ApiContext, user_id: Uuid, message_id: Uuid) -> Result<()> {
+    publish_like_activity(ctx, user_id, message_id, false).await
+}
+
+/// Takes back a like sent by `publish_like`.
+pub async fn publish_unlike(ctx: &ApiContext, user_id: Uuid, message_id: Uuid) -> Result<()> {
+    publish_like_activity(ctx, user_id, message_id, true).await
+}
+
+async fn publish_like_activity(ctx: &ApiContext, user_id: Uuid, message_id: Uuid, undo: bool) -> Result<()> {
+    let object = sqlx::query!(
+        r#"
+            select remote_object.object_url, message.author_id as "author_id!: Uuid"
+            from remote_object
+            join message on message.id = remote_object.message_id
+            where remote_object.message_id = $1
+        "#,
+        message_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    let Some(object) = object else {
+        return Ok(());
+    };
+
+    let Some(author) = actors::find_remote(&ctx.db, object.author_id).await? else {
+        return Ok(());
+    };
+
+    let actor_url = federation::actor_url(&ctx.config.public_url, user_id);
+    let like_url = format!("{}/likes/{}", actor_url, message_id);
+
+    let like = json!({
+        "id": like_url,
+        "type": "Like",
+        "actor": actor_url,
+        "object": object.object_url,
+    });
+
+    let activity = if undo {
+        json!({
+            "id": format!("{}/undo", like_url),
+            "type": "Undo",
+            "actor": actor_url,
+            "object": like,
+        })
+    } else {
+        like
+    };
+
+    delivery::enqueue(&ctx.db, user_id, &[author.inbox_url], &activity).await
+}
+
+async fn fetch_note(ctx: &ApiContext, message_id: Uuid) -> Result<Option<NoteRow>> {
+    let note = sqlx::query_as!(
+        NoteRow,
+        r#"
+            select
+                message.id as "id!: Uuid",
+                message.author_id as "author_id!: Uuid",
+                message.message as "message!",
+                message.created_at as "created_at!: PrimitiveDateTime",
+                message.edited_at as "edited_at: PrimitiveDateTime",
+                message.message_parent_id as "message_parent_id: Uuid",
+                remote_object.object_url as "parent_object_url?"
+            from message
+            left join remote_object on remote_object.message_id = message.message_parent_id
+            where message.id = $1 and message.deleted_at is null
+            and not exists(select 1 from remote_actor where remote_actor.user_id = message.author_id)
+        "#,
+        message_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    Ok(note)
+}
+
+fn to_note(ctx: &ApiContext, note: &NoteRow) -> Value {
+    let public_url = &ctx.config.public_url;
+    let actor_url = federation::actor_url(public_url, note.author_id);
+    let id = federation::note_url(public_url, note.id);
+
+    let in_reply_to = match (&note.parent_object_url, note.message_parent_id) {
+        (Some(url), _) => Some(url.clone()),
+        (None, Some(parent_id)) => Some(federation::note_url(public_url, parent_id)),
+        (None, None) => None,
+    };
+
+    let mut object = json!({
+        "id": id,
+        "type": "Note",
+        "attributedTo": actor_url,
+        "content": to_html(&note.message),
+        "published": published(note.created_at),
+        "inReplyTo": in_reply_to,
+        "url": id,
+        "to": [PUBLIC],
+        "cc": [format!("{}/followers", actor_url)],
+    });
+
+    if let Some(edited_at) = note.edited_at {
+        object["updated"] = published(edited_at).into();
+    }
+
+    object
+}
+
+fn create_activity(ctx: &ApiContext, note: &NoteRow) -> Value {
+    let object = to_note(ctx, note);
+
+    json!({
+        "id": format!("{}/activity", object["id"].as_str().unwrap_or_default()),
+        "type": "Create",
+        "actor": object["attributedTo"],
+        "published": object["published"],
+        "to": object["to"],
+        "cc": object["cc"],
+        "object": object,
+    })
+}
+
+fn published(created_at: PrimitiveDateTime) -> String {
+    created_at.assume_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+/// Messages are plain text, other servers expect HTML.
+pub fn to_html(text: &str) -> String {
+    let escaped = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+
+    escaped
+        .split("\n\n")
+        .map(|paragraph| format!("<p>{}</p>", paragraph.replace('\n', "<br>")))
+        .collect()
+}
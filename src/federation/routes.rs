@@ -0,0 +1,57 @@
+use crate::federation::{actors, inbox, outbox, wellknown};
+use axum::{
+    routing::{get, post, delete},
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/.well-known/webfinger",
+            get(wellknown::webfinger)
+        )
+        .route(
+            "/.well-known/nodeinfo",
+            get(wellknown::nodeinfo_links)
+        )
+        .route(
+            "/nodeinfo/2.0",
+            get(wellknown::nodeinfo)
+        )
+        .route(
+            "/inbox",
+            post(inbox::post_inbox)
+        )
+        .route(
+            "/users/:id",
+            get(actors::get_actor)
+        )
+        .route(
+            "/users/:id/inbox",
+            post(inbox::post_inbox)
+        )
+        .route(
+            "/users/:id/outbox",
+            get(outbox::get_outbox)
+        )
+        .route(
+            "/users/:id/followers",
+            get(actors::get_followers)
+        )
+        .route(
+            "/users/:id/following",
+            get(actors::get_following)
+        )
+        .route(
+            "/notes/:id",
+            get(outbox::get_note)
+        )
+        .route(
+            "/api/federation/follows",
+            post(outbox::follow)
+        )
+        .route(
+            "/api/federation/follows/:user_id",
+            delete(outbox::unfollow)
+        )
+}
@@ -0,0 +1,167 @@
+use crate::{
+    Result,
+    Error,
+    router::server::ApiContext,
+    federation::{actors::{self, RemoteActor}, keys},
+};
+use axum::http::{HeaderMap, Method};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+// Signed requests older (or newer) than this are rejected to limit replays.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// The `Date`, `Digest` and `Signature` headers of a request to `url`, following the
+/// HTTP signatures draft (draft-cavage-http-signatures) that ActivityPub servers use.
+///
+/// `Host` is signed too, but reqwest already derives the same value from the URL.
+pub fn sign(
+    key_id: &str,
+    private_key_pem: &str,
+    method: &Method,
+    url: &reqwest::Url,
+    body: &[u8],
+) -> anyhow::Result<Vec<(&'static str, String)>> {
+    let date = httpdate::fmt_http_date(SystemTime::now());
+    let digest = digest(body);
+
+    let signed = format!(
+        "(request-target): {} {}\nhost: {}\ndate: {}\ndigest: {}",
+        method.as_str().to_lowercase(),
+        request_target(url),
+        host(url),
+        date,
+        digest
+    );
+
+    let signature = STANDARD.encode(keys::sign(private_key_pem, signed.as_bytes())?);
+
+    Ok(vec![
+        ("Date", date),
+        ("Digest", digest),
+        (
+            "Signature",
+            format!(
+                r#"keyId="{}",algorithm="rsa-sha256",headers="(request-target) host date digest",signature="{}""#,
+                key_id, signature
+            ),
+        ),
+    ])
+}
+
+/// Checks the signature of an incoming request and returns the actor who signed it.
+///
+/// The request must sign at least `date`, and `digest` when it has a body.
+pub async fn verify(
+    ctx: &ApiContext,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<RemoteActor> {
+    let header = headers
+        .get("signature")
+        .and_then(|value| value.to_str().ok())
+        .ok_or(Error::Unauthorized)?;
+
+    let params = parse(header);
+
+    let (Some(key_id), Some(signature)) = (params.get("keyId"), params.get("signature")) else {
+        return Err(Error::Unauthorized);
+    };
+
+    let signed_headers: Vec<&str> = params
+        .get("headers")
+        .map(|names| names.split_whitespace().collect())
+        .unwrap_or_else(|| vec!["date"]);
+
+    if !signed_headers.contains(&"date") || (!body.is_empty() && !signed_headers.contains(&"digest")) {
+        return Err(Error::Unauthorized);
+    }
+
+    let date = headers
+        .get("date")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .ok_or(Error::Unauthorized)?;
+
+    let skew = match SystemTime::now().duration_since(date) {
+        Ok(skew) => skew,
+        Err(e) => e.duration(),
+    };
+
+    if skew > MAX_CLOCK_SKEW {
+        return Err(Error::Unauthorized);
+    }
+
+    if signed_headers.contains(&"digest") {
+        let matches = headers
+            .get("digest")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value == digest(body));
+
+        if !matches {
+            return Err(Error::Unauthorized);
+        }
+    }
+
+    let mut lines = Vec::with_capacity(signed_headers.len());
+
+    for name in signed_headers {
+        let value = if name == "(request-target)" {
+            format!("{} {}", method.as_str().to_lowercase(), path_and_query)
+        } else {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .ok_or(Error::Unauthorized)?
+                .to_string()
+        };
+
+        lines.push(format!("{}: {}", name, value));
+    }
+
+    let signature = STANDARD.decode(signature).map_err(|_| Error::Unauthorized)?;
+
+    // Key ids are the actor URL with a fragment, e.g. `https://example.com/users/alice#main-key`.
+    let actor_url = key_id.split('#').next().unwrap_or_default();
+    let actor = actors::fetch(ctx, actor_url).await?;
+
+    if !keys::verify(&actor.public_key_pem, lines.join("\n").as_bytes(), &signature) {
+        return Err(Error::Unauthorized);
+    }
+
+    Ok(actor)
+}
+
+fn digest(body: &[u8]) -> String {
+    format!("SHA-256={}", STANDARD.encode(Sha256::digest(body)))
+}
+
+fn request_target(url: &reqwest::Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+/// The `Host` header of a request to `url`, which only has a port when it isn't the default one.
+pub fn host(url: &reqwest::Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// Splits `keyId="...",headers="..."` into its parameters.
+fn parse(header: &str) -> HashMap<&str, &str> {
+    header
+        .split(',')
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim().trim_matches('"')))
+        .collect()
+}
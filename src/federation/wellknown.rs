@@ -0,0 +1,99 @@
+use crate::{
+    Result,
+    Error,
+    router::server::ApiContext,
+    federation::{self, actors, signatures, ACTIVITY_JSON},
+};
+use axum::{
+    extract::{Extension, Query},
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+const NODEINFO_SCHEMA: &str = "http://nodeinfo.diaspora.software/ns/schema/2.0";
+
+#[derive(Debug, Deserialize)]
+pub struct WebfingerQuery {
+    resource: String,
+}
+
+/// `GET /.well-known/webfinger?resource=acct:name@domain`, which is how other servers
+/// find the actor behind a handle. Also accepts the actor URL as the resource.
+pub async fn webfinger(
+    ctx: Extension<ApiContext>,
+    Query(query): Query<WebfingerQuery>
+) -> Result<Response> {
+    let public_url = &ctx.config.public_url;
+    let domain = reqwest::Url::parse(public_url)
+        .map(|url| signatures::host(&url))
+        .unwrap_or_default();
+
+    let actor = match query.resource.strip_prefix("acct:") {
+        Some(handle) => match handle.trim_start_matches('@').split_once('@') {
+            Some((username, handle_domain)) if handle_domain.eq_ignore_ascii_case(&domain) => {
+                actors::find_local(&ctx.db, username).await?
+            }
+            _ => return Err(Error::NotFound),
+        },
+        None => actors::find_local_by_url(&ctx, &query.resource).await?,
+    };
+
+    let actor = actor.ok_or(Error::NotFound)?;
+    let actor_url = federation::actor_url(public_url, actor.user_id);
+
+    let document = json!({
+        "subject": format!("acct:{}@{}", actor.username, domain),
+        "aliases": [actor_url],
+        "links": [
+            { "rel": "self", "type": ACTIVITY_JSON, "href": actor_url },
+            { "rel": "http://webfinger.net/rel/profile-page", "type": "text/html", "href": actor_url },
+        ],
+    });
+
+    Ok(([(CONTENT_TYPE, "application/jrd+json")], Json(document)).into_response())
+}
+
+/// `GET /.well-known/nodeinfo`, which points to the supported NodeInfo documents.
+pub async fn nodeinfo_links(ctx: Extension<ApiContext>) -> Json<serde_json::Value> {
+    Json(json!({
+        "links": [
+            { "rel": NODEINFO_SCHEMA, "href": format!("{}/nodeinfo/2.0", ctx.config.public_url) },
+        ],
+    }))
+}
+
+/// `GET /nodeinfo/2.0`, general information about this server and how many local users
+/// and posts it has.
+pub async fn nodeinfo(ctx: Extension<ApiContext>) -> Result<Json<serde_json::Value>> {
+    let users = sqlx::query_scalar!(
+        r#"
+            select count(*) as "count!: i64" from user
+            where not exists(select 1 from remote_actor where remote_actor.user_id = user.id)
+        "#
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let posts = sqlx::query_scalar!(
+        r#"
+            select count(*) as "count!: i64" from message
+            where deleted_at is null
+            and not exists(select 1 from remote_actor where remote_actor.user_id = message.author_id)
+        "#
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(Json(json!({
+        "version": "2.0",
+        "software": { "name": "kiwi", "version": env!("CARGO_PKG_VERSION") },
+        "protocols": ["activitypub"],
+        "services": { "inbound": [], "outbound": [] },
+        "openRegistrations": true,
+        "usage": { "users": { "total": users }, "localPosts": posts },
+        "metadata": {},
+    })))
+}
@@ -0,0 +1,254 @@
+use crate::{
+    Result,
+    Error,
+    router::server::ApiContext,
+    feed::render::{self, Entry, Feed},
+    federation::{self, actors},
+    tag::tags,
+};
+use axum::{
+    extract::{Extension, Path},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+// Number of messages in a feed.
+const FEED_SIZE: i64 = 20;
+
+#[derive(Clone, Copy)]
+enum Format {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl Format {
+    fn from_file_name(name: &str) -> Option<Self> {
+        match name {
+            "feed.rss" => Some(Self::Rss),
+            "feed.atom" => Some(Self::Atom),
+            "feed.json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Rss => "application/rss+xml; charset=utf-8",
+            Self::Atom => "application/atom+xml; charset=utf-8",
+            Self::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+}
+
+struct EntryRow {
+    id: Uuid,
+    author_id: Uuid,
+    username: String,
+    display_name: String,
+    message: String,
+    created_at: PrimitiveDateTime,
+    edited_at: Option<PrimitiveDateTime>,
+    // Messages from other servers link to their original.
+    object_url: Option<String>,
+}
+
+/// `GET /@:username/feed.{rss,atom,json}`, the latest top-level messages of a local user.
+pub async fn get_user_feed(
+    ctx: Extension<ApiContext>,
+    Path((username, file)): Path<(String, String)>,
+    headers: HeaderMap
+) -> Result<Response> {
+    let format = Format::from_file_name(&file).ok_or(Error::NotFound)?;
+    let actor = actors::find_local(&ctx.db, &username).await?.ok_or(Error::NotFound)?;
+
+    let user = sqlx::query!(
+        r#"
+            select
+                display_name,
+                bio,
+                coalesce(updated_at, created_at) as "updated_at!: PrimitiveDateTime"
+            from user
+            where id = $1
+        "#,
+        actor.user_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let rows = sqlx::query_as!(
+        EntryRow,
+        r#"
+            select
+                message.id as "id!: Uuid",
+                message.author_id as "author_id!: Uuid",
+                user.username as "username!",
+                user.display_name as "display_name!",
+                message.message as "message!",
+                message.created_at as "created_at!: PrimitiveDateTime",
+                message.edited_at as "edited_at: PrimitiveDateTime",
+                remote_object.object_url as "object_url?"
+            from message
+            join user on user.id = message.author_id
+            left join remote_object on remote_object.message_id = message.id
+            where message.author_id = $1
+            and message.message_parent_id is null
+            and message.deleted_at is null
+            order by message.created_at desc, message.rowid desc
+            limit $2
+        "#,
+        actor.user_id,
+        FEED_SIZE
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let public_url = &ctx.config.public_url;
+    let name = if user.display_name.is_empty() { &actor.username } else { &user.display_name };
+
+    let feed = Feed {
+        title: format!("{} (@{})", name, actor.username),
+        description: user.bio,
+        home_url: federation::actor_url(public_url, actor.user_id),
+        feed_url: format!("{}/@{}/{}", public_url, actor.username, file),
+        updated: user.updated_at.assume_utc(),
+        entries: Vec::new(),
+    };
+
+    Ok(respond(&ctx, format, feed, rows, &headers))
+}
+
+/// `GET /tags/:tag/feed.{rss,atom,json}`, the latest top-level messages with a hashtag.
+pub async fn get_tag_feed(
+    ctx: Extension<ApiContext>,
+    Path((tag, file)): Path<(String, String)>,
+    headers: HeaderMap
+) -> Result<Response> {
+    let format = Format::from_file_name(&file).ok_or(Error::NotFound)?;
+    let tag = tags::normalize(&tag).ok_or(Error::NotFound)?;
+
+    let created_at = sqlx::query_scalar!(
+        r#"select created_at as "created_at!: PrimitiveDateTime" from tag where name = $1"#,
+        tag
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let rows = sqlx::query_as!(
+        EntryRow,
+        r#"
+            select
+                message.id as "id!: Uuid",
+                message.author_id as "author_id!: Uuid",
+                user.username as "username!",
+                user.display_name as "display_name!",
+                message.message as "message!",
+                message.created_at as "created_at!: PrimitiveDateTime",
+                message.edited_at as "edited_at: PrimitiveDateTime",
+                remote_object.object_url as "object_url?"
+            from message
+            join message_tag on message_tag.message_id = message.id
+            join tag on tag.id = message_tag.tag_id
+            join user on user.id = message.author_id
+            left join remote_object on remote_object.message_id = message.id
+            where tag.name = $1
+            and message.message_parent_id is null
+            and message.deleted_at is null
+            order by message.created_at desc, message.rowid desc
+            limit $2
+        "#,
+        tag,
+        FEED_SIZE
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let public_url = &ctx.config.public_url;
+
+    let feed = Feed {
+        title: format!("#{}", tag),
+        description: format!("Messages tagged #{}", tag),
+        home_url: format!("{}/api/tags/{}/messages", public_url, tag),
+        feed_url: format!("{}/tags/{}/{}", public_url, tag, file),
+        // Raised to the newest entry by `respond`.
+        updated: created_at.assume_utc(),
+        entries: Vec::new(),
+    };
+
+    Ok(respond(&ctx, format, feed, rows, &headers))
+}
+
+/// Renders the feed, or answers `304 Not Modified` if the client's copy is still current.
+///
+/// The feed is `updated` when its newest entry was posted or edited, or when `feed.updated`
+/// says so (e.g. a profile change). The `ETag` covers the rendered feed itself, so it also
+/// changes when an older entry is deleted.
+fn respond(ctx: &ApiContext, format: Format, mut feed: Feed, rows: Vec<EntryRow>, headers: &HeaderMap) -> Response {
+    let public_url = &ctx.config.public_url;
+
+    feed.entries = rows
+        .into_iter()
+        .map(|row| {
+            let published = row.created_at.assume_utc();
+
+            Entry {
+                url: row.object_url.unwrap_or_else(|| federation::note_url(public_url, row.id)),
+                author: if row.display_name.is_empty() { row.username.clone() } else { row.display_name },
+                author_url: federation::actor_url(public_url, row.author_id),
+                text: row.message,
+                published,
+                updated: row.edited_at.map_or(published, |edited_at| edited_at.assume_utc()),
+            }
+        })
+        .collect();
+
+    if let Some(latest) = feed.entries.iter().map(|entry| entry.updated).max() {
+        feed.updated = feed.updated.max(latest);
+    }
+
+    let body = match format {
+        Format::Rss => render::rss(&feed),
+        Format::Atom => render::atom(&feed),
+        Format::Json => render::json(&feed),
+    };
+
+    let hash: String = Sha256::digest(body.as_bytes())
+        .iter()
+        .take(16)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let etag = format!("\"{}\"", hash);
+    let last_modified = httpdate::fmt_http_date(feed.updated.into());
+
+    // `If-None-Match` takes precedence, `If-Modified-Since` is only checked without it.
+    let not_modified = match headers.get(IF_NONE_MATCH).and_then(|value| value.to_str().ok()) {
+        Some(tags) => tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*"),
+        None => headers
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+            .is_some_and(|since| OffsetDateTime::from(since).unix_timestamp() >= feed.updated.unix_timestamp()),
+    };
+
+    let cache_headers = [
+        (ETAG, etag),
+        (LAST_MODIFIED, last_modified),
+        (CACHE_CONTROL, "public, max-age=300".to_string()),
+    ];
+
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    (cache_headers, [(CONTENT_TYPE, format.content_type())], body).into_response()
+}
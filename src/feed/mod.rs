@@ -0,0 +1,3 @@
+pub mod feeds;
+pub mod render;
+pub mod routes;
@@ -0,0 +1,210 @@
+use serde_json::json;
+use time::{
+    format_description::well_known::{Rfc2822, Rfc3339},
+    OffsetDateTime,
+};
+
+// Item titles are the beginning of the message, cut at this many characters.
+const MAX_TITLE_LEN: usize = 80;
+
+pub struct Feed {
+    pub title: String,
+    pub description: String,
+    // The page the feed is about, and the URL of the feed itself.
+    pub home_url: String,
+    pub feed_url: String,
+    pub updated: OffsetDateTime,
+    pub entries: Vec<Entry>,
+}
+
+pub struct Entry {
+    pub url: String,
+    pub author: String,
+    pub author_url: String,
+    pub text: String,
+    pub published: OffsetDateTime,
+    pub updated: OffsetDateTime,
+}
+
+impl Entry {
+    fn title(&self) -> String {
+        let first_line = self.text.lines().next().unwrap_or_default();
+
+        if first_line.chars().count() > MAX_TITLE_LEN {
+            let mut title: String = first_line.chars().take(MAX_TITLE_LEN - 1).collect();
+            title.push('…');
+            title
+        } else {
+            first_line.to_string()
+        }
+    }
+}
+
+/// RSS 2.0.
+pub fn rss(feed: &Feed) -> String {
+    let mut xml = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#, "\n",
+            r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">"#, "\n",
+            "<channel>\n",
+            "<title>{}</title>\n",
+            "<link>{}</link>\n",
+            "<description>{}</description>\n",
+            r#"<atom:link href="{}" rel="self" type="application/rss+xml"/>"#, "\n",
+            "<lastBuildDate>{}</lastBuildDate>\n",
+        ),
+        escape(&feed.title),
+        escape(&feed.home_url),
+        escape(&feed.description),
+        escape(&feed.feed_url),
+        rfc2822(feed.updated),
+    );
+
+    for entry in &feed.entries {
+        xml.push_str(&format!(
+            concat!(
+                "<item>\n",
+                "<title>{}</title>\n",
+                "<link>{}</link>\n",
+                r#"<guid isPermaLink="true">{}</guid>"#, "\n",
+                "<pubDate>{}</pubDate>\n",
+                "<description>{}</description>\n",
+                "</item>\n",
+            ),
+            escape(&entry.title()),
+            escape(&entry.url),
+            escape(&entry.url),
+            rfc2822(entry.published),
+            escape(&entry.text),
+        ));
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+/// Atom 1.0.
+pub fn atom(feed: &Feed) -> String {
+    let mut xml = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#, "\n",
+            r#"<feed xmlns="http://www.w3.org/2005/Atom">"#, "\n",
+            "<id>{}</id>\n",
+            "<title>{}</title>\n",
+            "<subtitle>{}</subtitle>\n",
+            "<updated>{}</updated>\n",
+            r#"<link href="{}" rel="alternate"/>"#, "\n",
+            r#"<link href="{}" rel="self" type="application/atom+xml"/>"#, "\n",
+        ),
+        escape(&feed.feed_url),
+        escape(&feed.title),
+        escape(&feed.description),
+        rfc3339(feed.updated),
+        escape(&feed.home_url),
+        escape(&feed.feed_url),
+    );
+
+    for entry in &feed.entries {
+        xml.push_str(&format!(
+            concat!(
+                "<entry>\n",
+                "<id>{}</id>\n",
+                "<title>{}</title>\n",
+                r#"<link href="{}" rel="alternate"/>"#, "\n",
+                "<published>{}</published>\n",
+                "<updated>{}</updated>\n",
+                "<author><name>{}</name><uri>{}</uri></author>\n",
+                r#"<content type="text">{}</content>"#, "\n",
+                "</entry>\n",
+            ),
+            escape(&entry.url),
+            escape(&entry.title()),
+            escape(&entry.url),
+            rfc3339(entry.published),
+            rfc3339(entry.updated),
+            escape(&entry.author),
+            escape(&entry.author_url),
+            escape(&entry.text),
+        ));
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// JSON Feed 1.1.
+pub fn json(feed: &Feed) -> String {
+    let items: Vec<serde_json::Value> = feed.entries
+        .iter()
+        .map(|entry| json!({
+            "id": entry.url,
+            "url": entry.url,
+            "title": entry.title(),
+            "content_text": entry.text,
+            "date_published": rfc3339(entry.published),
+            "date_modified": rfc3339(entry.updated),
+            "authors": [{ "name": entry.author, "url": entry.author_url }],
+        }))
+        .collect();
+
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed.title,
+        "description": feed.description,
+        "home_page_url": feed.home_url,
+        "feed_url": feed.feed_url,
+        "items": items,
+    })
+    .to_string()
+}
+
+/// Escapes text for XML, dropping the control characters XML 1.0 doesn't allow at all.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' || c == '\u{FFFE}' || c == '\u{FFFF}' => (),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn rfc2822(date: OffsetDateTime) -> String {
+    date.format(&Rfc2822).unwrap_or_default()
+}
+
+fn rfc3339(date: OffsetDateTime) -> String {
+    date.format(&Rfc3339).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_replaces_markup_characters() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn escape_drops_characters_xml_does_not_allow() {
+        assert_eq!(escape("a\u{0}b\u{1b}c\u{FFFE}d\u{FFFF}"), "abcd");
+    }
+
+    #[test]
+    fn escape_keeps_whitespace_and_unicode() {
+        assert_eq!(escape("line\n\ttab\r\nkiwi 🥝"), "line\n\ttab\r\nkiwi 🥝");
+    }
+}
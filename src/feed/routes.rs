@@ -0,0 +1,17 @@
+use crate::feed::feeds;
+use axum::{
+    routing::get,
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/@:username/:feed",
+            get(feeds::get_user_feed)
+        )
+        .route(
+            "/tags/:tag/:feed",
+            get(feeds::get_tag_feed)
+        )
+}
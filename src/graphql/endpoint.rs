@@ -0,0 +1,58 @@
+use crate::{
+    graphql::{
+        loaders::{BookmarkedLoader, LikeCountLoader, LikedLoader, MessageLoader, ReplyCountLoader, UserLoader},
+        schema::{ApiSchema, Viewer},
+    },
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+    },
+};
+use async_graphql::{dataloader::DataLoader, http::GraphiQLSource};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{
+    extract::Extension,
+    response::Html,
+};
+
+/// Runs a GraphQL query for an authenticated user.
+///
+/// Loaders are built for every request so that batching and caching stay scoped to it.
+pub async fn execute(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    schema: Extension<ApiSchema>,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    let db = ctx.db.clone();
+
+    let req = req
+        .into_inner()
+        .data(Viewer(auth_user.user_id))
+        .data(DataLoader::new(UserLoader(db.clone()), tokio::spawn))
+        .data(DataLoader::new(MessageLoader(db.clone()), tokio::spawn))
+        .data(DataLoader::new(LikeCountLoader(db.clone()), tokio::spawn))
+        .data(DataLoader::new(ReplyCountLoader(db.clone()), tokio::spawn))
+        .data(DataLoader::new(
+            LikedLoader {
+                db: db.clone(),
+                viewer_id: auth_user.user_id,
+            },
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            BookmarkedLoader {
+                db: db.clone(),
+                viewer_id: auth_user.user_id,
+            },
+            tokio::spawn,
+        ))
+        .data(db);
+
+    schema.execute(req).await.into()
+}
+
+/// An in-browser IDE for exploring the schema.
+pub async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
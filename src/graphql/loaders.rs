@@ -0,0 +1,166 @@
+use async_graphql::dataloader::Loader;
+use axum::async_trait;
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use crate::graphql::schema::{Message, User};
+
+// Batches the lookups made while resolving one request, e.g. the authors of every message
+// in a connection are loaded with one query instead of one per message.
+//
+// Loaders are created per request, so their caches never outlive it.
+
+#[derive(sqlx::FromRow)]
+pub struct UserRow {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub bio: String,
+    pub image: Option<String>,
+    pub banner: Option<String>,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct MessageRow {
+    pub id: Uuid,
+    pub author_id: Uuid,
+    pub message: String,
+    pub message_parent_id: Option<Uuid>,
+    pub created_at: PrimitiveDateTime,
+    pub edited_at: Option<PrimitiveDateTime>,
+    pub deleted_at: Option<PrimitiveDateTime>,
+}
+
+pub const USER_COLUMNS: &str = "user.id, user.username, user.display_name, user.bio, user.image, user.banner, user.created_at";
+
+pub const MESSAGE_COLUMNS: &str = "message.id, message.author_id, message.message, message.message_parent_id, \
+    message.created_at, message.edited_at, message.deleted_at";
+
+pub struct UserLoader(pub SqlitePool);
+
+pub struct MessageLoader(pub SqlitePool);
+
+/// Number of likes per message, from `message.like_count`.
+pub struct LikeCountLoader(pub SqlitePool);
+
+/// Number of replies per message not counting deleted ones, from `message.reply_count`.
+pub struct ReplyCountLoader(pub SqlitePool);
+
+/// Whether the user making the request liked each message.
+pub struct LikedLoader {
+    pub db: SqlitePool,
+    pub viewer_id: Uuid,
+}
+
+/// Whether the user making the request bookmarked each message.
+pub struct BookmarkedLoader {
+    pub db: SqlitePool,
+    pub viewer_id: Uuid,
+}
+
+#[async_trait]
+impl Loader<Uuid> for UserLoader {
+    type Value = User;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, User>, Self::Error> {
+        let mut query = QueryBuilder::new(format!("select {} from user where user.id in (", USER_COLUMNS));
+        push_ids(&mut query, keys);
+
+        let rows: Vec<UserRow> = query.build_query_as().fetch_all(&self.0).await?;
+
+        Ok(rows.into_iter().map(|row| (row.id, User::from(row))).collect())
+    }
+}
+
+#[async_trait]
+impl Loader<Uuid> for MessageLoader {
+    type Value = Message;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Message>, Self::Error> {
+        let mut query = QueryBuilder::new(format!("select {} from message where message.id in (", MESSAGE_COLUMNS));
+        push_ids(&mut query, keys);
+
+        let rows: Vec<MessageRow> = query.build_query_as().fetch_all(&self.0).await?;
+
+        Ok(rows.into_iter().map(|row| (row.id, Message::from(row))).collect())
+    }
+}
+
+#[async_trait]
+impl Loader<Uuid> for LikeCountLoader {
+    type Value = i64;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, i64>, Self::Error> {
+        let mut query = QueryBuilder::new("select id, like_count from message where id in (");
+        push_ids(&mut query, keys);
+
+        let counts: Vec<(Uuid, i64)> = query.build_query_as().fetch_all(&self.0).await?;
+
+        Ok(counts.into_iter().collect())
+    }
+}
+
+#[async_trait]
+impl Loader<Uuid> for ReplyCountLoader {
+    type Value = i64;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, i64>, Self::Error> {
+        let mut query = QueryBuilder::new("select id, reply_count from message where id in (");
+        push_ids(&mut query, keys);
+
+        let counts: Vec<(Uuid, i64)> = query.build_query_as().fetch_all(&self.0).await?;
+
+        Ok(counts.into_iter().collect())
+    }
+}
+
+#[async_trait]
+impl Loader<Uuid> for LikedLoader {
+    type Value = bool;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, bool>, Self::Error> {
+        let mut query = QueryBuilder::new(r#"select message_id from "like" where user_id = "#);
+        query.push_bind(self.viewer_id);
+        query.push(" and message_id in (");
+        push_ids(&mut query, keys);
+
+        let liked: Vec<(Uuid,)> = query.build_query_as().fetch_all(&self.db).await?;
+
+        Ok(liked.into_iter().map(|(message_id,)| (message_id, true)).collect())
+    }
+}
+
+#[async_trait]
+impl Loader<Uuid> for BookmarkedLoader {
+    type Value = bool;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, bool>, Self::Error> {
+        let mut query = QueryBuilder::new("select message_id from bookmark where user_id = ");
+        query.push_bind(self.viewer_id);
+        query.push(" and message_id in (");
+        push_ids(&mut query, keys);
+
+        let bookmarked: Vec<(Uuid,)> = query.build_query_as().fetch_all(&self.db).await?;
+
+        Ok(bookmarked.into_iter().map(|(message_id,)| (message_id, true)).collect())
+    }
+}
+
+/// Appends `ids` and the closing parenthesis of an `in (` list.
+fn push_ids(query: &mut QueryBuilder<'_, Sqlite>, ids: &[Uuid]) {
+    let mut separated = query.separated(", ");
+    for id in ids {
+        separated.push_bind(*id);
+    }
+    separated.push_unseparated(")");
+}
@@ -0,0 +1,4 @@
+pub mod schema;
+pub mod loaders;
+pub mod endpoint;
+pub mod routes;
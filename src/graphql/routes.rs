@@ -0,0 +1,16 @@
+use crate::graphql::{endpoint, schema};
+use axum::{
+    extract::Extension,
+    routing::get,
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/graphql",
+            get(endpoint::graphiql)
+                .post(endpoint::execute)
+        )
+        .layer(Extension(schema::build()))
+}
@@ -0,0 +1,402 @@
+use crate::graphql::loaders::{
+    BookmarkedLoader, LikeCountLoader, LikedLoader, MessageLoader, MessageRow, ReplyCountLoader, UserLoader, UserRow,
+    MESSAGE_COLUMNS, USER_COLUMNS,
+};
+use async_graphql::{
+    connection::{Connection, Edge},
+    dataloader::DataLoader,
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject,
+};
+use sqlx::{QueryBuilder, SqlitePool};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+pub type ApiSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+// Deeper queries are almost always reply chains walked recursively, `Message.parent` and
+// `Message.replies` give clients enough to fetch a whole thread in a few requests.
+const MAX_DEPTH: usize = 10;
+// Connections multiply the complexity of their children by the requested page size.
+const MAX_COMPLEXITY: usize = 2000;
+
+const DEFAULT_PAGE_SIZE: i32 = 20;
+const MAX_PAGE_SIZE: i32 = 50;
+
+pub fn build() -> ApiSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// The user making the request, added to every request's data.
+pub struct Viewer(pub Uuid);
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex)]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub bio: String,
+    pub image: Option<String>,
+    pub banner: Option<String>,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex)]
+pub struct Message {
+    pub id: Uuid,
+    #[graphql(skip)]
+    pub author_id: Uuid,
+    #[graphql(skip)]
+    pub parent_id: Option<Uuid>,
+    /// Empty once the message is deleted.
+    pub text: String,
+    pub created_at: OffsetDateTime,
+    pub edited_at: Option<OffsetDateTime>,
+    pub deleted_at: Option<OffsetDateTime>,
+}
+
+#[derive(SimpleObject, Clone)]
+#[graphql(complex)]
+pub struct Like {
+    #[graphql(skip)]
+    pub user_id: Uuid,
+    #[graphql(skip)]
+    pub message_id: Uuid,
+    pub created_at: OffsetDateTime,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        User {
+            id: row.id,
+            username: row.username,
+            display_name: row.display_name,
+            bio: row.bio,
+            image: row.image,
+            banner: row.banner,
+            created_at: row.created_at.assume_utc(),
+        }
+    }
+}
+
+impl From<MessageRow> for Message {
+    fn from(row: MessageRow) -> Self {
+        Message {
+            id: row.id,
+            author_id: row.author_id,
+            parent_id: row.message_parent_id,
+            text: if row.deleted_at.is_some() {
+                String::new()
+            } else {
+                row.message
+            },
+            created_at: row.created_at.assume_utc(),
+            edited_at: row.edited_at.map(PrimitiveDateTime::assume_utc),
+            deleted_at: row.deleted_at.map(PrimitiveDateTime::assume_utc),
+        }
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The authenticated user.
+    async fn me(&self, ctx: &Context<'_>) -> async_graphql::Result<User> {
+        let viewer = ctx.data_unchecked::<Viewer>();
+
+        load_user(ctx, viewer.0)
+            .await?
+            .ok_or_else(|| "user not found".into())
+    }
+
+    /// Looks up a user by `id` or `username`.
+    async fn user(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<Uuid>,
+        username: Option<String>,
+    ) -> async_graphql::Result<Option<User>> {
+        match (id, username) {
+            (Some(id), None) => load_user(ctx, id).await,
+            (None, Some(username)) => {
+                let mut query = QueryBuilder::new(format!("select {} from user where username = ", USER_COLUMNS));
+                query.push_bind(username);
+
+                let row: Option<UserRow> = query
+                    .build_query_as()
+                    .fetch_optional(ctx.data_unchecked::<SqlitePool>())
+                    .await
+                    .map_err(db_error)?;
+
+                Ok(row.map(User::from))
+            }
+            _ => Err("exactly one of `id` or `username` is required".into()),
+        }
+    }
+
+    async fn message(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Option<Message>> {
+        load_message(ctx, id).await
+    }
+
+    /// Every top-level message, newest first.
+    #[graphql(complexity = "page_size(first) * child_complexity")]
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<String, Message>> {
+        messages(ctx, Thread::TopLevel, first, after).await
+    }
+}
+
+#[ComplexObject]
+impl User {
+    /// Messages written by this user, newest first.
+    #[graphql(complexity = "page_size(first) * child_complexity")]
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<String, Message>> {
+        messages(ctx, Thread::Author(self.id), first, after).await
+    }
+}
+
+#[ComplexObject]
+impl Message {
+    async fn author(&self, ctx: &Context<'_>) -> async_graphql::Result<User> {
+        load_user(ctx, self.author_id)
+            .await?
+            .ok_or_else(|| "author not found".into())
+    }
+
+    /// The message this one replies to.
+    async fn parent(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Message>> {
+        match self.parent_id {
+            Some(parent_id) => load_message(ctx, parent_id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Direct replies, oldest first.
+    #[graphql(complexity = "page_size(first) * child_complexity")]
+    async fn replies(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<String, Message>> {
+        messages(ctx, Thread::Replies(self.id), first, after).await
+    }
+
+    /// Likes on this message, newest first.
+    #[graphql(complexity = "page_size(first) * child_complexity")]
+    async fn likes(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<String, Like>> {
+        let first = page_size(first);
+        let after = parse_cursor(after)?;
+
+        let mut query = QueryBuilder::new(r#"select user_id, created_at from "like" where message_id = "#);
+        query.push_bind(self.id);
+        if let Some(after) = after {
+            query.push(r#" and (created_at, rowid) < (select created_at, rowid from "like" where message_id = "#);
+            query.push_bind(self.id);
+            query.push(" and user_id = ");
+            query.push_bind(after);
+            query.push(")");
+        }
+        query.push(" order by created_at desc, rowid desc limit ");
+        query.push_bind(first as i64 + 1);
+
+        let mut rows: Vec<(Uuid, PrimitiveDateTime)> = query
+            .build_query_as()
+            .fetch_all(ctx.data_unchecked::<SqlitePool>())
+            .await
+            .map_err(db_error)?;
+
+        let has_next_page = rows.len() > first;
+        rows.truncate(first);
+
+        let mut connection = Connection::new(after.is_some(), has_next_page);
+        connection.edges.extend(rows.into_iter().map(|(user_id, created_at)| {
+            Edge::new(
+                user_id.to_string(),
+                Like {
+                    user_id,
+                    message_id: self.id,
+                    created_at: created_at.assume_utc(),
+                },
+            )
+        }));
+
+        Ok(connection)
+    }
+
+    async fn like_count(&self, ctx: &Context<'_>) -> async_graphql::Result<i64> {
+        let count = ctx
+            .data_unchecked::<DataLoader<LikeCountLoader>>()
+            .load_one(self.id)
+            .await
+            .map_err(db_error)?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn reply_count(&self, ctx: &Context<'_>) -> async_graphql::Result<i64> {
+        let count = ctx
+            .data_unchecked::<DataLoader<ReplyCountLoader>>()
+            .load_one(self.id)
+            .await
+            .map_err(db_error)?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Whether the authenticated user liked this message.
+    async fn liked_by_me(&self, ctx: &Context<'_>) -> async_graphql::Result<bool> {
+        let liked = ctx
+            .data_unchecked::<DataLoader<LikedLoader>>()
+            .load_one(self.id)
+            .await
+            .map_err(db_error)?;
+
+        Ok(liked.unwrap_or(false))
+    }
+
+    /// Whether the authenticated user bookmarked this message.
+    async fn bookmarked_by_me(&self, ctx: &Context<'_>) -> async_graphql::Result<bool> {
+        let bookmarked = ctx
+            .data_unchecked::<DataLoader<BookmarkedLoader>>()
+            .load_one(self.id)
+            .await
+            .map_err(db_error)?;
+
+        Ok(bookmarked.unwrap_or(false))
+    }
+}
+
+#[ComplexObject]
+impl Like {
+    async fn user(&self, ctx: &Context<'_>) -> async_graphql::Result<User> {
+        load_user(ctx, self.user_id)
+            .await?
+            .ok_or_else(|| "user not found".into())
+    }
+
+    async fn message(&self, ctx: &Context<'_>) -> async_graphql::Result<Message> {
+        load_message(ctx, self.message_id)
+            .await?
+            .ok_or_else(|| "message not found".into())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Thread {
+    TopLevel,
+    Author(Uuid),
+    Replies(Uuid),
+}
+
+/// Pages through messages with a keyset on `(created_at, rowid)`, the cursor being the id
+/// of the last message of the previous page. Likes page the same way on the liking user's id.
+async fn messages(
+    ctx: &Context<'_>,
+    thread: Thread,
+    first: Option<i32>,
+    after: Option<String>,
+) -> async_graphql::Result<Connection<String, Message>> {
+    let first = page_size(first);
+    let after = parse_cursor(after)?;
+
+    // Replies read top to bottom like a conversation, everything else is newest first.
+    let (comparison, order) = match thread {
+        Thread::Replies(_) => (">", "asc"),
+        _ => ("<", "desc"),
+    };
+
+    let mut query = QueryBuilder::new(format!("select {} from message where ", MESSAGE_COLUMNS));
+    match thread {
+        Thread::TopLevel => {
+            query.push("message_parent_id is null and deleted_at is null");
+        }
+        Thread::Author(author_id) => {
+            query.push("author_id = ");
+            query.push_bind(author_id);
+            query.push(" and deleted_at is null");
+        }
+        // Deleted replies are kept so the rest of the thread still makes sense.
+        Thread::Replies(parent_id) => {
+            query.push("message_parent_id = ");
+            query.push_bind(parent_id);
+        }
+    }
+    if let Some(after) = after {
+        query.push(format!(
+            " and (created_at, rowid) {} (select created_at, rowid from message where id = ",
+            comparison
+        ));
+        query.push_bind(after);
+        query.push(")");
+    }
+    query.push(format!(" order by created_at {0}, rowid {0} limit ", order));
+    query.push_bind(first as i64 + 1);
+
+    let mut rows: Vec<MessageRow> = query
+        .build_query_as()
+        .fetch_all(ctx.data_unchecked::<SqlitePool>())
+        .await
+        .map_err(db_error)?;
+
+    let has_next_page = rows.len() > first;
+    rows.truncate(first);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection.edges.extend(
+        rows.into_iter()
+            .map(|row| Edge::new(row.id.to_string(), Message::from(row))),
+    );
+
+    Ok(connection)
+}
+
+async fn load_user(ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Option<User>> {
+    ctx.data_unchecked::<DataLoader<UserLoader>>()
+        .load_one(id)
+        .await
+        .map_err(db_error)
+}
+
+async fn load_message(ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Option<Message>> {
+    ctx.data_unchecked::<DataLoader<MessageLoader>>()
+        .load_one(id)
+        .await
+        .map_err(db_error)
+}
+
+fn parse_cursor(cursor: Option<String>) -> async_graphql::Result<Option<Uuid>> {
+    cursor
+        .map(|cursor| Uuid::parse_str(&cursor))
+        .transpose()
+        .map_err(|_| "invalid cursor".into())
+}
+
+fn page_size(first: Option<i32>) -> usize {
+    first.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize
+}
+
+// Database errors are logged rather than sent back, like `crate::Error::Sqlx`.
+fn db_error(e: impl std::fmt::Debug) -> async_graphql::Error {
+    log::error!("GraphQL database error: {:?}", e);
+    async_graphql::Error::new("an error occurred with the database")
+}
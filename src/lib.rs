@@ -4,9 +4,22 @@ pub mod message;
 pub mod like;
 pub mod error;
 pub mod user;
+pub mod audit;
+pub mod storage;
+pub mod media;
+pub mod tag;
+pub mod search;
+pub mod conversation;
+pub mod notification;
+pub mod stream;
+pub mod webhook;
+pub mod federation;
+pub mod feed;
+pub mod mastodon;
+pub mod graphql;
+pub mod counter;
+pub mod bookmark;
 
 pub use error::{Error, ResultExt};
 
-pub type Result<T, E = Error> = std::result::Result<T, E>;
-
-use tower_http::trace::TraceLayer;
\ No newline at end of file
+pub type Result<T, E = Error> = std::result::Result<T, E>;
\ No newline at end of file
@@ -1,9 +1,16 @@
 use crate::{
     Result,
+    Error,
+    ResultExt,
     router::{
         server::ApiContext,
         extractor::AuthUser,
-    }
+    },
+    notification::notifications::{self, Kind},
+    stream::bus::{Audience, EventKind},
+    webhook::{delivery, webhooks},
+    federation::outbox,
+    counter::counters::{self, Counter},
 };
 use uuid::Uuid;
 use time::PrimitiveDateTime;
@@ -15,51 +22,127 @@ use axum::{
 
 #[derive(Debug, Serialize, sqlx::FromRow)]
 pub struct Like {
-    id: Uuid,
+    user_id: Uuid,
     message_id: Uuid,
     created_at: PrimitiveDateTime,
 }
 
+/// Likes the message `id`, answering `404 Not Found` if it doesn't exist or was deleted
+/// and `409 Conflict` if it was liked already.
 pub async fn create_like(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
     Path(id): Path<Uuid>
 ) -> Result<Json<Like>> {
+    let mut tx = ctx.db.begin().await?;
+
+    let exists = sqlx::query_scalar!(
+        r#"select exists(select 1 from message where id = $1 and deleted_at is null) as "exists!: bool""#,
+        id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    if !exists {
+        return Err(Error::NotFound);
+    }
+
     let like = sqlx::query!(
         r#"
-            insert into like (id, message_id)
+            insert into like (user_id, message_id)
             values ($1, $2)
             returning
-                id as "id!: Uuid",
+                user_id as "user_id!: Uuid",
                 message_id as "message_id!: Uuid",
                 created_at as "created_at!: PrimitiveDateTime"
         "#,
         auth_user.user_id,
         id
     )
-    .fetch_one(&ctx.db)
+    .fetch_one(&mut tx)
     .await
-    .unwrap();
+    .on_constraint("like", |_| Error::Conflict)?;
+
+    counters::add(&mut tx, Counter::Likes, id, 1).await?;
 
-    Ok(Json(
-        Like {
-            id: like.id,
-            message_id: like.message_id,
-            created_at: like.created_at
+    tx.commit().await?;
+
+    let author_id = sqlx::query_scalar!(
+        r#"select author_id as "author_id!: Uuid" from message where id = $1"#,
+        id
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    if let Some(author_id) = author_id {
+        let notified = notifications::notify(&ctx.db, author_id, Kind::Like, auth_user.user_id, Some(id)).await?;
+        notifications::publish(&ctx.events, notified);
+    }
+
+    let like = Like {
+        user_id: like.user_id,
+        message_id: like.message_id,
+        created_at: like.created_at
+    };
+
+    ctx.events.publish(EventKind::Like, Audience::Everyone, &like);
+
+    if let Some(author_id) = author_id {
+        let payload = serde_json::json!({ "like": &like, "user_id": auth_user.user_id });
+        if let Err(e) = delivery::enqueue(&ctx.db, webhooks::MESSAGE_LIKED, author_id, &payload).await {
+            log::error!("failed to queue webhooks for like of message {}: {:?}", like.message_id, e);
         }
-    ))
+    }
+
+    if let Err(e) = outbox::publish_like(&ctx, auth_user.user_id, id).await {
+        log::error!("failed to federate like of message {}: {:?}", id, e);
+    }
+
+    Ok(Json(like))
+}
+
+/// Removes the current user's like of the message `id`.
+pub async fn delete_like(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<()> {
+    let mut tx = ctx.db.begin().await?;
+
+    let deleted = sqlx::query!(
+        "delete from like where user_id = $1 and message_id = $2",
+        auth_user.user_id,
+        id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(Error::NotFound);
+    }
+
+    counters::add(&mut tx, Counter::Likes, id, -1).await?;
+
+    tx.commit().await?;
+
+    if let Err(e) = outbox::publish_unlike(&ctx, auth_user.user_id, id).await {
+        log::error!("failed to federate unlike of message {}: {:?}", id, e);
+    }
+
+    Ok(())
 }
 
 pub async fn get_likes(
     _: AuthUser,
     ctx: Extension<ApiContext>,
-    Path(id): Path<String>
+    Path(id): Path<Uuid>
 ) -> Result<Json<Vec<Like>>> {
     let likes = sqlx::query_as!(
         Like,
         r#"
             select
-                id as "id!: Uuid",
+                user_id as "user_id!: Uuid",
                 message_id as "message_id!: Uuid",
                 created_at as "created_at!: PrimitiveDateTime"
             from like 
@@ -68,8 +151,7 @@ pub async fn get_likes(
         id
     )
     .fetch_all(&ctx.db)
-    .await
-    .unwrap();
+    .await?;
 
     Ok(Json(likes))
 }
\ No newline at end of file
@@ -1,2 +1,2 @@
-mod likes;
+pub mod likes;
 pub mod routes;
\ No newline at end of file
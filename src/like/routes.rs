@@ -10,5 +10,6 @@ pub fn router() -> Router {
             "/message/:id/like",
             get(likes::get_likes)
             .post(likes::create_like)
+            .delete(likes::delete_like)
         )
 }
\ No newline at end of file
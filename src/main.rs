@@ -1,7 +1,8 @@
 use anyhow::Context;
 use clap::Parser;
 use sqlx::sqlite::SqlitePoolOptions;
-use kiwi::config::Config;
+use kiwi::config::{Command, Config};
+use kiwi::user::users;
 use kiwi::router;
 
 #[tokio::main]
@@ -19,6 +20,18 @@ async fn main() -> anyhow::Result<()> {
         .await
         .context("could not connect to database_url")?;
 
+    if let Some(Command::SetAdmin { email, revoke }) = &config.command {
+        let found = users::set_admin(&db, email, !revoke)
+            .await
+            .context("could not update user")?;
+
+        if !found {
+            anyhow::bail!("no user with email {}", email);
+        }
+
+        return Ok(());
+    }
+
     // Serve our application!
     router::server::serve(config, db).await?;
 
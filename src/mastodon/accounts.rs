@@ -0,0 +1,367 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+    },
+    federation::{actors, outbox},
+    notification::notifications::{self, Kind},
+    counter::counters,
+    mastodon::{
+        entities::{self, Account, Status},
+        params::{FormOrJson, Page},
+    },
+};
+use axum::{
+    extract::{Extension, OriginalUri, Path, Query, RawQuery},
+    http::header::LINK,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::QueryBuilder;
+use uuid::Uuid;
+
+// Most followers or followees listed at once.
+const MAX_FOLLOWS: i64 = 40;
+
+#[derive(Debug, Serialize)]
+pub struct CredentialAccount {
+    #[serde(flatten)]
+    account: Account,
+    source: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Relationship {
+    id: String,
+    following: bool,
+    followed_by: bool,
+    // Follows of remote users that their server hasn't accepted yet.
+    requested: bool,
+    blocking: bool,
+    muting: bool,
+    endorsed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateCredentials {
+    display_name: Option<String>,
+    note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LookupQuery {
+    acct: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct StatusesQuery {
+    exclude_replies: bool,
+}
+
+/// `GET /api/v1/accounts/verify_credentials`, the current user.
+pub async fn verify_credentials(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>
+) -> Result<Json<CredentialAccount>> {
+    credential_account(&ctx, auth_user.user_id).await.map(Json)
+}
+
+/// `PATCH /api/v1/accounts/update_credentials`. Only the display name and bio can be changed.
+pub async fn update_credentials(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    FormOrJson(req): FormOrJson<UpdateCredentials>
+) -> Result<Json<CredentialAccount>> {
+    sqlx::query!(
+        r#"
+            update user
+            set display_name = coalesce($1, display_name),
+                bio = coalesce($2, bio),
+                updated_at = current_timestamp
+            where id = $3
+        "#,
+        req.display_name,
+        req.note,
+        auth_user.user_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    credential_account(&ctx, auth_user.user_id).await.map(Json)
+}
+
+pub async fn get_account(
+    _: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Account>> {
+    let account = entities::account(&ctx, id).await?.ok_or(Error::NotFound)?;
+
+    Ok(Json(account))
+}
+
+/// `GET /api/v1/accounts/lookup?acct=`, finds an account by `username` or `username@domain`.
+///
+/// Remote accounts are fetched from their server the first time they are looked up.
+pub async fn lookup_account(
+    _: AuthUser,
+    ctx: Extension<ApiContext>,
+    Query(query): Query<LookupQuery>
+) -> Result<Json<Account>> {
+    let acct = query.acct.trim_start_matches('@');
+
+    let user_id = sqlx::query_scalar!(
+        r#"select id as "id!: Uuid" from user where username = $1"#,
+        acct
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None if acct.contains('@') => actors::resolve(&ctx, acct).await?.user_id,
+        None => return Err(Error::NotFound),
+    };
+
+    let account = entities::account(&ctx, user_id).await?.ok_or(Error::NotFound)?;
+
+    Ok(Json(account))
+}
+
+/// `GET /api/v1/accounts/:id/statuses`, newest first.
+pub async fn get_account_statuses(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    OriginalUri(uri): OriginalUri,
+    Path(id): Path<Uuid>,
+    Query(page): Query<Page>,
+    Query(filter): Query<StatusesQuery>
+) -> Result<Response> {
+    let mut query = QueryBuilder::new("select message.id from message where message.deleted_at is null and message.author_id = ");
+    query.push_bind(id);
+
+    if filter.exclude_replies {
+        query.push(" and message.message_parent_id is null");
+    }
+
+    page.push(&mut query, "message");
+
+    let mut ids: Vec<Uuid> = query
+        .build_query_as::<(Uuid,)>()
+        .fetch_all(&ctx.db)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+
+    if page.min_id.is_some() {
+        ids.reverse();
+    }
+
+    statuses_response(&ctx, auth_user.user_id, uri.path(), &ids).await
+}
+
+/// `POST /api/v1/accounts/:id/follow`. Follows of local users are accepted right away,
+/// remote ones once their server accepts them.
+pub async fn follow_account(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Relationship>> {
+    if id == auth_user.user_id {
+        return Err(Error::unprocessable_entity([("id", "cannot follow yourself")]));
+    }
+
+    match actors::find_remote(&ctx.db, id).await? {
+        Some(actor) => {
+            outbox::follow_remote(&ctx, auth_user.user_id, &actor).await?;
+        }
+        None => {
+            let exists = sqlx::query_scalar!(
+                r#"select exists(select 1 from user where id = $1) as "exists!: bool""#,
+                id
+            )
+            .fetch_one(&ctx.db)
+            .await?;
+
+            if !exists {
+                return Err(Error::NotFound);
+            }
+
+            let activity_url = format!("{}/follows/{}", ctx.config.public_url, Uuid::new_v4());
+
+            let mut tx = ctx.db.begin().await?;
+
+            let inserted = sqlx::query!(
+                r#"
+                    insert into follow (follower_id, followee_id, activity_url, accepted)
+                    values ($1, $2, $3, true)
+                    on conflict do nothing
+                "#,
+                auth_user.user_id,
+                id,
+                activity_url
+            )
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+
+            counters::recount_users(&mut tx, &[id]).await?;
+
+            tx.commit().await?;
+
+            if inserted > 0 {
+                let notified = notifications::notify(&ctx.db, id, Kind::Follow, auth_user.user_id, None).await?;
+                notifications::publish(&ctx.events, notified);
+            }
+        }
+    }
+
+    relationship(&ctx, auth_user.user_id, id).await.map(Json)
+}
+
+pub async fn unfollow_account(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Relationship>> {
+    outbox::unfollow_user(&ctx, auth_user.user_id, id).await?;
+
+    relationship(&ctx, auth_user.user_id, id).await.map(Json)
+}
+
+/// `GET /api/v1/accounts/relationships?id[]=...`
+pub async fn get_relationships(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    RawQuery(query): RawQuery
+) -> Result<Json<Vec<Relationship>>> {
+    // Repeated `id[]` parameters aren't supported by `Query`.
+    let ids: Vec<Uuid> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(name, _)| matches!(*name, "id" | "id[]" | "id%5B%5D"))
+        .filter_map(|(_, value)| value.parse().ok())
+        .collect();
+
+    let mut relationships = Vec::with_capacity(ids.len());
+
+    for id in ids {
+        relationships.push(relationship(&ctx, auth_user.user_id, id).await?);
+    }
+
+    Ok(Json(relationships))
+}
+
+/// `GET /api/v1/accounts/:id/followers`, the most recent ones.
+pub async fn get_followers(
+    _: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Vec<Account>>> {
+    let ids = sqlx::query_scalar!(
+        r#"
+            select follower_id as "follower_id!: Uuid" from follow
+            where followee_id = $1 and accepted
+            order by created_at desc
+            limit $2
+        "#,
+        id,
+        MAX_FOLLOWS
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    ordered_accounts(&ctx, &ids).await.map(Json)
+}
+
+/// `GET /api/v1/accounts/:id/following`, the most recent ones.
+pub async fn get_following(
+    _: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Vec<Account>>> {
+    let ids = sqlx::query_scalar!(
+        r#"
+            select followee_id as "followee_id!: Uuid" from follow
+            where follower_id = $1 and accepted
+            order by created_at desc
+            limit $2
+        "#,
+        id,
+        MAX_FOLLOWS
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    ordered_accounts(&ctx, &ids).await.map(Json)
+}
+
+/// Responds with the statuses of `ids` and a `Link` header to the neighbouring pages.
+pub async fn statuses_response(ctx: &ApiContext, viewer_id: Uuid, path: &str, ids: &[Uuid]) -> Result<Response> {
+    let statuses: Vec<Status> = entities::statuses(ctx, viewer_id, ids).await?;
+
+    match Page::link_header(&ctx.config.public_url, path, ids) {
+        Some(link) => Ok(([(LINK, link)], Json(statuses)).into_response()),
+        None => Ok(Json(statuses).into_response()),
+    }
+}
+
+async fn ordered_accounts(ctx: &ApiContext, ids: &[Uuid]) -> Result<Vec<Account>> {
+    let mut accounts = entities::accounts(ctx, ids).await?;
+
+    Ok(ids.iter().filter_map(|id| accounts.remove(id)).collect())
+}
+
+async fn credential_account(ctx: &ApiContext, user_id: Uuid) -> Result<CredentialAccount> {
+    let account = entities::account(ctx, user_id).await?.ok_or(Error::NotFound)?;
+
+    let bio = sqlx::query_scalar!("select bio from user where id = $1", user_id)
+        .fetch_one(&ctx.db)
+        .await?;
+
+    Ok(CredentialAccount {
+        account,
+        source: serde_json::json!({
+            "note": bio,
+            "fields": [],
+            "privacy": "public",
+            "sensitive": false,
+            "language": null,
+        }),
+    })
+}
+
+async fn relationship(ctx: &ApiContext, user_id: Uuid, other_id: Uuid) -> Result<Relationship> {
+    let follows = sqlx::query!(
+        r#"
+            select
+                follower_id as "follower_id!: Uuid",
+                accepted as "accepted!: bool"
+            from follow
+            where (follower_id = $1 and followee_id = $2) or (follower_id = $2 and followee_id = $1)
+        "#,
+        user_id,
+        other_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let outgoing = follows.iter().find(|follow| follow.follower_id == user_id);
+    let incoming = follows.iter().find(|follow| follow.follower_id == other_id);
+
+    Ok(Relationship {
+        id: other_id.to_string(),
+        following: outgoing.is_some_and(|follow| follow.accepted),
+        followed_by: incoming.is_some_and(|follow| follow.accepted),
+        requested: outgoing.is_some_and(|follow| !follow.accepted),
+        blocking: false,
+        muting: false,
+        endorsed: false,
+    })
+}
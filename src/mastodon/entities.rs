@@ -0,0 +1,366 @@
+use crate::{
+    Result,
+    router::server::ApiContext,
+    federation::{self, outbox},
+    media::processing::Thumbnail,
+};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{types::Json as SqlJson, QueryBuilder};
+use std::collections::HashMap;
+use time::{format_description::well_known::Rfc3339, PrimitiveDateTime};
+use uuid::Uuid;
+
+/// A user as a Mastodon `Account`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Account {
+    id: String,
+    username: String,
+    // `username` for local users, `username@domain` for remote ones.
+    acct: String,
+    display_name: String,
+    locked: bool,
+    bot: bool,
+    created_at: String,
+    note: String,
+    url: String,
+    avatar: String,
+    avatar_static: String,
+    header: String,
+    header_static: String,
+    followers_count: i64,
+    following_count: i64,
+    statuses_count: i64,
+    last_status_at: Option<String>,
+    emojis: Vec<serde_json::Value>,
+    fields: Vec<serde_json::Value>,
+}
+
+/// A message as a Mastodon `Status`. Every message is public.
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    id: String,
+    created_at: String,
+    edited_at: Option<String>,
+    in_reply_to_id: Option<String>,
+    in_reply_to_account_id: Option<String>,
+    sensitive: bool,
+    spoiler_text: String,
+    visibility: &'static str,
+    language: Option<String>,
+    uri: String,
+    url: String,
+    replies_count: i64,
+    reblogs_count: i64,
+    favourites_count: i64,
+    favourited: bool,
+    reblogged: bool,
+    muted: bool,
+    bookmarked: bool,
+    content: String,
+    // Set to the original message when listing reposts, which kiwi doesn't do here.
+    reblog: Option<Box<Status>>,
+    account: Account,
+    media_attachments: Vec<MediaAttachment>,
+    mentions: Vec<serde_json::Value>,
+    tags: Vec<serde_json::Value>,
+    emojis: Vec<serde_json::Value>,
+    card: Option<serde_json::Value>,
+    poll: Option<serde_json::Value>,
+}
+
+impl Status {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaAttachment {
+    id: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    url: String,
+    preview_url: String,
+    remote_url: Option<String>,
+    description: Option<String>,
+    blurhash: Option<String>,
+    meta: serde_json::Value,
+}
+
+#[derive(sqlx::FromRow)]
+struct AccountRow {
+    id: Uuid,
+    username: String,
+    display_name: String,
+    bio: String,
+    image: Option<String>,
+    banner: Option<String>,
+    created_at: PrimitiveDateTime,
+    actor_url: Option<String>,
+    followers_count: i64,
+    following_count: i64,
+    statuses_count: i64,
+    last_status_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(sqlx::FromRow)]
+struct StatusRow {
+    id: Uuid,
+    author_id: Uuid,
+    created_at: PrimitiveDateTime,
+    message: String,
+    message_parent_id: Option<Uuid>,
+    edited_at: Option<PrimitiveDateTime>,
+    deleted_at: Option<PrimitiveDateTime>,
+    parent_author_id: Option<Uuid>,
+    object_url: Option<String>,
+    replies_count: i64,
+    reblogs_count: i64,
+    favourites_count: i64,
+    favourited: bool,
+    reblogged: bool,
+    bookmarked: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct MediaRow {
+    id: Uuid,
+    message_id: Uuid,
+    kind: String,
+    url: String,
+    alt_text: String,
+    width: Option<i64>,
+    height: Option<i64>,
+    blurhash: Option<String>,
+    thumbnails: SqlJson<Vec<Thumbnail>>,
+}
+
+/// Loads the accounts of `ids`, keyed by user id. Unknown ids are left out.
+pub async fn accounts(ctx: &ApiContext, ids: &[Uuid]) -> Result<HashMap<Uuid, Account>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut query = QueryBuilder::new(
+        r#"
+            select
+                user.id,
+                user.username,
+                user.display_name,
+                user.bio,
+                user.image,
+                user.banner,
+                user.created_at,
+                remote_actor.actor_url,
+                user.follower_count as followers_count,
+                (select count(*) from follow where follower_id = user.id and accepted) as following_count,
+                user.message_count as statuses_count,
+                (select max(created_at) from message where author_id = user.id and deleted_at is null) as last_status_at
+            from user
+            left join remote_actor on remote_actor.user_id = user.id
+            where user.id in (
+        "#,
+    );
+
+    let mut separated = query.separated(", ");
+    for id in ids {
+        separated.push_bind(*id);
+    }
+    separated.push_unseparated(")");
+
+    let rows: Vec<AccountRow> = query.build_query_as().fetch_all(&ctx.db).await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.id, to_account(ctx, row)))
+        .collect())
+}
+
+pub async fn account(ctx: &ApiContext, id: Uuid) -> Result<Option<Account>> {
+    Ok(accounts(ctx, &[id]).await?.remove(&id))
+}
+
+/// Loads the statuses of `ids` as seen by `viewer_id`, in the same order.
+/// Unknown ids are left out, deleted messages come back without content.
+pub async fn statuses(ctx: &ApiContext, viewer_id: Uuid, ids: &[Uuid]) -> Result<Vec<Status>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut query = QueryBuilder::new(
+        r#"
+            select
+                message.id,
+                message.author_id,
+                message.created_at,
+                message.message,
+                message.message_parent_id,
+                message.edited_at,
+                message.deleted_at,
+                parent.author_id as parent_author_id,
+                remote_object.object_url,
+                message.reply_count as replies_count,
+                message.repost_count as reblogs_count,
+                message.like_count as favourites_count,
+                exists(select 1 from "like" where "like".message_id = message.id and "like".user_id = 
+        "#,
+    );
+    query.push_bind(viewer_id);
+    query.push(") as favourited, exists(select 1 from repost where repost.message_id = message.id and repost.user_id = ");
+    query.push_bind(viewer_id);
+    query.push(") as reblogged, exists(select 1 from bookmark where bookmark.message_id = message.id and bookmark.user_id = ");
+    query.push_bind(viewer_id);
+    query.push(
+        r#") as bookmarked
+            from message
+            left join message parent on parent.id = message.message_parent_id
+            left join remote_object on remote_object.message_id = message.id
+            where message.id in (
+        "#,
+    );
+
+    let mut separated = query.separated(", ");
+    for id in ids {
+        separated.push_bind(*id);
+    }
+    separated.push_unseparated(")");
+
+    let rows: Vec<StatusRow> = query.build_query_as().fetch_all(&ctx.db).await?;
+
+    let mut author_ids: Vec<Uuid> = rows.iter().map(|row| row.author_id).collect();
+    author_ids.sort();
+    author_ids.dedup();
+
+    let accounts = accounts(ctx, &author_ids).await?;
+    let mut media = media(ctx, ids).await?;
+
+    let mut statuses: HashMap<Uuid, Status> = HashMap::with_capacity(rows.len());
+
+    for row in rows {
+        let Some(account) = accounts.get(&row.author_id) else {
+            continue;
+        };
+
+        let uri = row.object_url.unwrap_or_else(|| federation::note_url(&ctx.config.public_url, row.id));
+        let is_deleted = row.deleted_at.is_some();
+
+        statuses.insert(row.id, Status {
+            id: row.id.to_string(),
+            created_at: format_date(row.created_at),
+            edited_at: row.edited_at.map(format_date),
+            in_reply_to_id: row.message_parent_id.map(|id| id.to_string()),
+            in_reply_to_account_id: row.parent_author_id.map(|id| id.to_string()),
+            sensitive: false,
+            spoiler_text: String::new(),
+            visibility: "public",
+            language: None,
+            url: uri.clone(),
+            uri,
+            replies_count: row.replies_count,
+            reblogs_count: row.reblogs_count,
+            favourites_count: row.favourites_count,
+            favourited: row.favourited,
+            reblogged: row.reblogged,
+            muted: false,
+            bookmarked: row.bookmarked,
+            content: if is_deleted { String::new() } else { outbox::to_html(&row.message) },
+            reblog: None,
+            account: account.clone(),
+            media_attachments: if is_deleted { Vec::new() } else { media.remove(&row.id).unwrap_or_default() },
+            mentions: Vec::new(),
+            tags: Vec::new(),
+            emojis: Vec::new(),
+            card: None,
+            poll: None,
+        });
+    }
+
+    Ok(ids.iter().filter_map(|id| statuses.remove(id)).collect())
+}
+
+pub async fn status(ctx: &ApiContext, viewer_id: Uuid, id: Uuid) -> Result<Option<Status>> {
+    Ok(statuses(ctx, viewer_id, &[id]).await?.pop())
+}
+
+async fn media(ctx: &ApiContext, message_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<MediaAttachment>>> {
+    let mut query = QueryBuilder::new(
+        r#"
+            select id, message_id, kind, url, alt_text, width, height, blurhash, thumbnails
+            from message_media
+            where message_id in (
+        "#,
+    );
+
+    let mut separated = query.separated(", ");
+    for id in message_ids {
+        separated.push_bind(*id);
+    }
+    separated.push_unseparated(") order by position");
+
+    let rows: Vec<MediaRow> = query.build_query_as().fetch_all(&ctx.db).await?;
+
+    let mut media: HashMap<Uuid, Vec<MediaAttachment>> = HashMap::new();
+
+    for row in rows {
+        let preview_url = row.thumbnails
+            .first()
+            .map_or_else(|| row.url.clone(), |thumbnail| thumbnail.url.clone());
+
+        media.entry(row.message_id).or_default().push(MediaAttachment {
+            id: row.id.to_string(),
+            kind: if row.kind == "video" { "video" } else { "image" },
+            url: row.url,
+            preview_url,
+            remote_url: None,
+            description: (!row.alt_text.is_empty()).then_some(row.alt_text),
+            blurhash: row.blurhash,
+            meta: json!({ "original": { "width": row.width, "height": row.height } }),
+        });
+    }
+
+    Ok(media)
+}
+
+fn to_account(ctx: &ApiContext, row: AccountRow) -> Account {
+    let public_url = &ctx.config.public_url;
+
+    let is_remote = row.actor_url.is_some();
+
+    let username = match row.username.split_once('@') {
+        Some((name, _)) if is_remote => name.to_string(),
+        _ => row.username.clone(),
+    };
+
+    // Bios of remote users are already HTML.
+    let note = if is_remote { row.bio } else { outbox::to_html(&row.bio) };
+
+    let avatar = row.image.unwrap_or_default();
+    let header = row.banner.unwrap_or_default();
+
+    Account {
+        id: row.id.to_string(),
+        username,
+        url: row.actor_url.unwrap_or_else(|| federation::actor_url(public_url, row.id)),
+        acct: row.username,
+        display_name: row.display_name,
+        locked: false,
+        bot: false,
+        created_at: format_date(row.created_at),
+        note,
+        avatar_static: avatar.clone(),
+        avatar,
+        header_static: header.clone(),
+        header,
+        followers_count: row.followers_count,
+        following_count: row.following_count,
+        statuses_count: row.statuses_count,
+        last_status_at: row.last_status_at.map(|date| date.date().to_string()),
+        emojis: Vec::new(),
+        fields: Vec::new(),
+    }
+}
+
+pub fn format_date(date: PrimitiveDateTime) -> String {
+    date.assume_utc().format(&Rfc3339).unwrap_or_default()
+}
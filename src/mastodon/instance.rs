@@ -0,0 +1,69 @@
+use crate::{
+    Result,
+    router::server::ApiContext,
+    federation::signatures,
+    media::attachments::MAX_ATTACHMENTS,
+};
+use axum::{extract::Extension, Json};
+use serde_json::{json, Value};
+
+// Mastodon version the API is modeled after. Clients check it to decide which features to use.
+const COMPATIBLE_VERSION: &str = "4.0.0";
+
+// Messages have no length limit, but clients enforce this one, which defaults to 500 if unset.
+const MAX_CHARACTERS: usize = 5000;
+
+/// `GET /api/v1/instance`, which clients fetch before logging in.
+pub async fn get_instance(ctx: Extension<ApiContext>) -> Result<Json<Value>> {
+    let public_url = &ctx.config.public_url;
+    let domain = reqwest::Url::parse(public_url)
+        .map(|url| signatures::host(&url))
+        .unwrap_or_default();
+
+    let stats = sqlx::query!(
+        r#"
+            select
+                (select count(*) from user
+                    where not exists(select 1 from remote_actor where remote_actor.user_id = user.id)) as "users!: i64",
+                (select count(*) from message
+                    where deleted_at is null
+                    and not exists(select 1 from remote_actor where remote_actor.user_id = message.author_id)) as "statuses!: i64",
+                (select count(distinct substr(username, instr(username, '@') + 1)) from user
+                    where exists(select 1 from remote_actor where remote_actor.user_id = user.id)) as "domains!: i64"
+        "#
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let streaming_url = public_url.replacen("http", "ws", 1);
+
+    Ok(Json(json!({
+        "uri": domain,
+        "title": "kiwi",
+        "short_description": "",
+        "description": "",
+        "email": "",
+        "version": format!("{} (compatible; kiwi {})", COMPATIBLE_VERSION, env!("CARGO_PKG_VERSION")),
+        "urls": { "streaming_api": streaming_url },
+        "stats": {
+            "user_count": stats.users,
+            "status_count": stats.statuses,
+            "domain_count": stats.domains,
+        },
+        "thumbnail": null,
+        "languages": ["en"],
+        "registrations": true,
+        "approval_required": false,
+        "invites_enabled": false,
+        "configuration": {
+            "statuses": { "max_characters": MAX_CHARACTERS, "max_media_attachments": MAX_ATTACHMENTS },
+        },
+        "contact_account": null,
+        "rules": [],
+    })))
+}
+
+/// `GET /api/v1/custom_emojis`. There are none, but clients ask.
+pub async fn get_custom_emojis() -> Json<Value> {
+    Json(json!([]))
+}
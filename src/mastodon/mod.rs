@@ -0,0 +1,9 @@
+pub mod params;
+pub mod entities;
+pub mod oauth;
+pub mod instance;
+pub mod accounts;
+pub mod statuses;
+pub mod timelines;
+pub mod notifications;
+pub mod routes;
@@ -0,0 +1,175 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+    },
+    mastodon::{
+        entities::{self, format_date, Account, Status},
+        params::Page,
+    },
+};
+use axum::{
+    extract::{Extension, OriginalUri, Path, Query},
+    http::header::LINK,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use sqlx::QueryBuilder;
+use std::collections::HashMap;
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+/// A notification as a Mastodon `Notification`. Unlike `GET /api/notifications`,
+/// these aren't grouped.
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    id: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    created_at: String,
+    account: Account,
+    status: Option<Status>,
+}
+
+#[derive(sqlx::FromRow)]
+struct NotificationRow {
+    id: Uuid,
+    kind: String,
+    actor_id: Uuid,
+    message_id: Option<Uuid>,
+    created_at: PrimitiveDateTime,
+}
+
+/// `GET /api/v1/notifications`, newest first.
+pub async fn get_notifications(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    OriginalUri(uri): OriginalUri,
+    Query(page): Query<Page>
+) -> Result<Response> {
+    let mut query = QueryBuilder::new(
+        "select id, kind, actor_id, message_id, created_at from notification where notification.user_id = ",
+    );
+    query.push_bind(auth_user.user_id);
+
+    page.push(&mut query, "notification");
+
+    let mut rows: Vec<NotificationRow> = query.build_query_as().fetch_all(&ctx.db).await?;
+
+    if page.min_id.is_some() {
+        rows.reverse();
+    }
+
+    let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+    let notifications = to_notifications(&ctx, auth_user.user_id, rows).await?;
+
+    match Page::link_header(&ctx.config.public_url, uri.path(), &ids) {
+        Some(link) => Ok(([(LINK, link)], Json(notifications)).into_response()),
+        None => Ok(Json(notifications).into_response()),
+    }
+}
+
+pub async fn get_notification(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Notification>> {
+    let row = sqlx::query_as!(
+        NotificationRow,
+        r#"
+            select
+                id as "id!: Uuid",
+                kind,
+                actor_id as "actor_id!: Uuid",
+                message_id as "message_id: Uuid",
+                created_at as "created_at!: PrimitiveDateTime"
+            from notification
+            where id = $1 and user_id = $2
+        "#,
+        id,
+        auth_user.user_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    to_notifications(&ctx, auth_user.user_id, vec![row])
+        .await?
+        .pop()
+        .map(Json)
+        .ok_or(Error::NotFound)
+}
+
+/// `POST /api/v1/notifications/clear`, deletes every notification of the current user.
+pub async fn clear_notifications(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>
+) -> Result<Json<serde_json::Value>> {
+    sqlx::query!("delete from notification where user_id = $1", auth_user.user_id)
+        .execute(&ctx.db)
+        .await?;
+
+    Ok(Json(serde_json::json!({})))
+}
+
+/// `POST /api/v1/notifications/:id/dismiss`
+pub async fn dismiss_notification(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<serde_json::Value>> {
+    sqlx::query!(
+        "delete from notification where id = $1 and user_id = $2",
+        id,
+        auth_user.user_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(Json(serde_json::json!({})))
+}
+
+async fn to_notifications(ctx: &ApiContext, viewer_id: Uuid, rows: Vec<NotificationRow>) -> Result<Vec<Notification>> {
+    let mut actor_ids: Vec<Uuid> = rows.iter().map(|row| row.actor_id).collect();
+    actor_ids.sort();
+    actor_ids.dedup();
+
+    let message_ids: Vec<Uuid> = rows.iter().filter_map(|row| row.message_id).collect();
+
+    let accounts = entities::accounts(ctx, &actor_ids).await?;
+    let statuses: HashMap<String, Status> = entities::statuses(ctx, viewer_id, &message_ids)
+        .await?
+        .into_iter()
+        .map(|status| (status.id().to_string(), status))
+        .collect();
+
+    let mut notifications = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let Some(account) = accounts.get(&row.actor_id) else {
+            continue;
+        };
+
+        // Replies show up as mentions, Mastodon has no notification type for them.
+        let kind = match row.kind.as_str() {
+            "like" => "favourite",
+            "follow" => "follow",
+            _ => "mention",
+        };
+
+        let status = row.message_id.and_then(|message_id| statuses.get(&message_id.to_string()).cloned());
+
+        notifications.push(Notification {
+            id: row.id.to_string(),
+            kind,
+            created_at: format_date(row.created_at),
+            account: account.clone(),
+            status,
+        });
+    }
+
+    Ok(notifications)
+}
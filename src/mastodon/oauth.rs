@@ -0,0 +1,432 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::{AuthUser, RequestMeta},
+    },
+    user::users,
+    audit::events::{self, Event},
+    mastodon::params::FormOrJson,
+};
+use axum::{
+    extract::{Extension, Form, Query},
+    response::{Html, IntoResponse, Redirect, Response},
+    Json,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+// Redirect URI of clients that show the code to the user instead of receiving it.
+const OUT_OF_BAND: &str = "urn:ietf:wg:oauth:2.0:oob";
+
+// How long an authorization code can be exchanged for a token.
+const CODE_LIFETIME: &str = "+10 minutes";
+
+// The only scopes granted. Tokens can do anything the user can, so clients asking for less
+// are refused rather than given more than they asked for.
+const SCOPES: &str = "read write follow";
+
+#[derive(Debug, Deserialize)]
+pub struct AppRequest {
+    client_name: String,
+    redirect_uris: String,
+    scopes: Option<String>,
+    website: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Application {
+    id: String,
+    name: String,
+    website: Option<String>,
+    redirect_uri: String,
+    client_id: String,
+    client_secret: String,
+    vapid_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    state: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeForm {
+    client_id: String,
+    redirect_uri: String,
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    state: Option<String>,
+    email: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    client_id: String,
+    client_secret: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    // The email, for the `password` grant.
+    username: Option<String>,
+    password: Option<String>,
+    scope: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeRequest {
+    client_id: String,
+    client_secret: String,
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Token {
+    access_token: String,
+    token_type: &'static str,
+    scope: String,
+    created_at: i64,
+}
+
+struct App {
+    id: Uuid,
+    redirect_uris: String,
+}
+
+/// `POST /api/v1/apps`, registers a client application.
+///
+/// `scopes` must be `read write follow` if given, see `SCOPES`.
+pub async fn create_app(
+    ctx: Extension<ApiContext>,
+    FormOrJson(req): FormOrJson<AppRequest>
+) -> Result<Json<Application>> {
+    if req.client_name.trim().is_empty() {
+        return Err(Error::unprocessable_entity([("client_name", "must not be empty")]));
+    }
+
+    if req.redirect_uris.split_whitespace().next().is_none() {
+        return Err(Error::unprocessable_entity([("redirect_uris", "must not be empty")]));
+    }
+
+    check_scopes("scopes", req.scopes.as_deref())?;
+
+    let id = Uuid::new_v4();
+    let client_id = random_token();
+    let client_secret = random_token();
+
+    sqlx::query!(
+        r#"
+            insert into oauth_app (id, name, website, redirect_uris, scopes, client_id, client_secret)
+            values ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        id,
+        req.client_name,
+        req.website,
+        req.redirect_uris,
+        SCOPES,
+        client_id,
+        client_secret
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(Json(Application {
+        id: id.to_string(),
+        name: req.client_name,
+        website: req.website,
+        redirect_uri: req.redirect_uris,
+        client_id,
+        client_secret,
+        vapid_key: None,
+    }))
+}
+
+/// `GET /oauth/authorize`, the login form clients open in a browser.
+pub async fn authorize_form(
+    ctx: Extension<ApiContext>,
+    Query(query): Query<AuthorizeQuery>
+) -> Result<Html<String>> {
+    let app = find_app(&ctx, &query.client_id).await?;
+    check_redirect_uri(&app, &query.redirect_uri)?;
+    check_scopes("scope", query.scope.as_deref())?;
+
+    Ok(login_page(&query.client_id, &query.redirect_uri, query.scope.as_deref(), query.state.as_deref(), None))
+}
+
+/// `POST /oauth/authorize`, checks the credentials entered in the login form and sends
+/// the browser back to the client with an authorization code.
+pub async fn authorize(
+    ctx: Extension<ApiContext>,
+    meta: RequestMeta,
+    Form(form): Form<AuthorizeForm>
+) -> Result<Response> {
+    let app = find_app(&ctx, &form.client_id).await?;
+    check_redirect_uri(&app, &form.redirect_uri)?;
+    check_scopes("scope", form.scope.as_deref())?;
+
+    let auth_user = match users::authenticate(&ctx, &meta, form.email, form.password).await {
+        Ok(auth_user) => auth_user,
+        Err(Error::UnprocessableEntity { .. }) => {
+            return Ok(login_page(
+                &form.client_id,
+                &form.redirect_uri,
+                form.scope.as_deref(),
+                form.state.as_deref(),
+                Some("Invalid email or password."),
+            )
+            .into_response());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let code = random_token();
+
+    sqlx::query!(
+        r#"
+            insert into oauth_code (code, app_id, user_id, redirect_uri, scopes, expires_at)
+            values ($1, $2, $3, $4, $5, datetime('now', $6))
+        "#,
+        code,
+        app.id,
+        auth_user.user_id,
+        form.redirect_uri,
+        SCOPES,
+        CODE_LIFETIME
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    if form.redirect_uri == OUT_OF_BAND {
+        return Ok(Html(format!(
+            "<!doctype html><title>kiwi</title><p>Copy this code into the application:</p><pre>{}</pre>",
+            code
+        ))
+        .into_response());
+    }
+
+    let mut redirect = reqwest::Url::parse(&form.redirect_uri)
+        .map_err(|_| Error::unprocessable_entity([("redirect_uri", "is invalid")]))?;
+
+    redirect.query_pairs_mut().append_pair("code", &code);
+    if let Some(state) = &form.state {
+        redirect.query_pairs_mut().append_pair("state", state);
+    }
+
+    Ok(Redirect::to(redirect.as_str()).into_response())
+}
+
+/// `POST /oauth/token`, issues an access token for an authorization code, or directly
+/// for an email and password with the `password` grant.
+///
+/// Access tokens are session tokens tied to an `oauth_token` row, sent back as
+/// `Authorization: Bearer`. They always have the scopes `read write follow` and expire with
+/// the session, after which the client has to log in again.
+pub async fn token(
+    ctx: Extension<ApiContext>,
+    meta: RequestMeta,
+    FormOrJson(req): FormOrJson<TokenRequest>
+) -> Result<Json<Token>> {
+    let app = find_client(&ctx, &req.client_id, &req.client_secret).await?;
+
+    let auth_user = match req.grant_type.as_str() {
+        "authorization_code" => {
+            let code = req.code.ok_or_else(|| Error::unprocessable_entity([("code", "is missing")]))?;
+
+            let grant = sqlx::query!(
+                r#"
+                    delete from oauth_code
+                    where code = $1 and app_id = $2 and expires_at > current_timestamp
+                    returning user_id as "user_id!: Uuid", redirect_uri as "redirect_uri!"
+                "#,
+                code,
+                app.id
+            )
+            .fetch_optional(&ctx.db)
+            .await?
+            .ok_or(Error::Unauthorized)?;
+
+            if req.redirect_uri.is_some_and(|redirect_uri| redirect_uri != grant.redirect_uri) {
+                return Err(Error::Unauthorized);
+            }
+
+            let token_version = sqlx::query_scalar!(
+                r#"select token_version as "token_version!: i64" from user where id = $1"#,
+                grant.user_id
+            )
+            .fetch_one(&ctx.db)
+            .await?;
+
+            AuthUser { user_id: grant.user_id, token_version, token_id: None }
+        }
+        "password" => {
+            let (Some(email), Some(password)) = (req.username, req.password) else {
+                return Err(Error::unprocessable_entity([("username", "and password are required")]));
+            };
+
+            check_scopes("scope", req.scope.as_deref())?;
+
+            users::authenticate(&ctx, &meta, email, password).await?
+        }
+        _ => return Err(Error::unprocessable_entity([("grant_type", "is not supported")])),
+    };
+
+    let token_id = Uuid::new_v4();
+
+    sqlx::query!(
+        "insert into oauth_token (id, app_id, user_id) values ($1, $2, $3)",
+        token_id,
+        app.id,
+        auth_user.user_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    events::record(&ctx.db, &meta, Event::TokenCreated, Some(auth_user.user_id), Some(auth_user.user_id)).await?;
+
+    let auth_user = AuthUser { token_id: Some(token_id), ..auth_user };
+
+    Ok(Json(Token {
+        access_token: auth_user.to_jwt(&ctx),
+        token_type: "Bearer",
+        scope: SCOPES.to_string(),
+        created_at: OffsetDateTime::now_utc().unix_timestamp(),
+    }))
+}
+
+/// `POST /oauth/revoke`, revokes a token the client was issued.
+///
+/// Like RFC 7009 says, tokens that are invalid, already revoked or issued to another client
+/// are not an error.
+pub async fn revoke(
+    ctx: Extension<ApiContext>,
+    FormOrJson(req): FormOrJson<RevokeRequest>
+) -> Result<Json<serde_json::Value>> {
+    let app = find_client(&ctx, &req.client_id, &req.client_secret).await?;
+
+    if let Some(token_id) = AuthUser::from_token(&ctx, &req.token).ok().and_then(|auth_user| auth_user.token_id) {
+        sqlx::query!(
+            "delete from oauth_token where id = $1 and app_id = $2",
+            token_id,
+            app.id
+        )
+        .execute(&ctx.db)
+        .await?;
+    }
+
+    Ok(Json(json!({})))
+}
+
+async fn find_app(ctx: &ApiContext, client_id: &str) -> Result<App> {
+    sqlx::query_as!(
+        App,
+        r#"select id as "id!: Uuid", redirect_uris from oauth_app where client_id = $1"#,
+        client_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or_else(|| Error::unprocessable_entity([("client_id", "is unknown")]))
+}
+
+/// Finds the app a client authenticates as with its credentials.
+async fn find_client(ctx: &ApiContext, client_id: &str, client_secret: &str) -> Result<App> {
+    sqlx::query_as!(
+        App,
+        r#"
+            select id as "id!: Uuid", redirect_uris
+            from oauth_app
+            where client_id = $1 and client_secret = $2
+        "#,
+        client_id,
+        client_secret
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::Unauthorized)
+}
+
+/// Checks that `scopes`, when a client asks for some, are `SCOPES`.
+fn check_scopes(field: &'static str, scopes: Option<&str>) -> Result<()> {
+    let Some(scopes) = scopes else {
+        return Ok(());
+    };
+
+    let mut requested: Vec<_> = scopes.split_whitespace().collect();
+    requested.sort_unstable();
+    requested.dedup();
+
+    let mut supported: Vec<_> = SCOPES.split_whitespace().collect();
+    supported.sort_unstable();
+
+    if requested != supported {
+        return Err(Error::unprocessable_entity([(field, "must be read write follow")]));
+    }
+
+    Ok(())
+}
+
+fn check_redirect_uri(app: &App, redirect_uri: &str) -> Result<()> {
+    if !app.redirect_uris.split_whitespace().any(|uri| uri == redirect_uri) {
+        return Err(Error::unprocessable_entity([("redirect_uri", "is not registered for this application")]));
+    }
+
+    Ok(())
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn login_page(client_id: &str, redirect_uri: &str, scope: Option<&str>, state: Option<&str>, error: Option<&str>) -> Html<String> {
+    let hidden = [
+        ("client_id", Some(client_id)),
+        ("redirect_uri", Some(redirect_uri)),
+        ("scope", scope),
+        ("state", state),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.map(|value| {
+        format!(r#"<input type="hidden" name="{}" value="{}">"#, name, escape(value))
+    }))
+    .collect::<String>();
+
+    let error = error
+        .map(|error| format!("<p><strong>{}</strong></p>", escape(error)))
+        .unwrap_or_default();
+
+    Html(format!(
+        concat!(
+            "<!doctype html>\n",
+            "<title>Log in to kiwi</title>\n",
+            "<h1>Log in to kiwi</h1>\n",
+            "{}\n",
+            r#"<form method="post" action="/oauth/authorize">"#, "\n",
+            "{}\n",
+            r#"<p><label>Email <input type="email" name="email" required></label></p>"#, "\n",
+            r#"<p><label>Password <input type="password" name="password" required></label></p>"#, "\n",
+            r#"<p><button type="submit">Authorize</button></p>"#, "\n",
+            "</form>\n",
+        ),
+        error, hidden
+    ))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,97 @@
+use crate::Error;
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{Form, FromRequest},
+    http::{header::CONTENT_TYPE, Request},
+    BoxError, Json,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use sqlx::{QueryBuilder, Sqlite};
+use uuid::Uuid;
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 40;
+
+/// A request body that Mastodon clients send either as a form or as JSON.
+pub struct FormOrJson<T>(pub T);
+
+#[async_trait]
+impl<S, B, T> FromRequest<S, B> for FormOrJson<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("application/json"));
+
+        let value = if is_json {
+            Json::<T>::from_request(req, state).await.map(|Json(value)| value).map_err(|e| e.to_string())
+        } else {
+            Form::<T>::from_request(req, state).await.map(|Form(value)| value).map_err(|e| e.to_string())
+        };
+
+        value
+            .map(Self)
+            .map_err(|e| Error::unprocessable_entity([("body", e)]))
+    }
+}
+
+/// Mastodon's id-based pagination: `max_id` pages towards older items, `since_id` and
+/// `min_id` towards newer ones, `min_id` starting right after the given item.
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct Page {
+    pub max_id: Option<Uuid>,
+    pub since_id: Option<Uuid>,
+    pub min_id: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+impl Page {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    /// Appends the conditions, order and limit of the page to a query on `table`,
+    /// whose rows are ordered by `created_at` and `rowid`.
+    ///
+    /// The query must end in a `where` clause. Pages fetched with `min_id` come back oldest
+    /// first and have to be reversed.
+    pub fn push(&self, query: &mut QueryBuilder<'_, Sqlite>, table: &str) {
+        for (id, op) in [(self.max_id, "<"), (self.since_id, ">"), (self.min_id, ">")] {
+            if let Some(id) = id {
+                query.push(format!(
+                    " and ({0}.created_at, {0}.rowid) {1} (select created_at, rowid from {0} where id = ",
+                    table, op
+                ));
+                query.push_bind(id);
+                query.push(")");
+            }
+        }
+
+        let direction = if self.min_id.is_some() { "asc" } else { "desc" };
+
+        query.push(format!(" order by {0}.created_at {1}, {0}.rowid {1} limit ", table, direction));
+        query.push_bind(self.limit());
+    }
+
+    /// The `Link` header pointing to the pages before and after `ids`, which are newest first.
+    pub fn link_header(public_url: &str, path: &str, ids: &[Uuid]) -> Option<String> {
+        let (first, last) = (ids.first()?, ids.last()?);
+
+        Some(format!(
+            r#"<{0}{1}?max_id={2}>; rel="next", <{0}{1}?min_id={3}>; rel="prev""#,
+            public_url, path, last, first
+        ))
+    }
+}
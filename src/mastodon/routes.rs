@@ -0,0 +1,128 @@
+use crate::mastodon::{accounts, instance, notifications, oauth, statuses, timelines};
+use axum::{
+    routing::{get, post, patch},
+    Router,
+};
+
+/// The subset of the Mastodon client API that maps onto kiwi.
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/v1/instance",
+            get(instance::get_instance)
+        )
+        .route(
+            "/api/v1/custom_emojis",
+            get(instance::get_custom_emojis)
+        )
+        .route(
+            "/api/v1/apps",
+            post(oauth::create_app)
+        )
+        .route(
+            "/oauth/authorize",
+            get(oauth::authorize_form)
+                .post(oauth::authorize)
+        )
+        .route(
+            "/oauth/token",
+            post(oauth::token)
+        )
+        .route(
+            "/oauth/revoke",
+            post(oauth::revoke)
+        )
+        .route(
+            "/api/v1/accounts/verify_credentials",
+            get(accounts::verify_credentials)
+        )
+        .route(
+            "/api/v1/accounts/update_credentials",
+            patch(accounts::update_credentials)
+        )
+        .route(
+            "/api/v1/accounts/lookup",
+            get(accounts::lookup_account)
+        )
+        .route(
+            "/api/v1/accounts/relationships",
+            get(accounts::get_relationships)
+        )
+        .route(
+            "/api/v1/accounts/:id",
+            get(accounts::get_account)
+        )
+        .route(
+            "/api/v1/accounts/:id/statuses",
+            get(accounts::get_account_statuses)
+        )
+        .route(
+            "/api/v1/accounts/:id/followers",
+            get(accounts::get_followers)
+        )
+        .route(
+            "/api/v1/accounts/:id/following",
+            get(accounts::get_following)
+        )
+        .route(
+            "/api/v1/accounts/:id/follow",
+            post(accounts::follow_account)
+        )
+        .route(
+            "/api/v1/accounts/:id/unfollow",
+            post(accounts::unfollow_account)
+        )
+        .route(
+            "/api/v1/statuses",
+            post(statuses::create_status)
+        )
+        .route(
+            "/api/v1/statuses/:id",
+            get(statuses::get_status)
+                .delete(statuses::delete_status)
+        )
+        .route(
+            "/api/v1/statuses/:id/context",
+            get(statuses::get_context)
+        )
+        .route(
+            "/api/v1/statuses/:id/favourite",
+            post(statuses::favourite)
+        )
+        .route(
+            "/api/v1/statuses/:id/unfavourite",
+            post(statuses::unfavourite)
+        )
+        .route(
+            "/api/v1/favourites",
+            get(statuses::get_favourites)
+        )
+        .route(
+            "/api/v1/timelines/home",
+            get(timelines::get_home)
+        )
+        .route(
+            "/api/v1/timelines/public",
+            get(timelines::get_public)
+        )
+        .route(
+            "/api/v1/timelines/tag/:hashtag",
+            get(timelines::get_tag)
+        )
+        .route(
+            "/api/v1/notifications",
+            get(notifications::get_notifications)
+        )
+        .route(
+            "/api/v1/notifications/clear",
+            post(notifications::clear_notifications)
+        )
+        .route(
+            "/api/v1/notifications/:id",
+            get(notifications::get_notification)
+        )
+        .route(
+            "/api/v1/notifications/:id/dismiss",
+            post(notifications::dismiss_notification)
+        )
+}
@@ -0,0 +1,200 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::{AuthUser, RequestMeta},
+    },
+    message::messages::{self, MessageRequest},
+    like::likes,
+    mastodon::{
+        entities::{self, Status},
+        params::{FormOrJson, Page},
+    },
+};
+use axum::{
+    extract::{Extension, Path, Query},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Replies further down a thread than this are left out of `context`.
+const MAX_THREAD_DEPTH: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct StatusRequest {
+    status: String,
+    in_reply_to_id: Option<Uuid>,
+    // Only sent in JSON bodies, forms can't carry `media_ids[]` here.
+    #[serde(default, alias = "media_ids[]")]
+    media_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Context {
+    ancestors: Vec<Status>,
+    descendants: Vec<Status>,
+}
+
+/// `POST /api/v1/statuses`, posts a message or a reply.
+pub async fn create_status(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    FormOrJson(req): FormOrJson<StatusRequest>
+) -> Result<Json<Status>> {
+    let message = messages::insert_message(
+        &ctx,
+        auth_user.user_id,
+        MessageRequest::new(req.status, req.media_ids),
+        req.in_reply_to_id,
+        None,
+    )
+    .await?;
+
+    get(&ctx, auth_user.user_id, message.id()).await
+}
+
+pub async fn get_status(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Status>> {
+    get(&ctx, auth_user.user_id, id).await
+}
+
+/// `DELETE /api/v1/statuses/:id`, returns the status as it was before being deleted.
+pub async fn delete_status(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    meta: RequestMeta,
+    Path(id): Path<Uuid>
+) -> Result<Json<Status>> {
+    let user_id = auth_user.user_id;
+    let status = get(&ctx, user_id, id).await?;
+
+    messages::delete_message(auth_user, ctx, meta, Path(id)).await?;
+
+    Ok(status)
+}
+
+/// `GET /api/v1/statuses/:id/context`, the parents of a status and the replies below it.
+pub async fn get_context(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Context>> {
+    let ancestor_ids = sqlx::query_scalar!(
+        r#"
+            with recursive ancestor (id, parent_id, depth) as (
+                select id, message_parent_id, 0 from message where id = $1
+                union all
+                select message.id, message.message_parent_id, ancestor.depth + 1
+                from message
+                join ancestor on message.id = ancestor.parent_id
+                where ancestor.depth < $2
+            )
+            select id as "id!: Uuid" from ancestor where depth > 0 order by depth desc
+        "#,
+        id,
+        MAX_THREAD_DEPTH
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let descendant_ids = sqlx::query_scalar!(
+        r#"
+            with recursive descendant (id, created_at, depth) as (
+                select id, created_at, 0 from message where id = $1
+                union all
+                select message.id, message.created_at, descendant.depth + 1
+                from message
+                join descendant on message.message_parent_id = descendant.id
+                where descendant.depth < $2
+            )
+            select id as "id!: Uuid" from descendant where depth > 0 order by created_at
+        "#,
+        id,
+        MAX_THREAD_DEPTH
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(Context {
+        ancestors: entities::statuses(&ctx, auth_user.user_id, &ancestor_ids).await?,
+        descendants: entities::statuses(&ctx, auth_user.user_id, &descendant_ids).await?,
+    }))
+}
+
+/// `POST /api/v1/statuses/:id/favourite`, likes a status. Liking it again does nothing.
+pub async fn favourite(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Status>> {
+    let user_id = auth_user.user_id;
+    let status = get(&ctx, user_id, id).await?;
+
+    let liked = sqlx::query_scalar!(
+        r#"select exists(select 1 from "like" where user_id = $1 and message_id = $2) as "exists!: bool""#,
+        user_id,
+        id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if liked {
+        return Ok(status);
+    }
+
+    likes::create_like(auth_user, ctx.clone(), Path(id)).await?;
+
+    get(&ctx, user_id, id).await
+}
+
+pub async fn unfavourite(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Status>> {
+    let user_id = auth_user.user_id;
+
+    match likes::delete_like(auth_user, ctx.clone(), Path(id)).await {
+        Ok(()) | Err(Error::NotFound) => (),
+        Err(e) => return Err(e),
+    }
+
+    get(&ctx, user_id, id).await
+}
+
+/// `GET /api/v1/favourites`, the statuses the current user liked, most recently liked first.
+///
+/// Only the first page is available, likes have no id to page from.
+pub async fn get_favourites(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Query(page): Query<Page>
+) -> Result<Json<Vec<Status>>> {
+    let limit = page.limit();
+
+    let ids = sqlx::query_scalar!(
+        r#"
+            select message_id as "message_id!: Uuid" from "like"
+            where user_id = $1
+            order by created_at desc
+            limit $2
+        "#,
+        auth_user.user_id,
+        limit
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(entities::statuses(&ctx, auth_user.user_id, &ids).await?))
+}
+
+async fn get(ctx: &ApiContext, viewer_id: Uuid, id: Uuid) -> Result<Json<Status>> {
+    let status = entities::status(ctx, viewer_id, id).await?.ok_or(Error::NotFound)?;
+
+    Ok(Json(status))
+}
@@ -0,0 +1,121 @@
+use crate::{
+    Result,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+    },
+    tag::tags,
+    mastodon::{accounts, params::Page},
+};
+use axum::{
+    extract::{Extension, OriginalUri, Path, Query},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use sqlx::QueryBuilder;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct PublicQuery {
+    // Only messages of local users.
+    local: bool,
+    // Only messages of remote users.
+    remote: bool,
+}
+
+/// `GET /api/v1/timelines/home`, messages of the current user and the users they follow.
+pub async fn get_home(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    OriginalUri(uri): OriginalUri,
+    Query(page): Query<Page>
+) -> Result<Response> {
+    let mut query = QueryBuilder::new(
+        r#"
+            select message.id from message
+            where message.deleted_at is null
+            and message.author_id in (
+                select followee_id from follow where accepted and follower_id =
+        "#,
+    );
+    query.push_bind(auth_user.user_id);
+    query.push(" union select ");
+    query.push_bind(auth_user.user_id);
+    query.push(")");
+
+    page.push(&mut query, "message");
+
+    respond(&ctx, auth_user.user_id, uri.path(), &page, query).await
+}
+
+/// `GET /api/v1/timelines/public`, every message, or only local or remote ones.
+pub async fn get_public(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    OriginalUri(uri): OriginalUri,
+    Query(page): Query<Page>,
+    Query(filter): Query<PublicQuery>
+) -> Result<Response> {
+    let mut query = QueryBuilder::new("select message.id from message where message.deleted_at is null");
+
+    if filter.local {
+        query.push(" and not exists(select 1 from remote_actor where remote_actor.user_id = message.author_id)");
+    } else if filter.remote {
+        query.push(" and exists(select 1 from remote_actor where remote_actor.user_id = message.author_id)");
+    }
+
+    page.push(&mut query, "message");
+
+    respond(&ctx, auth_user.user_id, uri.path(), &page, query).await
+}
+
+/// `GET /api/v1/timelines/tag/:hashtag`
+pub async fn get_tag(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    OriginalUri(uri): OriginalUri,
+    Path(tag): Path<String>,
+    Query(page): Query<Page>
+) -> Result<Response> {
+    let Some(tag) = tags::normalize(&tag) else {
+        return Ok(Json(Vec::<()>::new()).into_response());
+    };
+
+    let mut query = QueryBuilder::new(
+        r#"
+            select message.id from message
+            join message_tag on message_tag.message_id = message.id
+            join tag on tag.id = message_tag.tag_id
+            where message.deleted_at is null and tag.name =
+        "#,
+    );
+    query.push_bind(tag);
+
+    page.push(&mut query, "message");
+
+    respond(&ctx, auth_user.user_id, uri.path(), &page, query).await
+}
+
+async fn respond(
+    ctx: &ApiContext,
+    viewer_id: Uuid,
+    path: &str,
+    page: &Page,
+    mut query: QueryBuilder<'_, sqlx::Sqlite>,
+) -> Result<Response> {
+    let mut ids: Vec<Uuid> = query
+        .build_query_as::<(Uuid,)>()
+        .fetch_all(&ctx.db)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+
+    if page.min_id.is_some() {
+        ids.reverse();
+    }
+
+    accounts::statuses_response(ctx, viewer_id, path, &ids).await
+}
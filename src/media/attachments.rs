@@ -0,0 +1,483 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+    },
+    media::{
+        metadata,
+        processing::{self, Sniffed, Thumbnail},
+    },
+    storage::INCOMING_PREFIX,
+};
+use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use axum::{
+    Json,
+    extract::{Path, Extension, Multipart},
+};
+use bytes::Bytes;
+use sqlx::{QueryBuilder, Sqlite, SqlitePool, Transaction};
+use sqlx::types::Json as SqlJson;
+use std::collections::HashMap;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+
+// A message can reference up to this many attachments.
+pub const MAX_ATTACHMENTS: usize = 4;
+
+pub const MAX_IMAGE_SIZE: usize = 8 * 1024 * 1024;
+pub const MAX_VIDEO_SIZE: usize = 40 * 1024 * 1024;
+
+const MAX_ALT_TEXT_LENGTH: usize = 1500;
+
+// How long a presigned upload URL is valid.
+const PRESIGNED_UPLOAD_TTL: Duration = Duration::minutes(15);
+// The actual type is sniffed once the upload completes, like for `upload_media`.
+const PRESIGNED_UPLOAD_CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct MessageMedia {
+    id: Uuid,
+    #[serde(skip)]
+    message_id: Option<Uuid>,
+    kind: String,
+    content_type: String,
+    url: String,
+    alt_text: String,
+    width: Option<i64>,
+    height: Option<i64>,
+    blurhash: Option<String>,
+    thumbnails: SqlJson<Vec<Thumbnail>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMedia {
+    description: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PresignedUpload {
+    id: Uuid,
+    url: String,
+    // The `Content-Type` header the upload must be sent with.
+    content_type: &'static str,
+    expires_at: PrimitiveDateTime,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CompleteUpload {
+    description: String,
+}
+
+struct Upload {
+    bytes: Bytes,
+    description: String,
+}
+
+/// The stored files of an attachment, shared by every upload of the same content.
+struct StoredMedia {
+    kind: String,
+    content_type: String,
+    storage_key: String,
+    url: String,
+    width: Option<i64>,
+    height: Option<i64>,
+    blurhash: Option<String>,
+    thumbnails: SqlJson<Vec<Thumbnail>>,
+}
+
+/// Uploads an attachment so it can later be referenced in `MessageRequest::media_ids`.
+///
+/// Expects a multipart body with a `file` field and an optional `description` field
+/// holding the alt text.
+pub async fn upload_media(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    multipart: Multipart
+) -> Result<Json<MessageMedia>> {
+    let upload = read_upload(multipart).await?;
+
+    Ok(Json(create_media(&ctx, auth_user.user_id, upload).await?))
+}
+
+/// Issues a URL the client can `PUT` an attachment to, straight to the storage.
+///
+/// Nothing is attached until the upload is passed through the same processing as
+/// `upload_media` by `complete_upload`.
+pub async fn presign_upload(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>
+) -> Result<Json<PresignedUpload>> {
+    let id = Uuid::new_v4();
+    let expires_at = OffsetDateTime::now_utc() + PRESIGNED_UPLOAD_TTL;
+    let expires_at = PrimitiveDateTime::new(expires_at.date(), expires_at.time());
+
+    let url = ctx.storage
+        .presign_upload(&incoming_key(auth_user.user_id, id), PRESIGNED_UPLOAD_CONTENT_TYPE, PRESIGNED_UPLOAD_TTL)
+        .await?;
+
+    Ok(Json(PresignedUpload {
+        id,
+        url,
+        content_type: PRESIGNED_UPLOAD_CONTENT_TYPE,
+        expires_at,
+    }))
+}
+
+/// Processes a file sent to a URL issued by `presign_upload` and creates its attachment.
+pub async fn complete_upload(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<CompleteUpload>
+) -> Result<Json<MessageMedia>> {
+    if req.description.chars().count() > MAX_ALT_TEXT_LENGTH {
+        return Err(Error::unprocessable_entity([("description", "is too long")]));
+    }
+
+    // Uploads are keyed by their owner, so nobody can complete somebody else's.
+    let key = incoming_key(auth_user.user_id, id);
+
+    // Presigned S3 uploads can't be size limited, so check before downloading anything.
+    let size = ctx.storage.size(&key).await?.ok_or(Error::NotFound)?;
+
+    let result = if size > MAX_VIDEO_SIZE as u64 {
+        Err(Error::unprocessable_entity([(
+            "file",
+            format!("must be smaller than {} MiB", MAX_VIDEO_SIZE / 1024 / 1024),
+        )]))
+    } else {
+        match ctx.storage.get(&key).await? {
+            Some(bytes) => {
+                let upload = Upload {
+                    bytes,
+                    description: req.description,
+                };
+                create_media(&ctx, auth_user.user_id, upload).await
+            }
+            None => Err(Error::NotFound),
+        }
+    };
+
+    // The raw upload is never served, keep it only as long as the client may retry.
+    if !matches!(result, Err(Error::Sqlx(_) | Error::Anyhow(_))) {
+        if let Err(e) = ctx.storage.delete(&key).await {
+            log::warn!("failed to remove presigned upload {}: {:?}", key, e);
+        }
+    }
+
+    Ok(Json(result?))
+}
+
+fn incoming_key(owner_id: Uuid, id: Uuid) -> String {
+    format!("{}{}/{}", INCOMING_PREFIX, owner_id, id)
+}
+
+/// Checks, processes and stores an upload, then creates its attachment.
+async fn create_media(ctx: &ApiContext, owner_id: Uuid, upload: Upload) -> Result<MessageMedia> {
+    // The declared content type is ignored, only the actual bytes are trusted.
+    let sniffed = processing::sniff(&upload.bytes)
+        .ok_or_else(|| Error::unprocessable_entity([("file", "must be a PNG, JPEG, GIF or WebP image, or an MP4 or WebM video")]))?;
+
+    let max_size = match sniffed {
+        Sniffed::Image(_) => MAX_IMAGE_SIZE,
+        Sniffed::Video { .. } => MAX_VIDEO_SIZE,
+    };
+
+    if upload.bytes.len() > max_size {
+        return Err(Error::unprocessable_entity([(
+            "file",
+            format!("must be smaller than {} MiB", max_size / 1024 / 1024),
+        )]));
+    }
+
+    let content_hash = processing::content_hash(&upload.bytes);
+
+    let existing = sqlx::query_as!(
+        StoredMedia,
+        r#"
+            select
+                kind,
+                content_type,
+                storage_key,
+                url,
+                width as "width: i64",
+                height as "height: i64",
+                blurhash,
+                thumbnails as "thumbnails!: SqlJson<Vec<Thumbnail>>"
+            from message_media
+            where content_hash = $1
+            limit 1
+        "#,
+        content_hash
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    let stored = match existing {
+        Some(stored) => stored,
+        None => store(ctx, &content_hash, upload.bytes, sniffed).await?,
+    };
+
+    let id = Uuid::new_v4();
+
+    let media = sqlx::query_as!(
+        MessageMedia,
+        r#"
+            insert into message_media (
+                id, owner_id, kind, content_type, storage_key, url, alt_text,
+                width, height, blurhash, thumbnails, content_hash
+            )
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            returning
+                id as "id!: Uuid",
+                message_id as "message_id: Uuid",
+                kind as "kind!",
+                content_type as "content_type!",
+                url as "url!",
+                alt_text as "alt_text!",
+                width as "width: i64",
+                height as "height: i64",
+                blurhash,
+                thumbnails as "thumbnails!: SqlJson<Vec<Thumbnail>>"
+        "#,
+        id,
+        owner_id,
+        stored.kind,
+        stored.content_type,
+        stored.storage_key,
+        stored.url,
+        upload.description,
+        stored.width,
+        stored.height,
+        stored.blurhash,
+        stored.thumbnails,
+        content_hash
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(media)
+}
+
+/// Processes a new upload and stores the resulting files under keys derived from its hash.
+async fn store(ctx: &ApiContext, content_hash: &str, bytes: Bytes, sniffed: Sniffed) -> Result<StoredMedia> {
+    match sniffed {
+        Sniffed::Image(format) => {
+            let image = processing::process_image(bytes, format, &processing::THUMBNAIL_SIZES).await?;
+
+            let mut thumbnails = Vec::with_capacity(image.thumbnails.len());
+            for thumbnail in image.thumbnails {
+                let key = format!("attachments/{}-{}x{}.webp", content_hash, thumbnail.width, thumbnail.height);
+                ctx.storage.put(&key, "image/webp", thumbnail.bytes).await?;
+
+                thumbnails.push(Thumbnail {
+                    width: thumbnail.width,
+                    height: thumbnail.height,
+                    url: ctx.storage.url(&key),
+                });
+            }
+
+            let key = format!("attachments/{}.{}", content_hash, image.extension);
+            ctx.storage.put(&key, image.content_type, image.bytes).await?;
+
+            Ok(StoredMedia {
+                kind: "image".to_string(),
+                content_type: image.content_type.to_string(),
+                url: ctx.storage.url(&key),
+                storage_key: key,
+                width: Some(image.width as i64),
+                height: Some(image.height as i64),
+                blurhash: Some(image.blurhash),
+                thumbnails: SqlJson(thumbnails),
+            })
+        }
+        // Videos are stored as uploaded, we don't transcode them (yet).
+        Sniffed::Video { content_type, extension } => {
+            // WebM videos are stored with their tags, see `media::metadata`.
+            let bytes = match extension {
+                "mp4" => metadata::strip_mp4(bytes.to_vec())
+                    .map(Bytes::from)
+                    .ok_or_else(|| Error::unprocessable_entity([("file", "could not be decoded")]))?,
+                _ => bytes,
+            };
+
+            let key = format!("attachments/{}.{}", content_hash, extension);
+            ctx.storage.put(&key, content_type, bytes).await?;
+
+            Ok(StoredMedia {
+                kind: "video".to_string(),
+                content_type: content_type.to_string(),
+                url: ctx.storage.url(&key),
+                storage_key: key,
+                width: None,
+                height: None,
+                blurhash: None,
+                thumbnails: SqlJson(Vec::new()),
+            })
+        }
+    }
+}
+
+/// Updates the alt text of an attachment.
+pub async fn update_media(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateMedia>
+) -> Result<Json<MessageMedia>> {
+    if req.description.chars().count() > MAX_ALT_TEXT_LENGTH {
+        return Err(Error::unprocessable_entity([("description", "is too long")]));
+    }
+
+    let media = sqlx::query_as!(
+        MessageMedia,
+        r#"
+            update message_media
+            set alt_text = $1
+            where id = $2 and owner_id = $3
+            returning
+                id as "id!: Uuid",
+                message_id as "message_id: Uuid",
+                kind as "kind!",
+                content_type as "content_type!",
+                url as "url!",
+                alt_text as "alt_text!",
+                width as "width: i64",
+                height as "height: i64",
+                blurhash,
+                thumbnails as "thumbnails!: SqlJson<Vec<Thumbnail>>"
+        "#,
+        req.description,
+        id,
+        auth_user.user_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    Ok(Json(media))
+}
+
+/// Attaches previously uploaded media to a newly created message, in the given order.
+///
+/// Every attachment must belong to `owner_id` and not be attached to another message yet.
+pub async fn attach(
+    tx: &mut Transaction<'_, Sqlite>,
+    owner_id: Uuid,
+    message_id: Uuid,
+    media_ids: &[Uuid],
+) -> Result<()> {
+    if media_ids.len() > MAX_ATTACHMENTS {
+        return Err(Error::unprocessable_entity([(
+            "media_ids",
+            format!("cannot attach more than {} files", MAX_ATTACHMENTS),
+        )]));
+    }
+
+    for (position, media_id) in media_ids.iter().enumerate() {
+        let position = position as i64;
+
+        let attached = sqlx::query!(
+            r#"
+                update message_media
+                set message_id = $1, position = $2
+                where id = $3 and owner_id = $4 and message_id is null
+            "#,
+            message_id,
+            position,
+            media_id,
+            owner_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if attached == 0 {
+            return Err(Error::unprocessable_entity([(
+                "media_ids",
+                format!("{} does not exist or is already attached", media_id),
+            )]));
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads the attachments of several messages at once, keyed by message id.
+pub async fn load(db: &SqlitePool, message_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<MessageMedia>>> {
+    let mut media_by_message: HashMap<Uuid, Vec<MessageMedia>> = HashMap::new();
+
+    if message_ids.is_empty() {
+        return Ok(media_by_message);
+    }
+
+    let mut query = QueryBuilder::new(
+        r#"
+            select id, message_id, kind, content_type, url, alt_text, width, height, blurhash, thumbnails
+            from message_media
+            where message_id in (
+        "#,
+    );
+
+    let mut ids = query.separated(", ");
+    for id in message_ids {
+        ids.push_bind(*id);
+    }
+    query.push(") order by position");
+
+    let media = query
+        .build_query_as::<MessageMedia>()
+        .fetch_all(db)
+        .await?;
+
+    for media in media {
+        if let Some(message_id) = media.message_id {
+            media_by_message.entry(message_id).or_default().push(media);
+        }
+    }
+
+    Ok(media_by_message)
+}
+
+async fn read_upload(mut multipart: Multipart) -> Result<Upload> {
+    let mut file = None;
+    let mut description = String::new();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| Error::unprocessable_entity([("file", "invalid multipart body")]))?
+    {
+        match field.name() {
+            Some("file") => {
+                let bytes = field
+                    .bytes()
+                    .await
+                    .map_err(|_| Error::unprocessable_entity([("file", "failed to read upload")]))?;
+
+                file = Some(bytes);
+            }
+            Some("description") => {
+                description = field
+                    .text()
+                    .await
+                    .map_err(|_| Error::unprocessable_entity([("description", "failed to read field")]))?;
+            }
+            _ => (),
+        }
+    }
+
+    let bytes = file.ok_or_else(|| Error::unprocessable_entity([("file", "is missing")]))?;
+
+    if description.chars().count() > MAX_ALT_TEXT_LENGTH {
+        return Err(Error::unprocessable_entity([("description", "is too long")]));
+    }
+
+    Ok(Upload {
+        bytes,
+        description,
+    })
+}
@@ -0,0 +1,189 @@
+// Strips metadata from the formats that are stored without being re-encoded.
+// Images other than animated GIFs are re-encoded to WebP, which drops their metadata already.
+// WebM videos are stored as they are, their tags are not stripped.
+
+// Boxes holding user data like titles, GPS coordinates or the recording device.
+const MP4_METADATA_BOXES: [&[u8; 4]; 2] = [b"udta", b"meta"];
+// Boxes holding other boxes that may contain metadata.
+const MP4_CONTAINER_BOXES: [&[u8; 4]; 2] = [b"moov", b"trak"];
+// The `uuid` box XMP metadata is stored in.
+const MP4_XMP_UUID: [u8; 16] = [
+    0xBE, 0x7A, 0xCF, 0xCB, 0x97, 0xA9, 0x42, 0xE8, 0x9C, 0x71, 0x99, 0x94, 0x91, 0xE3, 0xAF, 0xAC,
+];
+
+/// Blanks the metadata boxes of an MP4 file, returns `None` if the file is malformed.
+///
+/// Removing boxes would shift the media data and invalidate the chunk offsets pointing into it,
+/// so metadata boxes are turned into `free` boxes of the same size, filled with zeros.
+pub fn strip_mp4(mut bytes: Vec<u8>) -> Option<Vec<u8>> {
+    blank_mp4_boxes(&mut bytes, true)?;
+    Some(bytes)
+}
+
+fn blank_mp4_boxes(data: &mut [u8], top_level: bool) -> Option<()> {
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let rest = &data[offset..];
+        if rest.len() < 8 {
+            return None;
+        }
+
+        let (size, header_len) = match u32::from_be_bytes(rest[0..4].try_into().ok()?) {
+            // The box extends to the end of the file.
+            0 => (rest.len(), 8),
+            1 => {
+                let size = u64::from_be_bytes(rest.get(8..16)?.try_into().ok()?);
+                (usize::try_from(size).ok()?, 16)
+            }
+            size => (size as usize, 8),
+        };
+
+        if size < header_len || size > rest.len() {
+            return None;
+        }
+
+        let box_type: [u8; 4] = rest[4..8].try_into().ok()?;
+        let is_xmp = top_level && &box_type == b"uuid" && rest.get(8..24) == Some(&MP4_XMP_UUID[..]);
+        let payload = &mut data[offset + header_len..offset + size];
+
+        if MP4_METADATA_BOXES.contains(&&box_type) || is_xmp {
+            payload.fill(0);
+            data[offset + 4..offset + 8].copy_from_slice(b"free");
+        } else if MP4_CONTAINER_BOXES.contains(&&box_type) {
+            blank_mp4_boxes(payload, false)?;
+        }
+
+        offset += size;
+    }
+
+    Some(())
+}
+
+/// Drops the comment and application extensions of a GIF, returns `None` if the file is malformed.
+///
+/// Only the `NETSCAPE2.0` application extension is kept, since it holds the loop count.
+pub fn strip_gif(bytes: &[u8]) -> Option<Vec<u8>> {
+    // Header and logical screen descriptor.
+    let screen = bytes.get(..13)?;
+    let mut stripped = Vec::with_capacity(bytes.len());
+    stripped.extend_from_slice(screen);
+
+    let mut offset = 13 + color_table_len(screen[10]);
+    stripped.extend_from_slice(bytes.get(13..offset)?);
+
+    loop {
+        let start = offset;
+
+        match *bytes.get(offset)? {
+            // Extension: introducer, label and data sub-blocks.
+            0x21 => {
+                let label = *bytes.get(offset + 1)?;
+                offset = skip_sub_blocks(bytes, offset + 2)?;
+
+                let is_loop_count = label == 0xFF && bytes.get(start + 3..start + 14) == Some(b"NETSCAPE2.0");
+                let is_metadata = label == 0xFE || (label == 0xFF && !is_loop_count);
+
+                if !is_metadata {
+                    stripped.extend_from_slice(&bytes[start..offset]);
+                }
+            }
+            // Image: descriptor, local color table, LZW code size and data sub-blocks.
+            0x2C => {
+                let descriptor = bytes.get(offset..offset + 10)?;
+                offset = skip_sub_blocks(bytes, offset + 10 + color_table_len(descriptor[9]) + 1)?;
+                stripped.extend_from_slice(&bytes[start..offset]);
+            }
+            // Trailer, anything after it is dropped too.
+            0x3B => {
+                stripped.push(0x3B);
+                return Some(stripped);
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn color_table_len(flags: u8) -> usize {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    }
+}
+
+/// Returns the offset right after the sub-blocks starting at `offset`.
+fn skip_sub_blocks(bytes: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *bytes.get(offset)? as usize;
+        offset += 1 + len;
+
+        if len == 0 {
+            return Some(offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut bytes = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(box_type);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn strip_mp4_blanks_metadata_without_moving_anything() {
+        let udta = mp4_box(b"udta", b"\xa9xyz+48.85+002.35/");
+        let trak = mp4_box(b"trak", &mp4_box(b"tkhd", &[1; 12]));
+        let moov = mp4_box(b"moov", &[mp4_box(b"mvhd", &[2; 12]), udta, trak.clone()].concat());
+        let file = [mp4_box(b"ftyp", b"isom\0\0\0\0"), moov, mp4_box(b"mdat", &[3; 16])].concat();
+
+        let stripped = strip_mp4(file.clone()).unwrap();
+
+        assert_eq!(stripped.len(), file.len());
+        assert!(!stripped.windows(4).any(|w| w == b"udta"));
+        assert!(!stripped.windows(6).any(|w| w == b"+48.85"));
+        assert!(stripped.windows(trak.len()).any(|w| w == trak));
+        assert!(stripped.ends_with(&mp4_box(b"mdat", &[3; 16])));
+    }
+
+    #[test]
+    fn strip_mp4_rejects_truncated_boxes() {
+        let mut file = mp4_box(b"moov", &[0; 16]);
+        file.truncate(12);
+
+        assert!(strip_mp4(file).is_none());
+    }
+
+    fn gif(blocks: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = b"GIF89a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+        // Global color table of 2 colors.
+        bytes.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        for block in blocks {
+            bytes.extend_from_slice(block);
+        }
+        bytes.push(0x3B);
+        bytes
+    }
+
+    const LOOP: &[u8] = b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00";
+    const COMMENT: &[u8] = b"\x21\xFE\x06secret\x00";
+    const XMP: &[u8] = b"\x21\xFF\x0BXMP DataXMP\x04<x:>\x00";
+    const IMAGE: &[u8] = b"\x2C\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00";
+
+    #[test]
+    fn strip_gif_keeps_frames_and_loop_count() {
+        let stripped = strip_gif(&gif(&[LOOP, COMMENT, IMAGE, XMP, IMAGE])).unwrap();
+
+        assert_eq!(stripped, gif(&[LOOP, IMAGE, IMAGE]));
+    }
+
+    #[test]
+    fn strip_gif_rejects_unknown_blocks() {
+        assert!(strip_gif(&gif(&[b"\x42"])).is_none());
+    }
+}
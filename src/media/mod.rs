@@ -0,0 +1,4 @@
+pub mod attachments;
+pub mod processing;
+pub mod metadata;
+pub mod routes;
\ No newline at end of file
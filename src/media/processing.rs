@@ -0,0 +1,196 @@
+use crate::{Result, Error, media::metadata};
+use anyhow::Context;
+use bytes::Bytes;
+use image::{DynamicImage, ImageFormat, io::{Limits, Reader}};
+use image::codecs::gif::GifDecoder;
+use image::AnimationDecoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+
+// Larger images are rejected before being decoded, to avoid decompression bombs.
+const MAX_DIMENSION: u32 = 8192;
+
+// Quality of the lossy WebP encoding, from 0 to 100.
+const WEBP_QUALITY: f32 = 80.0;
+
+// Major brands of the `ftyp` box accepted as MP4 videos.
+const MP4_BRANDS: [&[u8]; 11] = [
+    b"isom", b"iso2", b"iso3", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"dash",
+];
+
+// The longest side of the thumbnails generated for attachments,
+// only the ones smaller than the original are kept.
+pub const THUMBNAIL_SIZES: [u32; 3] = [160, 480, 1080];
+
+/// What an upload really is, based on its first bytes rather than the `Content-Type` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sniffed {
+    Image(ImageFormat),
+    Video { content_type: &'static str, extension: &'static str },
+}
+
+pub struct ProcessedImage {
+    pub content_type: &'static str,
+    pub extension: &'static str,
+    pub bytes: Bytes,
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    pub thumbnails: Vec<EncodedThumbnail>,
+}
+
+pub struct EncodedThumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub bytes: Bytes,
+}
+
+/// A thumbnail as stored alongside an attachment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub url: String,
+}
+
+/// Only PNG, JPEG, GIF and WebP images, and MP4 and WebM videos are recognized.
+pub fn sniff(bytes: &[u8]) -> Option<Sniffed> {
+    if let Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP)) = image::guess_format(bytes) {
+        return Some(Sniffed::Image(format));
+    }
+
+    // HEIC, AVIF and QuickTime files start with `ftyp` too, only MP4 major brands are videos we can serve.
+    if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" && MP4_BRANDS.contains(&&bytes[8..12]) {
+        Some(Sniffed::Video { content_type: "video/mp4", extension: "mp4" })
+    } else if bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        Some(Sniffed::Video { content_type: "video/webm", extension: "webm" })
+    } else {
+        None
+    }
+}
+
+/// Hex encoded SHA-256 of the content, used to store identical uploads only once.
+pub fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Re-encodes an image to WebP, which drops any metadata like EXIF GPS coordinates,
+/// and generates its blurhash and a thumbnail for each of `thumbnail_sizes`.
+///
+/// Animated GIFs aren't re-encoded since that would drop the animation, only their comment
+/// and application extensions are removed, and their thumbnails are converted.
+///
+/// Everything here is CPU bound, so it runs on a blocking thread.
+pub async fn process_image(
+    bytes: Bytes,
+    format: ImageFormat,
+    thumbnail_sizes: &'static [u32],
+) -> Result<ProcessedImage> {
+    tokio::task::spawn_blocking(move || process_image_blocking(bytes, format, thumbnail_sizes))
+        .await
+        .context("panic in processing image")?
+}
+
+fn process_image_blocking(
+    bytes: Bytes,
+    format: ImageFormat,
+    thumbnail_sizes: &[u32],
+) -> Result<ProcessedImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+
+    let mut reader = Reader::with_format(Cursor::new(&bytes), format);
+    reader.limits(limits);
+
+    let image = reader
+        .decode()
+        .map_err(|_| Error::unprocessable_entity([("file", "could not be decoded")]))?;
+
+    // The orientation is part of the metadata we are about to strip, so apply it to the pixels first.
+    let image = match orientation(&bytes) {
+        Some(orientation) => apply_orientation(image, orientation),
+        None => image,
+    };
+
+    let (width, height) = (image.width(), image.height());
+
+    // The blurhash only keeps a handful of components, a tiny version of the image is plenty.
+    let preview = image.thumbnail(32, 32).to_rgba8();
+    let blurhash = blurhash::encode(4, 3, preview.width(), preview.height(), preview.as_raw())
+        .map_err(|e| anyhow::anyhow!("failed to compute blurhash: {:?}", e))?;
+
+    let thumbnails = thumbnail_sizes
+        .iter()
+        .filter(|&&size| size < width.max(height))
+        .map(|&size| {
+            let thumbnail = image.thumbnail(size, size);
+            EncodedThumbnail {
+                width: thumbnail.width(),
+                height: thumbnail.height(),
+                bytes: encode_webp(&thumbnail),
+            }
+        })
+        .collect();
+
+    let (content_type, extension, bytes) = if format == ImageFormat::Gif && is_animated(&bytes) {
+        let stripped = metadata::strip_gif(&bytes)
+            .ok_or_else(|| Error::unprocessable_entity([("file", "could not be decoded")]))?;
+
+        ("image/gif", "gif", Bytes::from(stripped))
+    } else {
+        ("image/webp", "webp", encode_webp(&image))
+    };
+
+    Ok(ProcessedImage {
+        content_type,
+        extension,
+        bytes,
+        width,
+        height,
+        blurhash,
+        thumbnails,
+    })
+}
+
+fn encode_webp(image: &DynamicImage) -> Bytes {
+    let rgba = image.to_rgba8();
+    let webp = webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height()).encode(WEBP_QUALITY);
+
+    Bytes::copy_from_slice(&webp)
+}
+
+fn is_animated(bytes: &[u8]) -> bool {
+    GifDecoder::new(Cursor::new(bytes))
+        .map(|decoder| decoder.into_frames().take(2).count() > 1)
+        .unwrap_or(false)
+}
+
+/// Reads the EXIF orientation tag, if any.
+fn orientation(bytes: &[u8]) -> Option<u32> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()?;
+
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+/// See https://magnushoff.com/articles/jpeg-orientation/ for what each value means.
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
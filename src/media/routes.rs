@@ -0,0 +1,27 @@
+use crate::media::attachments;
+use axum::{
+    extract::DefaultBodyLimit,
+    routing::{post, put},
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/media",
+            post(attachments::upload_media)
+                .layer(DefaultBodyLimit::max(attachments::MAX_VIDEO_SIZE + 64 * 1024))
+        )
+        .route(
+            "/api/media/uploads",
+            post(attachments::presign_upload)
+        )
+        .route(
+            "/api/media/uploads/:id/complete",
+            post(attachments::complete_upload)
+        )
+        .route(
+            "/api/media/:id",
+            put(attachments::update_media)
+        )
+}
\ No newline at end of file
@@ -0,0 +1,176 @@
+use crate::Result;
+use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, SqlitePool};
+use std::collections::{HashMap, HashSet};
+
+/// Related data embedded in message responses on request, e.g. `?expand=author,counts`.
+///
+/// Nothing is embedded by default, so existing clients get the same responses as before.
+/// Can be extracted with `Query<Expand>` next to other query parameters.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(try_from = "ExpandQuery")]
+pub struct Expand {
+    pub author: bool,
+    pub counts: bool,
+    pub liked_by_me: bool,
+    pub bookmarked_by_me: bool,
+}
+
+#[derive(Deserialize)]
+struct ExpandQuery {
+    #[serde(default)]
+    expand: String,
+}
+
+impl TryFrom<ExpandQuery> for Expand {
+    type Error = String;
+
+    fn try_from(query: ExpandQuery) -> std::result::Result<Self, Self::Error> {
+        let mut expand = Expand::default();
+
+        for name in query.expand.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "author" => expand.author = true,
+                "counts" => expand.counts = true,
+                "liked_by_me" => expand.liked_by_me = true,
+                "bookmarked_by_me" => expand.bookmarked_by_me = true,
+                _ => return Err(format!("unknown expansion `{}`", name)),
+            }
+        }
+
+        Ok(expand)
+    }
+}
+
+impl Expand {
+    pub fn is_empty(&self) -> bool {
+        !(self.author || self.counts || self.liked_by_me || self.bookmarked_by_me)
+    }
+}
+
+/// The part of an author's profile needed to render a message.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Author {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Engagement {
+    pub likes: i64,
+    pub replies: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct EngagementRow {
+    message_id: Uuid,
+    likes: i64,
+    replies: i64,
+}
+
+pub async fn authors(db: &SqlitePool, author_ids: &[Uuid]) -> Result<HashMap<Uuid, Author>> {
+    if author_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut query = QueryBuilder::new(
+        "select id, username, display_name, image from user where id in (",
+    );
+
+    let mut ids = query.separated(", ");
+    for id in author_ids {
+        ids.push_bind(*id);
+    }
+    query.push(")");
+
+    let authors = query
+        .build_query_as::<Author>()
+        .fetch_all(db)
+        .await?;
+
+    Ok(authors.into_iter().map(|author| (author.id, author)).collect())
+}
+
+/// Reads the like and reply counters of each message.
+pub async fn engagement(db: &SqlitePool, message_ids: &[Uuid]) -> Result<HashMap<Uuid, Engagement>> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut query = QueryBuilder::new(
+        "select id as message_id, like_count as likes, reply_count as replies from message where id in (",
+    );
+
+    let mut ids = query.separated(", ");
+    for id in message_ids {
+        ids.push_bind(*id);
+    }
+    query.push(")");
+
+    let rows = query
+        .build_query_as::<EngagementRow>()
+        .fetch_all(db)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let engagement = Engagement {
+                likes: row.likes,
+                replies: row.replies,
+            };
+            (row.message_id, engagement)
+        })
+        .collect())
+}
+
+/// Returns which of `message_ids` the user `viewer_id` liked.
+pub async fn liked_by(db: &SqlitePool, viewer_id: Uuid, message_ids: &[Uuid]) -> Result<HashSet<Uuid>> {
+    if message_ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let mut query = QueryBuilder::new(r#"select message_id from "like" where user_id = "#);
+    query.push_bind(viewer_id);
+    query.push(" and message_id in (");
+
+    let mut ids = query.separated(", ");
+    for id in message_ids {
+        ids.push_bind(*id);
+    }
+    query.push(")");
+
+    let liked = query
+        .build_query_as::<(Uuid,)>()
+        .fetch_all(db)
+        .await?;
+
+    Ok(liked.into_iter().map(|(message_id,)| message_id).collect())
+}
+
+/// Returns which of `message_ids` the user `viewer_id` bookmarked.
+pub async fn bookmarked_by(db: &SqlitePool, viewer_id: Uuid, message_ids: &[Uuid]) -> Result<HashSet<Uuid>> {
+    if message_ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let mut query = QueryBuilder::new("select message_id from bookmark where user_id = ");
+    query.push_bind(viewer_id);
+    query.push(" and message_id in (");
+
+    let mut ids = query.separated(", ");
+    for id in message_ids {
+        ids.push_bind(*id);
+    }
+    query.push(")");
+
+    let bookmarked = query
+        .build_query_as::<(Uuid,)>()
+        .fetch_all(db)
+        .await?;
+
+    Ok(bookmarked.into_iter().map(|(message_id,)| message_id).collect())
+}
@@ -0,0 +1,211 @@
+use crate::{
+    Result,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+    message::{
+        messages::{self, Message, MessageRow},
+        expand::Expand,
+    },
+};
+use uuid::Uuid;
+use serde::Serialize;
+use axum::{
+    Json,
+    extract::{Extension, Query},
+};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool, Transaction};
+use std::collections::HashMap;
+
+/// A resolved `@username` in the text of a message.
+///
+/// `start` and `end` are character offsets (not bytes) of the mention, `@` included.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Mention {
+    #[serde(skip)]
+    message_id: Uuid,
+    user_id: Uuid,
+    // The current username, which may differ from the text if the user was renamed since,
+    // or `None` if the account no longer exists.
+    username: Option<String>,
+    start: i64,
+    end: i64,
+}
+
+struct Candidate<'a> {
+    username: &'a str,
+    start: usize,
+    end: usize,
+}
+
+/// Finds the `@username` candidates of a message, they still have to be resolved to users.
+///
+/// Like hashtags, a mention must start a word so that emails like `john@example.com` don't count.
+fn extract(text: &str) -> Vec<Candidate<'_>> {
+    let mut candidates = Vec::new();
+    let mut previous = None;
+    let mut chars = text.char_indices().enumerate().peekable();
+
+    while let Some((start, (byte_start, c))) = chars.next() {
+        let starts_word = previous.is_none_or(|p: char| !(p.is_alphanumeric() || p == '_' || p == '@'));
+        previous = Some(c);
+
+        if c != '@' || !starts_word {
+            continue;
+        }
+
+        let (mut end, mut byte_end) = (start + 1, byte_start + 1);
+        while let Some(&(i, (byte_i, next))) = chars.peek() {
+            if !(next.is_alphanumeric() || next == '_') {
+                break;
+            }
+            (end, byte_end) = (i + 1, byte_i + next.len_utf8());
+            previous = Some(next);
+            chars.next();
+        }
+
+        if byte_end > byte_start + 1 {
+            candidates.push(Candidate {
+                username: &text[byte_start + 1..byte_end],
+                start,
+                end,
+            });
+        }
+    }
+
+    candidates
+}
+
+/// Replaces the mentions of a message with the `@username`s of `text` that match a user.
+///
+/// Usernames are matched case-insensitively, unknown ones are left as plain text.
+/// Returns the users that weren't already mentioned before, so only they get notified on edit.
+pub async fn index(tx: &mut Transaction<'_, Sqlite>, message_id: Uuid, text: &str) -> Result<Vec<Uuid>> {
+    let previous = sqlx::query_scalar!(
+        r#"select user_id as "user_id!: Uuid" from message_mention where message_id = $1"#,
+        message_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query!("delete from message_mention where message_id = $1", message_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut mentioned = Vec::new();
+
+    let mut resolved: HashMap<String, Option<Uuid>> = HashMap::new();
+
+    for candidate in extract(text) {
+        let key = candidate.username.to_lowercase();
+
+        let user_id = match resolved.get(&key) {
+            Some(user_id) => *user_id,
+            None => {
+                let user_id = sqlx::query_scalar!(
+                    r#"select id as "id!: Uuid" from user where username = $1 collate nocase"#,
+                    candidate.username
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                resolved.insert(key, user_id);
+                user_id
+            }
+        };
+
+        let Some(user_id) = user_id else {
+            continue;
+        };
+
+        let (start, end) = (candidate.start as i64, candidate.end as i64);
+
+        sqlx::query!(
+            r#"
+                insert into message_mention (message_id, user_id, start_offset, end_offset)
+                values ($1, $2, $3, $4)
+            "#,
+            message_id,
+            user_id,
+            start,
+            end
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if !previous.contains(&user_id) && !mentioned.contains(&user_id) {
+            mentioned.push(user_id);
+        }
+    }
+
+    Ok(mentioned)
+}
+
+/// Loads the mentions of several messages at once, keyed by message id.
+pub async fn load(db: &SqlitePool, message_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<Mention>>> {
+    let mut mentions_by_message: HashMap<Uuid, Vec<Mention>> = HashMap::new();
+
+    if message_ids.is_empty() {
+        return Ok(mentions_by_message);
+    }
+
+    let mut query = QueryBuilder::new(
+        r#"
+            select message_mention.message_id, message_mention.user_id, user.username,
+                message_mention.start_offset as start, message_mention.end_offset as "end"
+            from message_mention
+            left join user on user.id = message_mention.user_id
+            where message_mention.message_id in (
+        "#,
+    );
+
+    let mut ids = query.separated(", ");
+    for id in message_ids {
+        ids.push_bind(*id);
+    }
+    query.push(") order by message_mention.start_offset");
+
+    let mentions = query
+        .build_query_as::<Mention>()
+        .fetch_all(db)
+        .await?;
+
+    for mention in mentions {
+        mentions_by_message.entry(mention.message_id).or_default().push(mention);
+    }
+
+    Ok(mentions_by_message)
+}
+
+/// Lists the messages mentioning the current user, most recent first.
+pub async fn get_mentions(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Query(page): Query<Pagination>,
+    Query(expansions): Query<Expand>
+) -> Result<Json<Vec<Message>>> {
+    let rows = sqlx::query_as::<_, MessageRow>(&format!(
+        r#"
+            select {}
+            from message
+            where message.deleted_at is null and exists (
+                select 1 from message_mention
+                where message_mention.message_id = message.id and message_mention.user_id = $1
+            )
+            order by message.created_at desc, message.rowid desc
+            limit $2 offset $3
+        "#,
+        messages::MESSAGE_COLUMNS
+    ))
+    .bind(auth_user.user_id)
+    .bind(page.limit())
+    .bind(page.offset())
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let messages = messages::hydrate(&ctx.db, rows).await?;
+
+    Ok(Json(messages::embed(&ctx.db, messages, expansions, Some(auth_user.user_id)).await?))
+}
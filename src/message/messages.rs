@@ -4,50 +4,205 @@ use crate::{
     ResultExt,
     router::{
         server::ApiContext,
-        extractor::AuthUser,
-    }
+        extractor::{AuthUser, RequestMeta},
+        pagination::Pagination,
+    },
+    audit::events::{self, Event},
+    media::attachments::{self, MessageMedia},
+    message::{
+        reposts::{self, Repost},
+        mentions::{self, Mention},
+        expand::{self, Author, Expand},
+    },
+    tag::tags,
+    notification::notifications::{self, Kind},
+    stream::bus::{Audience, EventKind},
+    webhook::{delivery, webhooks},
+    federation::outbox,
+    counter::counters::{self, Counter},
 };
 use uuid::Uuid;
-use time::PrimitiveDateTime;
+use time::{OffsetDateTime, PrimitiveDateTime};
 use serde::{Deserialize, Serialize};
 use axum::{
     Json,
-    extract::{Path, Extension},
+    extract::{Path, Extension, Query},
 };
+use sqlx::{QueryBuilder, SqlitePool};
+use std::collections::HashMap;
 
-#[derive(Debug, Serialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Message {
     id: Uuid,
     author_id: Uuid,
     created_at: PrimitiveDateTime,
     message: String,
     message_parent_id: Option<Uuid>,
+    // Set once the message has been edited, previous versions are in `GET /message/:id/history`.
+    edited_at: Option<PrimitiveDateTime>,
+    quoted_message_id: Option<Uuid>,
+    // Only embedded one level deep, a quote of a quote only carries the inner `quoted_message_id`.
+    quoted_message: Option<Box<Message>>,
+    media: Vec<MessageMedia>,
+    mentions: Vec<Mention>,
+    repost_count: i64,
+    quote_count: i64,
+    // Deleted messages are kept as tombstones so that replies still have a parent to point to,
+    // their text and attachments are left out.
+    deleted_at: Option<PrimitiveDateTime>,
+    // Set when the message shows up in a timeline because someone reposted it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reposted_by: Option<Repost>,
+    // The fields below are only set when asked for with `?expand=`, see `embed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<Author>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    like_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    liked_by_me: Option<bool>,
+    // Bookmarks are private, this is only ever about the viewer's own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bookmarked_by_me: Option<bool>,
+}
+
+// Most messages hydrated with one query per relation, see `hydrate`.
+const HYDRATE_BATCH_SIZE: usize = 500;
+
+/// The columns of a `MessageRow`, for queries that select messages from other modules.
+pub const MESSAGE_COLUMNS: &str = "message.id, message.author_id, message.created_at, message.message, \
+    message.message_parent_id, message.edited_at, message.quoted_message_id, message.deleted_at";
+
+impl Message {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// A `message` row, before its related data is loaded by `hydrate`.
+#[derive(Debug, sqlx::FromRow)]
+pub struct MessageRow {
+    id: Uuid,
+    author_id: Uuid,
+    created_at: PrimitiveDateTime,
+    message: String,
+    message_parent_id: Option<Uuid>,
+    edited_at: Option<PrimitiveDateTime>,
+    quoted_message_id: Option<Uuid>,
+    deleted_at: Option<PrimitiveDateTime>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MessageRequest {
     message: String,
+    // Ids of attachments uploaded through `POST /api/media`.
+    #[serde(default)]
+    media_ids: Vec<Uuid>,
 }
 
+impl MessageRequest {
+    pub fn new(message: String, media_ids: Vec<Uuid>) -> Self {
+        Self { message, media_ids }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EditMessageRequest {
+    message: String,
+}
+
+/// A previous version of an edited message.
+#[derive(Debug, Serialize)]
+pub struct MessageRevision {
+    id: Uuid,
+    message: String,
+    // When this version was posted, either as the original message or by an earlier edit.
+    created_at: PrimitiveDateTime,
+    replaced_at: PrimitiveDateTime,
+}
+
+/// Lists messages and reposts, most recent first.
+///
+/// A reposted message appears again at the time of the repost with `reposted_by` set.
 pub async fn get_messages(
-    ctx: Extension<ApiContext>
+    auth_user: Option<AuthUser>,
+    ctx: Extension<ApiContext>,
+    Query(page): Query<Pagination>,
+    Query(expansions): Query<Expand>
 ) -> Result<Json<Vec<Message>>> {
-    let messages = sqlx::query_as!(
-        Message,
+    let (limit, offset) = (page.limit(), page.offset());
+
+    let entries = sqlx::query!(
         r#"
-            select 
+            select
                 id as "id!: Uuid",
                 author_id as "author_id!: Uuid",
                 created_at as "created_at!: PrimitiveDateTime",
-                message,
-                message_parent_id as "message_parent_id!: Option<Uuid>"
+                message as "message!",
+                message_parent_id as "message_parent_id: Uuid",
+                edited_at as "edited_at: PrimitiveDateTime",
+                quoted_message_id as "quoted_message_id: Uuid",
+                deleted_at as "deleted_at: PrimitiveDateTime",
+                null as "reposted_by?: Uuid",
+                created_at as "sort_at!: PrimitiveDateTime"
             from message
-        "#
+            where deleted_at is null
+            union all
+            select
+                message.id,
+                message.author_id,
+                message.created_at,
+                message.message,
+                message.message_parent_id,
+                message.edited_at,
+                message.quoted_message_id,
+                message.deleted_at,
+                repost.user_id,
+                repost.created_at
+            from repost
+            inner join message on message.id = repost.message_id
+            where message.deleted_at is null
+            order by 10 desc
+            limit $1 offset $2
+        "#,
+        limit,
+        offset
     )
     .fetch_all(&ctx.db)
     .await?;
 
-    Ok(Json(messages))
+    let mut rows = Vec::with_capacity(entries.len());
+    let mut reposters = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        reposters.push(entry.reposted_by.map(|user_id| Repost {
+            user_id,
+            created_at: entry.sort_at,
+        }));
+
+        rows.push(MessageRow {
+            id: entry.id,
+            author_id: entry.author_id,
+            created_at: entry.created_at,
+            message: entry.message,
+            message_parent_id: entry.message_parent_id,
+            edited_at: entry.edited_at,
+            quoted_message_id: entry.quoted_message_id,
+            deleted_at: entry.deleted_at,
+        });
+    }
+
+    let messages = hydrate(&ctx.db, rows)
+        .await?
+        .into_iter()
+        .zip(reposters)
+        .map(|(message, reposted_by)| Message { reposted_by, ..message })
+        .collect();
+
+    let viewer_id = auth_user.map(|auth_user| auth_user.user_id);
+
+    Ok(Json(embed(&ctx.db, messages, expansions, viewer_id).await?))
 }
 
 pub async fn create_message(
@@ -55,53 +210,29 @@ pub async fn create_message(
     ctx: Extension<ApiContext>,
     Json(input): Json<MessageRequest>
 ) -> Result<Json<Message>> {
-    let message_id = Uuid::new_v4();
+    let message = insert_message(&ctx, auth_user.user_id, input, None, None).await?;
 
-    let message = sqlx::query!(
-        r#"
-            insert into message (id, author_id, message)
-            values ($1, $2, $3)
-            returning
-                id as "id!: Uuid",
-                author_id as "author_id!: Uuid",
-                created_at as "created_at!: PrimitiveDateTime",
-                message as "message!",
-                message_parent_id as "message_parent_id!: Option<Uuid>"
-        "#,
-        message_id,
-        auth_user.user_id,
-        input.message
-    )
-    .fetch_one(&ctx.db)
-    .await
-    .on_constraint("message", |_| {
-        Error::unprocessable_entity([("message", format!("duplicate message id"))])
-    })?;
-    
-    Ok(Json(
-        Message { 
-            id: message.id,
-            author_id: message.author_id,
-            created_at: message.created_at,
-            message: message.message,
-            message_parent_id: message.message_parent_id 
-        }
-    ))
+    Ok(Json(message))
 }
 
 pub async fn get_message(
-    _: AuthUser,
+    auth_user: AuthUser,
     ctx: Extension<ApiContext>,
-    Path(id): Path<Uuid>
+    Path(id): Path<Uuid>,
+    Query(expansions): Query<Expand>
 ) -> Result<Json<Message>> {
-    let message = sqlx::query!(
+    let row = sqlx::query_as!(
+        MessageRow,
         r#"
             select 
                 id as "id!: Uuid",
                 author_id as "author_id!: Uuid",
                 created_at as "created_at!: PrimitiveDateTime",
                 message as "message!",
-                message_parent_id as "message_parent_id!: Option<Uuid>"
+                message_parent_id as "message_parent_id!: Option<Uuid>",
+                edited_at as "edited_at: PrimitiveDateTime",
+                quoted_message_id as "quoted_message_id: Uuid",
+                deleted_at as "deleted_at: PrimitiveDateTime"
             from message
             where message.id = $1
         "#,
@@ -111,34 +242,222 @@ pub async fn get_message(
     .await?
     .ok_or(Error::NotFound)?;
 
-    Ok(Json(
-        Message { 
-            id: message.id,
-            author_id: message.author_id,
-            created_at: message.created_at,
-            message: message.message,
-            message_parent_id: message.message_parent_id 
-        }
-    ))
+    let message = hydrate_one(&ctx.db, row).await?;
+
+    let mut messages = embed(&ctx.db, vec![message], expansions, Some(auth_user.user_id)).await?;
+
+    Ok(Json(messages.pop().expect("BUG: embed should return one message per message")))
 }
 
+/// Soft-deletes a message, leaving a tombstone in its place.
+///
+/// Its content is purged for good by `message::purge` once the retention period is over.
 pub async fn delete_message(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
+    meta: RequestMeta,
     Path(id): Path<Uuid>
 ) -> Result<()> {
+    let mut tx = ctx.db.begin().await?;
+
+    let message = sqlx::query!(
+        r#"
+            select
+                author_id as "author_id!: Uuid",
+                message_parent_id as "message_parent_id: Uuid"
+            from message
+            where id = $1 and deleted_at is null
+        "#,
+        id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    if message.author_id != auth_user.user_id {
+        return Err(Error::Forbidden);
+    }
+
+    sqlx::query!(
+        "update message set deleted_at = current_timestamp where id = $1",
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    counters::add(&mut tx, Counter::Messages, message.author_id, -1).await?;
+
+    if let Some(parent_id) = message.message_parent_id {
+        counters::add(&mut tx, Counter::Replies, parent_id, -1).await?;
+    }
+
+    events::record(
+        &mut tx,
+        &meta,
+        Event::MessageDeleted { message_id: id },
+        Some(auth_user.user_id),
+        Some(auth_user.user_id),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    ctx.events.publish(EventKind::Delete, Audience::Everyone, serde_json::json!({ "id": id }));
+
+    if let Err(e) = outbox::publish_delete(&ctx, auth_user.user_id, id).await {
+        log::error!("failed to federate the deletion of message {}: {:?}", id, e);
+    }
+
+    Ok(())
+}
+
+/// Replaces the text of a message, keeping the previous version in its history.
+///
+/// Only the author can edit a message, and only within `Config::message_edit_window_minutes`
+/// of posting it.
+pub async fn edit_message(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Json(input): Json<EditMessageRequest>
+) -> Result<Json<Message>> {
+    let mut tx = ctx.db.begin().await?;
+
+    let current = sqlx::query_as!(
+        MessageRow,
+        r#"
+            select
+                id as "id!: Uuid",
+                author_id as "author_id!: Uuid",
+                created_at as "created_at!: PrimitiveDateTime",
+                message as "message!",
+                message_parent_id as "message_parent_id!: Option<Uuid>",
+                edited_at as "edited_at: PrimitiveDateTime",
+                quoted_message_id as "quoted_message_id: Uuid",
+                deleted_at as "deleted_at: PrimitiveDateTime"
+            from message
+            where message.id = $1 and deleted_at is null
+        "#,
+        id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    if current.author_id != auth_user.user_id {
+        return Err(Error::Forbidden);
+    }
+
+    let edit_window = time::Duration::minutes(ctx.config.message_edit_window_minutes);
+
+    if OffsetDateTime::now_utc() - current.created_at.assume_utc() > edit_window {
+        return Err(Error::unprocessable_entity([(
+            "message",
+            format!("can only be edited within {} minutes of posting", ctx.config.message_edit_window_minutes),
+        )]));
+    }
+
+    // Saving the same text again would only add a duplicate revision.
+    if input.message == current.message {
+        return Ok(Json(hydrate_one(&ctx.db, current).await?));
+    }
+
+    let revision_id = Uuid::new_v4();
+    let version_created_at = current.edited_at.unwrap_or(current.created_at);
+
     sqlx::query!(
         r#"
-            delete from message
-            where message.id = $1 and author_id = $2
+            insert into message_revision (id, message_id, message, created_at)
+            values ($1, $2, $3, $4)
         "#,
+        revision_id,
         id,
-        auth_user.user_id
+        current.message,
+        version_created_at
     )
-    .fetch_optional(&ctx.db)
+    .execute(&mut tx)
     .await?;
 
-    Ok(())
+    let row = sqlx::query_as!(
+        MessageRow,
+        r#"
+            update message
+            set message = $1, edited_at = current_timestamp
+            where id = $2
+            returning
+                id as "id!: Uuid",
+                author_id as "author_id!: Uuid",
+                created_at as "created_at!: PrimitiveDateTime",
+                message as "message!",
+                message_parent_id as "message_parent_id!: Option<Uuid>",
+                edited_at as "edited_at: PrimitiveDateTime",
+                quoted_message_id as "quoted_message_id: Uuid",
+                deleted_at as "deleted_at: PrimitiveDateTime"
+        "#,
+        input.message,
+        id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    tags::index(&mut tx, id, &input.message).await?;
+
+    let mut notified = Vec::new();
+
+    for user_id in mentions::index(&mut tx, id, &input.message).await? {
+        notified.extend(notifications::notify(&mut tx, user_id, Kind::Mention, auth_user.user_id, Some(id)).await?);
+    }
+
+    tx.commit().await?;
+
+    notifications::publish(&ctx.events, notified);
+
+    let message = hydrate_one(&ctx.db, row).await?;
+
+    ctx.events.publish(EventKind::Edit, Audience::Everyone, &message);
+
+    if let Err(e) = outbox::publish_update(&ctx, id).await {
+        log::error!("failed to federate the edit of message {}: {:?}", id, e);
+    }
+
+    Ok(Json(message))
+}
+
+/// Lists the previous versions of a message, most recent first.
+pub async fn get_message_history(
+    _: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Vec<MessageRevision>>> {
+    let exists = sqlx::query_scalar!(
+        r#"select exists(select 1 from message where id = $1 and deleted_at is null) as "exists!: bool""#,
+        id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if !exists {
+        return Err(Error::NotFound);
+    }
+
+    let revisions = sqlx::query_as!(
+        MessageRevision,
+        r#"
+            select
+                id as "id!: Uuid",
+                message as "message!",
+                created_at as "created_at!: PrimitiveDateTime",
+                replaced_at as "replaced_at!: PrimitiveDateTime"
+            from message_revision
+            where message_id = $1
+            order by replaced_at desc, rowid desc
+        "#,
+        id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(revisions))
 }
 
 pub async fn create_comment(
@@ -147,37 +466,368 @@ pub async fn create_comment(
     Path(id): Path<Uuid>,
     Json(input): Json<MessageRequest>
 ) -> Result<Json<Message>> {
+    let message = insert_message(&ctx, auth_user.user_id, input, Some(id), None).await?;
+
+    Ok(Json(message))
+}
+
+/// Posts a new message quoting the message `id`.
+///
+/// Deleting the quote post is how a quote is undone.
+pub async fn create_quote(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Json(input): Json<MessageRequest>
+) -> Result<Json<Message>> {
+    let message = insert_message(&ctx, auth_user.user_id, input, None, Some(id)).await?;
+
+    Ok(Json(message))
+}
+
+/// Lists the quote posts of the message `id`, most recent first.
+pub async fn get_quotes(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Query(page): Query<Pagination>,
+    Query(expansions): Query<Expand>
+) -> Result<Json<Vec<Message>>> {
+    let (limit, offset) = (page.limit(), page.offset());
+
+    let rows = sqlx::query_as!(
+        MessageRow,
+        r#"
+            select
+                id as "id!: Uuid",
+                author_id as "author_id!: Uuid",
+                created_at as "created_at!: PrimitiveDateTime",
+                message as "message!",
+                message_parent_id as "message_parent_id!: Option<Uuid>",
+                edited_at as "edited_at: PrimitiveDateTime",
+                quoted_message_id as "quoted_message_id: Uuid",
+                deleted_at as "deleted_at: PrimitiveDateTime"
+            from message
+            where quoted_message_id = $1 and deleted_at is null
+            order by created_at desc, rowid desc
+            limit $2 offset $3
+        "#,
+        id,
+        limit,
+        offset
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let messages = hydrate(&ctx.db, rows).await?;
+
+    Ok(Json(embed(&ctx.db, messages, expansions, Some(auth_user.user_id)).await?))
+}
+
+/// Inserts a message, a reply to `parent_id` or a quote of `quoted_id`, along with its attachments.
+///
+/// Answers `404 Not Found` if the parent or quoted message doesn't exist or was deleted.
+pub async fn insert_message(
+    ctx: &ApiContext,
+    author_id: Uuid,
+    input: MessageRequest,
+    parent_id: Option<Uuid>,
+    quoted_id: Option<Uuid>,
+) -> Result<Message> {
     let message_id = Uuid::new_v4();
 
-    let message = sqlx::query!(
+    let mut tx = ctx.db.begin().await?;
+
+    for id in [parent_id, quoted_id].into_iter().flatten() {
+        let exists = sqlx::query_scalar!(
+            r#"select exists(select 1 from message where id = $1 and deleted_at is null) as "exists!: bool""#,
+            id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        if !exists {
+            return Err(Error::NotFound);
+        }
+    }
+
+    let row = sqlx::query_as!(
+        MessageRow,
         r#"
-            insert into message (id, author_id, message, message_parent_id)
-            values ($1, $2, $3, $4)
+            insert into message (id, author_id, message, message_parent_id, quoted_message_id)
+            values ($1, $2, $3, $4, $5)
             returning
                 id as "id!: Uuid",
                 author_id as "author_id!: Uuid",
                 created_at as "created_at!: PrimitiveDateTime",
                 message as "message!",
-                message_parent_id as "message_parent_id!: Option<Uuid>"
+                message_parent_id as "message_parent_id!: Option<Uuid>",
+                edited_at as "edited_at: PrimitiveDateTime",
+                quoted_message_id as "quoted_message_id: Uuid",
+                deleted_at as "deleted_at: PrimitiveDateTime"
         "#,
         message_id,
-        auth_user.user_id,
+        author_id,
         input.message,
-        id
+        parent_id,
+        quoted_id
     )
-    .fetch_one(&ctx.db)
+    .fetch_one(&mut tx)
     .await
     .on_constraint("message", |_| {
-        Error::unprocessable_entity([("message", format!("duplicate message id"))])
+        Error::unprocessable_entity([("message", "duplicate message id")])
     })?;
-    
-    Ok(Json(
-        Message { 
-            id: message.id,
-            author_id: message.author_id,
-            created_at: message.created_at,
-            message: message.message,
-            message_parent_id: message.message_parent_id 
+
+    attachments::attach(&mut tx, author_id, message_id, &input.media_ids).await?;
+
+    counters::add(&mut tx, Counter::Messages, author_id, 1).await?;
+
+    if let Some(parent_id) = parent_id {
+        counters::add(&mut tx, Counter::Replies, parent_id, 1).await?;
+    }
+
+    tags::index(&mut tx, message_id, &input.message).await?;
+
+    let parent_author_id = match parent_id {
+        Some(parent_id) => {
+            sqlx::query_scalar!(
+                r#"select author_id as "author_id!: Uuid" from message where id = $1"#,
+                parent_id
+            )
+            .fetch_optional(&mut tx)
+            .await?
         }
-    ))
+        None => None,
+    };
+
+    let mut notified = Vec::new();
+
+    if let Some(parent_author_id) = parent_author_id {
+        notified.extend(notifications::notify(&mut tx, parent_author_id, Kind::Reply, author_id, Some(message_id)).await?);
+    }
+
+    // The author of the parent already gets a notification for the reply.
+    for user_id in mentions::index(&mut tx, message_id, &input.message).await? {
+        if Some(user_id) != parent_author_id {
+            notified.extend(notifications::notify(&mut tx, user_id, Kind::Mention, author_id, Some(message_id)).await?);
+        }
+    }
+
+    tx.commit().await?;
+
+    notifications::publish(&ctx.events, notified);
+
+    let message = hydrate_one(&ctx.db, row).await?;
+
+    let kind = if parent_id.is_some() { EventKind::Reply } else { EventKind::Message };
+    ctx.events.publish(kind, Audience::Everyone, &message);
+
+    // The message is already committed, a failure here shouldn't turn into an error response.
+    if let Err(e) = delivery::enqueue(&ctx.db, webhooks::MESSAGE_CREATED, author_id, &message).await {
+        log::error!("failed to queue webhooks for message {}: {:?}", message.id, e);
+    }
+
+    if let Err(e) = outbox::publish_create(ctx, message.id).await {
+        log::error!("failed to federate message {}: {:?}", message.id, e);
+    }
+
+    Ok(message)
+}
+
+/// Loads the data related to each message with one query per relation,
+/// rather than one per message.
+///
+/// Rows are loaded in batches so that the `in (...)` lists stay under SQLite's limit on bound parameters.
+pub async fn hydrate(db: &SqlitePool, rows: Vec<MessageRow>) -> Result<Vec<Message>> {
+    let mut messages = Vec::with_capacity(rows.len());
+    let mut rows = rows.into_iter().peekable();
+
+    while rows.peek().is_some() {
+        let batch: Vec<MessageRow> = rows.by_ref().take(HYDRATE_BATCH_SIZE).collect();
+        messages.extend(hydrate_batch(db, batch).await?);
+    }
+
+    Ok(messages)
+}
+
+async fn hydrate_batch(db: &SqlitePool, rows: Vec<MessageRow>) -> Result<Vec<Message>> {
+    let mut quoted_ids: Vec<Uuid> = rows.iter().filter_map(|row| row.quoted_message_id).collect();
+    quoted_ids.sort();
+    quoted_ids.dedup();
+
+    let quoted: HashMap<Uuid, Message> = load_related(db, fetch_rows(db, &quoted_ids).await?)
+        .await?
+        .into_iter()
+        .map(|message| (message.id, message))
+        .collect();
+
+    Ok(load_related(db, rows)
+        .await?
+        .into_iter()
+        .map(|mut message| {
+            message.quoted_message = message
+                .quoted_message_id
+                .filter(|_| message.deleted_at.is_none())
+                .and_then(|id| quoted.get(&id))
+                .map(|quoted| Box::new(quoted.clone()));
+            message
+        })
+        .collect())
+}
+
+/// Loads everything `hydrate` does except the quoted messages.
+async fn load_related(db: &SqlitePool, rows: Vec<MessageRow>) -> Result<Vec<Message>> {
+    let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+
+    let mut media = attachments::load(db, &ids).await?;
+    let mut mentions = mentions::load(db, &ids).await?;
+    let counts = reposts::counts(db, &ids).await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let counts = counts.get(&row.id).copied().unwrap_or_default();
+            let media = media.remove(&row.id).unwrap_or_default();
+            let mentions = mentions.remove(&row.id).unwrap_or_default();
+
+            let (message, media, mentions) = match row.deleted_at {
+                Some(_) => (String::new(), Vec::new(), Vec::new()),
+                None => (row.message, media, mentions),
+            };
+
+            Message {
+                media,
+                mentions,
+                id: row.id,
+                author_id: row.author_id,
+                created_at: row.created_at,
+                message,
+                message_parent_id: row.message_parent_id,
+                edited_at: row.edited_at,
+                quoted_message_id: row.quoted_message_id,
+                quoted_message: None,
+                repost_count: counts.reposts,
+                quote_count: counts.quotes,
+                deleted_at: row.deleted_at,
+                reposted_by: None,
+                author: None,
+                like_count: None,
+                reply_count: None,
+                liked_by_me: None,
+                bookmarked_by_me: None,
+            }
+        })
+        .collect())
+}
+
+/// Embeds the related data asked for in `expansions`, in quoted messages as well.
+///
+/// Like `hydrate`, each relation is loaded with one query for every message.
+/// `liked_by_me` and `bookmarked_by_me` are left out when there is no authenticated viewer.
+pub async fn embed(
+    db: &SqlitePool,
+    mut messages: Vec<Message>,
+    expansions: Expand,
+    viewer_id: Option<Uuid>,
+) -> Result<Vec<Message>> {
+    if expansions.is_empty() {
+        return Ok(messages);
+    }
+
+    let mut message_ids = Vec::with_capacity(messages.len());
+    let mut author_ids = Vec::with_capacity(messages.len());
+
+    for message in &messages {
+        for message in std::iter::once(message).chain(message.quoted_message.as_deref()) {
+            message_ids.push(message.id);
+            author_ids.push(message.author_id);
+        }
+    }
+
+    message_ids.sort();
+    message_ids.dedup();
+    author_ids.sort();
+    author_ids.dedup();
+
+    let authors = if expansions.author {
+        expand::authors(db, &author_ids).await?
+    } else {
+        HashMap::new()
+    };
+
+    let engagement = if expansions.counts {
+        expand::engagement(db, &message_ids).await?
+    } else {
+        HashMap::new()
+    };
+
+    let liked = match viewer_id {
+        Some(viewer_id) if expansions.liked_by_me => Some(expand::liked_by(db, viewer_id, &message_ids).await?),
+        _ => None,
+    };
+
+    let bookmarked = match viewer_id {
+        Some(viewer_id) if expansions.bookmarked_by_me => Some(expand::bookmarked_by(db, viewer_id, &message_ids).await?),
+        _ => None,
+    };
+
+    let apply = |message: &mut Message| {
+        if expansions.author {
+            message.author = authors.get(&message.author_id).cloned();
+        }
+
+        if expansions.counts {
+            let engagement = engagement.get(&message.id).copied().unwrap_or_default();
+            message.like_count = Some(engagement.likes);
+            message.reply_count = Some(engagement.replies);
+        }
+
+        if let Some(liked) = &liked {
+            message.liked_by_me = Some(liked.contains(&message.id));
+        }
+
+        if let Some(bookmarked) = &bookmarked {
+            message.bookmarked_by_me = Some(bookmarked.contains(&message.id));
+        }
+    };
+
+    for message in &mut messages {
+        apply(message);
+
+        if let Some(quoted) = message.quoted_message.as_deref_mut() {
+            apply(quoted);
+        }
+    }
+
+    Ok(messages)
+}
+
+async fn fetch_rows(db: &SqlitePool, ids: &[Uuid]) -> Result<Vec<MessageRow>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut query = QueryBuilder::new(format!(
+        r#"
+            select {}
+            from message
+            where id in (
+        "#,
+        MESSAGE_COLUMNS
+    ));
+
+    let mut separated = query.separated(", ");
+    for id in ids {
+        separated.push_bind(*id);
+    }
+    query.push(")");
+
+    Ok(query.build_query_as::<MessageRow>().fetch_all(db).await?)
+}
+
+async fn hydrate_one(db: &SqlitePool, row: MessageRow) -> Result<Message> {
+    Ok(hydrate(db, vec![row])
+        .await?
+        .pop()
+        .expect("BUG: hydrate should return one message per row"))
 }
\ No newline at end of file
@@ -1,2 +1,6 @@
 pub mod messages;
-pub mod routes;
\ No newline at end of file
+pub mod routes;
+pub mod reposts;
+pub mod mentions;
+pub mod expand;
+pub mod purge;
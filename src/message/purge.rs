@@ -0,0 +1,107 @@
+use crate::{
+    media::processing::Thumbnail,
+    storage::Storage,
+};
+use sqlx::types::Json as SqlJson;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+// How often deleted messages are checked for the end of their retention period.
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Spawns a task that purges deleted messages every `PURGE_INTERVAL`.
+pub fn spawn(db: SqlitePool, storage: Arc<dyn Storage>, retention: time::Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match purge(&db, storage.as_ref(), retention).await {
+                Ok(0) => (),
+                Ok(purged) => log::info!("purged {} deleted messages", purged),
+                Err(e) => log::error!("failed to purge deleted messages: {:?}", e),
+            }
+        }
+    });
+}
+
+/// Erases the content of messages deleted more than `retention` ago, along with their
+/// attachments, tags, mentions, notifications, likes, reposts and edit history.
+///
+/// The message rows themselves are kept as tombstones so that replies still render.
+pub async fn purge(db: &SqlitePool, storage: &dyn Storage, retention: time::Duration) -> anyhow::Result<u64> {
+    let cutoff = format!("-{} seconds", retention.whole_seconds());
+
+    let mut tx = db.begin().await?;
+
+    let media = sqlx::query!(
+        r#"
+            select
+                storage_key as "storage_key!",
+                thumbnails as "thumbnails!: SqlJson<Vec<Thumbnail>>"
+            from message_media
+            where message_id in (
+                select id from message
+                where deleted_at < datetime('now', $1) and purged_at is null
+            )
+        "#,
+        cutoff
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    for table in ["message_media", "message_tag", "message_mention", "notification", "like", "repost", "bookmark", "message_revision"] {
+        sqlx::query(&format!(
+            r#"
+                delete from "{}"
+                where message_id in (
+                    select id from message
+                    where deleted_at < datetime('now', $1) and purged_at is null
+                )
+            "#,
+            table
+        ))
+        .bind(&cutoff)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let purged = sqlx::query!(
+        r#"
+            update message
+            set message = '', like_count = 0, repost_count = 0, purged_at = current_timestamp
+            where deleted_at < datetime('now', $1) and purged_at is null
+        "#,
+        cutoff
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    // Identical uploads share their stored files, only remove the ones nothing points to anymore.
+    for media in media {
+        let still_used = sqlx::query_scalar!(
+            r#"select exists(select 1 from message_media where storage_key = $1) as "exists!: bool""#,
+            media.storage_key
+        )
+        .fetch_one(db)
+        .await?;
+
+        if still_used {
+            continue;
+        }
+
+        let thumbnail_keys = media.thumbnails.0.iter().filter_map(|thumbnail| storage.key(&thumbnail.url));
+
+        for key in std::iter::once(media.storage_key.clone()).chain(thumbnail_keys) {
+            if let Err(e) = storage.delete(&key).await {
+                log::warn!("failed to remove purged attachment {:?}: {:?}", key, e);
+            }
+        }
+    }
+
+    Ok(purged)
+}
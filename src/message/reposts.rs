@@ -0,0 +1,187 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+    counter::counters::{self, Counter},
+};
+use uuid::Uuid;
+use time::PrimitiveDateTime;
+use serde::Serialize;
+use axum::{
+    Json,
+    extract::{Path, Extension, Query},
+};
+use sqlx::{QueryBuilder, SqlitePool};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Repost {
+    pub user_id: Uuid,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counts {
+    pub reposts: i64,
+    pub quotes: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct CountsRow {
+    message_id: Uuid,
+    reposts: i64,
+    quotes: i64,
+}
+
+/// Reposts the message `id`. Reposting a message twice keeps the first repost.
+pub async fn create_repost(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<Json<Repost>> {
+    let exists = sqlx::query_scalar!(
+        r#"select exists(select 1 from message where id = $1 and deleted_at is null) as "exists!: bool""#,
+        id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if !exists {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = ctx.db.begin().await?;
+
+    let inserted = sqlx::query!(
+        "insert into repost (user_id, message_id) values ($1, $2) on conflict do nothing",
+        auth_user.user_id,
+        id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if inserted > 0 {
+        counters::add(&mut tx, Counter::Reposts, id, 1).await?;
+    }
+
+    let repost = sqlx::query_as!(
+        Repost,
+        r#"
+            select
+                user_id as "user_id!: Uuid",
+                created_at as "created_at!: PrimitiveDateTime"
+            from repost
+            where user_id = $1 and message_id = $2
+        "#,
+        auth_user.user_id,
+        id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(repost))
+}
+
+/// Undoes a repost of the message `id`.
+pub async fn delete_repost(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<()> {
+    let mut tx = ctx.db.begin().await?;
+
+    let deleted = sqlx::query!(
+        "delete from repost where user_id = $1 and message_id = $2",
+        auth_user.user_id,
+        id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(Error::NotFound);
+    }
+
+    counters::add(&mut tx, Counter::Reposts, id, -1).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Lists who reposted the message `id`, most recent first.
+pub async fn get_reposts(
+    _: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Query(page): Query<Pagination>
+) -> Result<Json<Vec<Repost>>> {
+    let (limit, offset) = (page.limit(), page.offset());
+
+    let reposts = sqlx::query_as!(
+        Repost,
+        r#"
+            select
+                user_id as "user_id!: Uuid",
+                created_at as "created_at!: PrimitiveDateTime"
+            from repost
+            where message_id = $1
+            order by created_at desc, rowid desc
+            limit $2 offset $3
+        "#,
+        id,
+        limit,
+        offset
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(reposts))
+}
+
+/// Counts the reposts and quotes of several messages at once, keyed by message id.
+///
+/// Reposts come from `message.repost_count`, quotes aren't denormalized and are still counted.
+pub async fn counts(db: &SqlitePool, message_ids: &[Uuid]) -> Result<HashMap<Uuid, Counts>> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut query = QueryBuilder::new(
+        r#"
+            select
+                message.id as message_id,
+                message.repost_count as reposts,
+                (
+                    select count(*) from message quote
+                    where quote.quoted_message_id = message.id and quote.deleted_at is null
+                ) as quotes
+            from message
+            where message.id in (
+        "#,
+    );
+
+    let mut ids = query.separated(", ");
+    for id in message_ids {
+        ids.push_bind(*id);
+    }
+    query.push(")");
+
+    let rows = query
+        .build_query_as::<CountsRow>()
+        .fetch_all(db)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.message_id, Counts { reposts: row.reposts, quotes: row.quotes }))
+        .collect())
+}
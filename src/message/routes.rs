@@ -1,6 +1,6 @@
-use crate::message::messages;
+use crate::message::{messages, reposts, mentions};
 use axum::{
-    routing::{get},
+    routing::{get, post},
     Router,
 };
 
@@ -14,7 +14,33 @@ pub fn router() -> Router {
                 .route(
                     "/message/:id",
                     get(messages::get_message)
+                    .patch(messages::edit_message)
                     .delete(messages::delete_message)
                     .post(messages::create_comment),
                 )
-}
\ No newline at end of file
+                .route(
+                    "/message/:id/history",
+                    get(messages::get_message_history),
+                )
+                .route(
+                    "/message/:id/repost",
+                    post(reposts::create_repost)
+                    .delete(reposts::delete_repost),
+                )
+                .route(
+                    "/message/:id/reposts",
+                    get(reposts::get_reposts),
+                )
+                .route(
+                    "/message/:id/quote",
+                    post(messages::create_quote),
+                )
+                .route(
+                    "/message/:id/quotes",
+                    get(messages::get_quotes),
+                )
+                .route(
+                    "/api/user/mentions",
+                    get(mentions::get_mentions),
+                )
+}
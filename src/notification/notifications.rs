@@ -0,0 +1,292 @@
+use crate::{
+    Result,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+    stream::bus::{Audience, EventBus, EventKind},
+};
+use uuid::Uuid;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use serde::{Deserialize, Serialize};
+use axum::{
+    Json,
+    extract::{Extension, Query},
+};
+use sqlx::{Executor, QueryBuilder, Sqlite};
+use std::collections::HashMap;
+
+// Number of actors listed in a grouped notification, `actor_count` has the total.
+const MAX_ACTORS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Like,
+    Reply,
+    Mention,
+    Follow,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Like => "like",
+            Self::Reply => "reply",
+            Self::Mention => "mention",
+            Self::Follow => "follow",
+        }
+    }
+
+    /// Likes of the same message and follows of the same (UTC) day are grouped together,
+    /// replies and mentions are each their own notification.
+    fn group_key(self, message_id: Option<Uuid>, notification_id: Uuid) -> String {
+        match (self, message_id) {
+            (Self::Like, Some(message_id)) => format!("like:{}", message_id),
+            (Self::Follow, _) => format!("follow:{}", OffsetDateTime::now_utc().date()),
+            _ => format!("{}:{}", self.as_str(), notification_id),
+        }
+    }
+}
+
+/// Notifications sharing a `group_key`, e.g. "5 people liked your message".
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    // The group key, which is what `mark_read` expects.
+    id: String,
+    kind: String,
+    message_id: Option<Uuid>,
+    // The most recent actors first.
+    actors: Vec<Uuid>,
+    actor_count: i64,
+    created_at: PrimitiveDateTime,
+    read: bool,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct NotificationFilter {
+    kind: Option<Kind>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct MarkRead {
+    // Notification ids to mark as read, all of them if missing.
+    ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadCount {
+    count: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct ActorRow {
+    group_key: String,
+    actor_id: Uuid,
+}
+
+/// A notification created by `notify`, which streaming clients haven't been told about yet.
+#[must_use = "streaming clients only learn about the notification once it is published"]
+pub struct Notified {
+    user_id: Uuid,
+    data: serde_json::Value,
+}
+
+/// Notifies `user_id` that `actor_id` did something, unless they are the same user.
+///
+/// Takes any executor so the notification can be created in the same transaction as its cause.
+/// Streaming clients of the user are told by passing the result to `publish` once that
+/// transaction is committed, so they never fetch a notification that doesn't exist.
+pub async fn notify<'c, E>(
+    db: E,
+    user_id: Uuid,
+    kind: Kind,
+    actor_id: Uuid,
+    message_id: Option<Uuid>,
+) -> Result<Option<Notified>>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    if user_id == actor_id {
+        return Ok(None);
+    }
+
+    let id = Uuid::new_v4();
+    let kind_name = kind.as_str();
+    let group_key = kind.group_key(message_id, id);
+
+    sqlx::query!(
+        r#"
+            insert into notification (id, user_id, kind, actor_id, message_id, group_key)
+            values ($1, $2, $3, $4, $5, $6)
+        "#,
+        id,
+        user_id,
+        kind_name,
+        actor_id,
+        message_id,
+        group_key
+    )
+    .execute(db)
+    .await?;
+
+    Ok(Some(Notified {
+        user_id,
+        data: serde_json::json!({
+            "id": group_key,
+            "kind": kind,
+            "actor_id": actor_id,
+            "message_id": message_id,
+        }),
+    }))
+}
+
+/// Tells streaming clients about notifications created by `notify`, once they are committed.
+pub fn publish(events: &EventBus, notified: impl IntoIterator<Item = Notified>) {
+    for notified in notified {
+        events.publish(EventKind::Notification, Audience::User(notified.user_id), notified.data);
+    }
+}
+
+/// Lists the notifications of the current user grouped by `group_key`, most recent first.
+///
+/// Notifications about deleted messages are left out.
+pub async fn get_notifications(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Query(filter): Query<NotificationFilter>
+) -> Result<Json<Vec<Notification>>> {
+    let page = Pagination {
+        limit: filter.limit,
+        offset: filter.offset,
+    };
+    let (limit, offset) = (page.limit(), page.offset());
+    let kind = filter.kind.map(Kind::as_str);
+
+    let groups = sqlx::query!(
+        r#"
+            select
+                group_key as "group_key!",
+                kind as "kind!",
+                message_id as "message_id: Uuid",
+                count(distinct actor_id) as "actor_count!: i64",
+                max(created_at) as "created_at!: PrimitiveDateTime",
+                min(read_at is not null) as "read!: bool"
+            from notification
+            where user_id = $1
+                and ($2 is null or kind = $2)
+                and (
+                    message_id is null
+                    or message_id not in (select id from message where deleted_at is not null)
+                )
+            group by group_key
+            order by max(created_at) desc, max(rowid) desc
+            limit $3 offset $4
+        "#,
+        auth_user.user_id,
+        kind,
+        limit,
+        offset
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let mut actors: HashMap<String, Vec<Uuid>> = HashMap::new();
+
+    if !groups.is_empty() {
+        let mut query = QueryBuilder::new(
+            "select group_key, actor_id from notification where user_id = ",
+        );
+        query.push_bind(auth_user.user_id).push(" and group_key in (");
+
+        let mut keys = query.separated(", ");
+        for group in &groups {
+            keys.push_bind(group.group_key.clone());
+        }
+        query.push(") order by created_at desc, rowid desc");
+
+        let rows = query
+            .build_query_as::<ActorRow>()
+            .fetch_all(&ctx.db)
+            .await?;
+
+        for row in rows {
+            let group = actors.entry(row.group_key).or_default();
+            if group.len() < MAX_ACTORS && !group.contains(&row.actor_id) {
+                group.push(row.actor_id);
+            }
+        }
+    }
+
+    Ok(Json(
+        groups
+            .into_iter()
+            .map(|group| Notification {
+                actors: actors.remove(&group.group_key).unwrap_or_default(),
+                id: group.group_key,
+                kind: group.kind,
+                message_id: group.message_id,
+                actor_count: group.actor_count,
+                created_at: group.created_at,
+                read: group.read,
+            })
+            .collect(),
+    ))
+}
+
+/// Marks the given notifications of the current user as read, or all of them.
+pub async fn mark_read(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<MarkRead>
+) -> Result<Json<UnreadCount>> {
+    let mut query = QueryBuilder::new(
+        "update notification set read_at = current_timestamp where read_at is null and user_id = ",
+    );
+    query.push_bind(auth_user.user_id);
+
+    if let Some(ids) = req.ids {
+        if ids.is_empty() {
+            return unread_count(auth_user, ctx).await;
+        }
+
+        query.push(" and group_key in (");
+        let mut keys = query.separated(", ");
+        for id in ids {
+            keys.push_bind(id);
+        }
+        query.push(")");
+    }
+
+    query.build().execute(&ctx.db).await?;
+
+    unread_count(auth_user, ctx).await
+}
+
+/// Number of unread notifications of the current user, counting each group once.
+pub async fn unread_count(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>
+) -> Result<Json<UnreadCount>> {
+    let count = sqlx::query_scalar!(
+        r#"
+            select count(distinct group_key) as "count!: i64"
+            from notification
+            where user_id = $1 and read_at is null
+                and (
+                    message_id is null
+                    or message_id not in (select id from message where deleted_at is not null)
+                )
+        "#,
+        auth_user.user_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(Json(UnreadCount { count }))
+}
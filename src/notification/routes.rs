@@ -0,0 +1,21 @@
+use crate::notification::notifications;
+use axum::{
+    routing::{get, post},
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/notifications",
+            get(notifications::get_notifications)
+        )
+        .route(
+            "/api/notifications/read",
+            post(notifications::mark_read)
+        )
+        .route(
+            "/api/notifications/unread-count",
+            get(notifications::unread_count)
+        )
+}
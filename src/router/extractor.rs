@@ -1,27 +1,55 @@
 use crate::error::Error;
 use axum::async_trait;
-use axum::extract::{Extension, FromRequest, FromRequestParts};
+use axum::extract::{ConnectInfo, Extension, FromRequestParts};
 use crate::router::server::ApiContext;
 
-use axum::http::header::AUTHORIZATION;
+use axum::http::header::{AUTHORIZATION, USER_AGENT};
 use axum::http::HeaderValue;
-use axum::http::{Request, request::Parts};
+use axum::http::request::Parts;
 use hmac::{Hmac, Mac};
 use jwt::{SignWithKey, VerifyWithKey};
 use sha2::Sha384;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
 const DEFAULT_SESSION_LENGTH: time::Duration = time::Duration::weeks(2);
 const SCHEME_PREFIX: &str = "Token ";
+// Mastodon clients send the same tokens as OAuth bearer tokens, see `mastodon::oauth`.
+const BEARER_PREFIX: &str = "Bearer ";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
 pub struct AuthUser {
     pub user_id: Uuid,
+    // Tokens are only valid while this matches `user.token_version`,
+    // bumping the column revokes every token issued before.
+    pub token_version: i64,
+    // Set on tokens issued to OAuth clients, which are only valid while their `oauth_token`
+    // row exists so they can be revoked one by one.
+    pub token_id: Option<Uuid>,
+}
+
+/// An authenticated user that may also send its token in an `access_token` query parameter.
+///
+/// Only meant for streaming routes, since browsers can't set headers on `EventSource`
+/// and `WebSocket` requests. Tokens in URLs tend to end up in logs, so every other route
+/// should stick to `AuthUser`.
+pub struct StreamAuthUser(pub AuthUser);
+
+/// An authenticated user that also has the `is_admin` flag set.
+///
+/// Rejects with `403 Forbidden` if the user is authenticated but not an admin.
+pub struct AdminUser {
+    pub user_id: Uuid,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 struct AuthUserClaims {
     user_id: Uuid,
+    #[serde(default)]
+    ver: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tok: Option<Uuid>,
     /// Standard JWT `exp` claim.
     exp: i64,
 }
@@ -33,6 +61,8 @@ impl AuthUser {
 
         AuthUserClaims {
             user_id: self.user_id,
+            ver: self.token_version,
+            tok: self.token_id,
             exp: (OffsetDateTime::now_utc() + DEFAULT_SESSION_LENGTH).unix_timestamp(),
         }
         .sign_with_key(&hmac)
@@ -45,23 +75,25 @@ impl AuthUser {
             Error::Unauthorized
         })?;
 
-        if !auth_header.starts_with(SCHEME_PREFIX) {
+        let Some(token) = auth_header
+            .strip_prefix(SCHEME_PREFIX)
+            .or_else(|| auth_header.strip_prefix(BEARER_PREFIX))
+        else {
             log::debug!(
                 "Authorization header is using the wrong scheme: {:?}",
                 auth_header
             );
             return Err(Error::Unauthorized);
-        }
+        };
 
-        let token = &auth_header[SCHEME_PREFIX.len()..];
+        Self::from_token(ctx, token)
+    }
 
+    /// Checks the signature and expiry of `token`, but not whether it has been revoked.
+    pub fn from_token(ctx: &ApiContext, token: &str) -> Result<Self, Error> {
         let jwt =
             jwt::Token::<jwt::Header, AuthUserClaims, _>::parse_unverified(token).map_err(|e| {
-                log::debug!(
-                    "failed to parse Authorization header {:?}: {}",
-                    auth_header,
-                    e
-                );
+                log::debug!("failed to parse token {:?}: {}", token, e);
                 Error::Unauthorized
             })?;
 
@@ -82,6 +114,8 @@ impl AuthUser {
 
         std::result::Result::Ok(Self {
             user_id: claims.user_id,
+            token_version: claims.ver,
+            token_id: claims.tok,
         })
     }
 }
@@ -105,6 +139,155 @@ where
             .get(AUTHORIZATION)
             .ok_or(Error::Unauthorized)?;
 
-        Self::from_authorization(&ctx, auth_header)
+        Self::authenticate(&ctx, auth_header).await
+    }
+}
+
+impl AuthUser {
+    /// Checks the token of an `Authorization` header and that it hasn't been revoked.
+    async fn authenticate(ctx: &ApiContext, auth_header: &HeaderValue) -> Result<Self, Error> {
+        let auth_user = Self::from_authorization(ctx, auth_header)?;
+
+        auth_user.ensure_current(ctx).await?;
+
+        Ok(auth_user)
+    }
+
+    /// Returns `401 Unauthorized` if the token was revoked since it was issued.
+    ///
+    /// Connections that outlive a request, like streams, call this again every now and then.
+    pub async fn ensure_current(&self, ctx: &ApiContext) -> Result<(), Error> {
+        let token_version = sqlx::query_scalar!(
+            r#"select token_version as "token_version!: i64" from user where id = $1"#,
+            self.user_id
+        )
+        .fetch_optional(&ctx.db)
+        .await?;
+
+        if token_version != Some(self.token_version) {
+            log::debug!("token revoked or user does not exist");
+            return Err(Error::Unauthorized);
+        }
+
+        if let Some(token_id) = self.token_id {
+            let issued = sqlx::query_scalar!(
+                r#"select exists(select 1 from oauth_token where id = $1) as "exists!: bool""#,
+                token_id
+            )
+            .fetch_one(&ctx.db)
+            .await?;
+
+            if !issued {
+                log::debug!("OAuth token revoked");
+                return Err(Error::Unauthorized);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(req: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth_user = AuthUser::from_request_parts(req, state).await?;
+
+        let ctx: Extension<ApiContext> = Extension::from_request_parts(req, state)
+            .await
+            .expect("BUG: ApiContext was not added as an extension");
+
+        let is_admin = sqlx::query_scalar!(
+            r#"select is_admin as "is_admin: bool" from user where id = $1"#,
+            auth_user.user_id
+        )
+        .fetch_optional(&ctx.db)
+        .await?
+        .unwrap_or(false);
+
+        if !is_admin {
+            return Err(Error::Forbidden);
+        }
+
+        Ok(Self {
+            user_id: auth_user.user_id,
+        })
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for StreamAuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(req: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if req.headers.contains_key(AUTHORIZATION) {
+            return AuthUser::from_request_parts(req, state).await.map(Self);
+        }
+
+        let ctx: Extension<ApiContext> = Extension::from_request_parts(req, state)
+            .await
+            .expect("BUG: ApiContext was not added as an extension");
+
+        let token = req
+            .uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("access_token="))
+            .ok_or(Error::Unauthorized)?;
+
+        let auth_header = HeaderValue::from_str(&format!("{}{}", SCHEME_PREFIX, token))
+            .map_err(|_| Error::Unauthorized)?;
+
+        AuthUser::authenticate(&ctx, &auth_header).await.map(Self)
+    }
+}
+
+/// Information about the client that sent a request, used for auditing and throttling.
+pub struct RequestMeta {
+    pub ip: IpAddr,
+    pub user_agent: Option<String>,
+    // Set by the `SetRequestIdLayer` in `server.rs` if the client did not send one.
+    pub request_id: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestMeta
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip = req
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+        let user_agent = req
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let request_id = req
+            .headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        Ok(Self {
+            ip,
+            user_agent,
+            request_id,
+        })
     }
 }
\ No newline at end of file
@@ -1,2 +1,3 @@
 pub mod server;
-pub mod extractor;
\ No newline at end of file
+pub mod extractor;
+pub mod pagination;
\ No newline at end of file
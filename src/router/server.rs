@@ -1,12 +1,35 @@
 use anyhow::Context;
-use axum::{Extension, Router};
+use axum::{
+    body::Body,
+    http::{Request, Uri},
+    Extension,
+    Router,
+};
 use tower::ServiceBuilder;
 use sqlx::sqlite::SqlitePool;
 use crate::config::Config;
 use crate::message;
 use crate::like;
 use crate::user;
+use crate::audit;
+use crate::media;
+use crate::tag;
+use crate::search;
+use crate::conversation;
+use crate::notification;
+use crate::stream::{self, bus::EventBus};
+use crate::webhook;
+use crate::federation;
+use crate::feed;
+use crate::mastodon;
+use crate::graphql;
+use crate::counter;
+use crate::bookmark;
+use crate::storage::{self, Storage};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
 
 // Core type through which handler functions can access API state.
@@ -16,28 +39,116 @@ use tower_http::trace::TraceLayer;
 pub struct ApiContext {
     pub config: Arc<Config>,
     pub db: SqlitePool,
+    // Argon2 parameters used for new password hashes, validated at startup.
+    pub argon2_params: argon2::Params,
+    // Limits how many password hashes are computed concurrently.
+    pub password_hashing: Arc<Semaphore>,
+    pub storage: Arc<dyn Storage>,
+    // Feeds the streaming routes, handlers publish to it once their changes are committed.
+    pub events: Arc<EventBus>,
+    // Fetches actors and WebFinger documents from other ActivityPub servers.
+    pub http: reqwest::Client,
 }
 
 pub async fn serve(config: Config, db: SqlitePool) -> anyhow::Result<()> {
+    let argon2_params = argon2::Params::new(
+        config.argon2_memory_cost,
+        config.argon2_time_cost,
+        config.argon2_parallelism,
+        None,
+    )
+    .map_err(|e| anyhow::anyhow!("invalid Argon2 parameters: {}", e))?;
+
+    // No permit could ever be acquired, so every login would hang.
+    anyhow::ensure!(config.password_hashing_concurrency > 0, "password_hashing_concurrency must be at least 1");
+
+    let password_hashing = Arc::new(Semaphore::new(config.password_hashing_concurrency));
+
+    let storage = storage::from_config(&config)?;
+
+    message::purge::spawn(
+        db.clone(),
+        storage.clone(),
+        time::Duration::days(config.message_retention_days),
+    );
+
+    webhook::delivery::spawn(db.clone(), config.webhook_allow_private_addresses);
+
+    let http = federation::client(&config)?;
+    federation::delivery::spawn(
+        db.clone(),
+        http.clone(),
+        config.public_url.clone(),
+        config.federation_allow_private_addresses,
+    );
+
+    let bind_address = config.bind_address;
+
     // Build the core of our router with different layer.
-    let app = router().layer(
+    let app = router(&config).layer(
         ServiceBuilder::new()
             .layer(Extension(ApiContext {
                 config: Arc::new(config),
                 db,
+                argon2_params,
+                password_hashing,
+                storage,
+                events: Arc::new(EventBus::new()),
+                http,
             }))
+            // Tags every request with an `x-request-id` so it can be traced in the logs
+            // and in the audit log, and sends it back in the response.
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
             // Enables logging. Use `RUST_LOG=tower_http=debug`
-            .layer(TraceLayer::new_for_http())
+            .layer(TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
+                tracing::debug_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %redacted_uri(request.uri()),
+                    version = ?request.version(),
+                )
+            }))
+            .layer(PropagateRequestIdLayer::x_request_id())
     );
 
-    axum::Server::bind(&"0.0.0.0:3000".parse()?)
-        .serve(app.into_make_service())
+    axum::Server::bind(&bind_address)
+        // Handlers can extract the client address through `ConnectInfo<SocketAddr>`.
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .context("error running HTTP server")
 }
 
-fn router() -> Router {
+fn router(config: &Config) -> Router {
     message::routes::router()
         .merge(like::routes::router())
         .merge(user::routes::router())
+        .merge(audit::routes::router())
+        .merge(media::routes::router())
+        .merge(tag::routes::router())
+        .merge(search::routes::router())
+        .merge(conversation::routes::router())
+        .merge(notification::routes::router())
+        .merge(stream::routes::router())
+        .merge(webhook::routes::router())
+        .merge(federation::routes::router())
+        .merge(feed::routes::router())
+        .merge(mastodon::routes::router())
+        .merge(graphql::routes::router())
+        .merge(counter::routes::router())
+        .merge(bookmark::routes::router())
+        .merge(storage::routes::router(config))
+}
+
+/// The URI of a request as logged, without the token streaming clients can send as `access_token`.
+fn redacted_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+
+    let query: Vec<&str> = query
+        .split('&')
+        .map(|pair| if pair.starts_with("access_token=") { "access_token=[redacted]" } else { pair })
+        .collect();
+
+    format!("{}?{}", uri.path(), query.join("&"))
 }
\ No newline at end of file
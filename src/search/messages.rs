@@ -0,0 +1,246 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+    message::{
+        messages::{self, Message, MessageRow},
+        expand::Expand,
+    },
+};
+use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use axum::{
+    Json,
+    extract::{Extension, Query},
+};
+use sqlx::{FromRow, QueryBuilder, Row};
+
+// Highlighted matches in snippets are wrapped in these.
+const HIGHLIGHT_START: &str = "<mark>";
+const HIGHLIGHT_END: &str = "</mark>";
+// What SQLite wraps the matches in, replaced by the tags above once the snippet is escaped.
+// Private use characters, so they can't be mistaken for anything a user would write.
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+// Number of tokens around the matches kept in a snippet.
+const SNIPPET_TOKENS: i64 = 16;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageSearchResult {
+    #[serde(flatten)]
+    message: Message,
+    // The matching part of the message with the matches highlighted,
+    // `None` when the query only has operators.
+    snippet: Option<String>,
+}
+
+/// A parsed search query, see `parse`.
+#[derive(Debug, Default)]
+struct ParsedQuery {
+    // Quoted FTS5 terms, empty when the query only has operators.
+    terms: Vec<String>,
+    from: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+/// Parses a search query made of:
+///
+/// * words, which must all appear in the message,
+/// * `"quoted phrases"`, which must appear as is,
+/// * `prefix*`, matching any word starting with `prefix`,
+/// * `from:username` to only search the messages of a user,
+/// * `since:` and `until:` followed by a date like `2023-03-01`.
+///
+/// Every term is quoted before being handed to FTS5, so its own query syntax
+/// (`OR`, `NEAR`, column filters...) can't be used to craft expensive queries.
+fn parse(q: &str) -> ParsedQuery {
+    let mut parsed = ParsedQuery::default();
+    let mut rest = q.trim();
+
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let (phrase, remaining) = quoted.split_once('"').unwrap_or((quoted, ""));
+            if !phrase.trim().is_empty() {
+                parsed.terms.push(quote(phrase));
+            }
+            rest = remaining.trim_start();
+            continue;
+        }
+
+        let (word, remaining) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        rest = remaining.trim_start();
+
+        match word.split_once(':') {
+            Some(("from", username)) if !username.is_empty() => {
+                parsed.from = Some(username.trim_start_matches('@').to_string());
+            }
+            Some(("since", date)) if !date.is_empty() => parsed.since = Some(date.to_string()),
+            Some(("until", date)) if !date.is_empty() => parsed.until = Some(date.to_string()),
+            _ => match word.strip_suffix('*') {
+                Some(prefix) if !prefix.is_empty() => parsed.terms.push(format!("{}*", quote(prefix))),
+                _ => parsed.terms.push(quote(word)),
+            },
+        }
+    }
+
+    parsed
+}
+
+fn quote(term: &str) -> String {
+    format!("\"{}\"", term.replace('"', "\"\""))
+}
+
+/// Searches messages, best matches first.
+///
+/// See `parse` for the query syntax. Queries with operators only, like `from:alice`,
+/// list the matching messages from the most recent.
+pub async fn search_messages(
+    auth_user: Option<AuthUser>,
+    ctx: Extension<ApiContext>,
+    Query(query): Query<SearchQuery>,
+    Query(expansions): Query<Expand>
+) -> Result<Json<Vec<MessageSearchResult>>> {
+    let parsed = parse(&query.q);
+    let page = Pagination {
+        limit: query.limit,
+        offset: query.offset,
+    };
+
+    if parsed.terms.is_empty() && parsed.from.is_none() && parsed.since.is_none() && parsed.until.is_none() {
+        return Err(Error::unprocessable_entity([("q", "must not be empty")]));
+    }
+
+    let author_id = match &parsed.from {
+        Some(username) => {
+            let author_id = sqlx::query_scalar!(
+                r#"select id as "id!: Uuid" from user where username = $1 collate nocase"#,
+                username
+            )
+            .fetch_optional(&ctx.db)
+            .await?;
+
+            // Searching the messages of a user that doesn't exist matches nothing.
+            match author_id {
+                Some(author_id) => Some(author_id),
+                None => return Ok(Json(Vec::new())),
+            }
+        }
+        None => None,
+    };
+
+    let mut sql = QueryBuilder::new(format!("select {}, ", messages::MESSAGE_COLUMNS));
+
+    if parsed.terms.is_empty() {
+        sql.push("null as snippet from message where message.deleted_at is null");
+    } else {
+        sql.push("snippet(message_fts, 0, ")
+            .push_bind(MATCH_START.to_string())
+            .push(", ")
+            .push_bind(MATCH_END.to_string())
+            .push(", '…', ")
+            .push_bind(SNIPPET_TOKENS)
+            .push(
+                r#") as snippet
+                from message_fts
+                inner join message on message.rowid = message_fts.rowid
+                where message.deleted_at is null and message_fts match "#,
+            )
+            .push_bind(parsed.terms.join(" "));
+    }
+
+    if let Some(author_id) = author_id {
+        sql.push(" and message.author_id = ").push_bind(author_id);
+    }
+
+    if let Some(since) = parsed.since {
+        sql.push(" and message.created_at >= datetime(").push_bind(since).push(")");
+    }
+
+    if let Some(until) = parsed.until {
+        sql.push(" and message.created_at < datetime(").push_bind(until).push(")");
+    }
+
+    if parsed.terms.is_empty() {
+        sql.push(" order by message.created_at desc, message.rowid desc");
+    } else {
+        // `rank` is the BM25 score of the match, lower is better.
+        sql.push(" order by message_fts.rank, message.created_at desc");
+    }
+
+    sql.push(" limit ")
+        .push_bind(page.limit())
+        .push(" offset ")
+        .push_bind(page.offset());
+
+    let rows = sql.build().fetch_all(&ctx.db).await?;
+
+    let mut message_rows = Vec::with_capacity(rows.len());
+    let mut snippets = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        message_rows.push(MessageRow::from_row(&row)?);
+        let snippet: Option<String> = row.try_get("snippet")?;
+        snippets.push(snippet.as_deref().map(highlight));
+    }
+
+    let messages = messages::hydrate(&ctx.db, message_rows).await?;
+    let viewer_id = auth_user.map(|auth_user| auth_user.user_id);
+
+    let results = messages::embed(&ctx.db, messages, expansions, viewer_id)
+        .await?
+        .into_iter()
+        .zip(snippets)
+        .map(|(message, snippet)| MessageSearchResult { message, snippet })
+        .collect();
+
+    Ok(Json(results))
+}
+
+/// HTML escapes a snippet returned by SQLite, then wraps its matches in `<mark>` tags.
+///
+/// Snippets are made of the message content, so they can't be trusted as HTML.
+fn highlight(snippet: &str) -> String {
+    let mut highlighted = String::with_capacity(snippet.len());
+
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => highlighted.push_str(HIGHLIGHT_START),
+            MATCH_END => highlighted.push_str(HIGHLIGHT_END),
+            '&' => highlighted.push_str("&amp;"),
+            '<' => highlighted.push_str("&lt;"),
+            '>' => highlighted.push_str("&gt;"),
+            '"' => highlighted.push_str("&quot;"),
+            '\'' => highlighted.push_str("&#39;"),
+            c => highlighted.push(c),
+        }
+    }
+
+    highlighted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_escapes_the_message_content() {
+        let snippet = "<img src=x onerror=\"alert('\u{E000}hi\u{E001}')\"> & more";
+
+        assert_eq!(
+            highlight(snippet),
+            "&lt;img src=x onerror=&quot;alert(&#39;<mark>hi</mark>&#39;)&quot;&gt; &amp; more",
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod messages;
+pub mod users;
+pub mod routes;
@@ -0,0 +1,17 @@
+use crate::search::{messages, users};
+use axum::{
+    routing::get,
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/search/messages",
+            get(messages::search_messages)
+        )
+        .route(
+            "/api/search/users",
+            get(users::search_users)
+        )
+}
@@ -0,0 +1,134 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+};
+use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use axum::{
+    Json,
+    extract::{Extension, Query},
+};
+
+// Besides the users the caller has interacted with, only this many users matching the prefix
+// are ranked, shortest usernames first, which keeps short prefixes like a single letter fast.
+const MAX_CANDIDATES: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct UserSearchQuery {
+    q: String,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct UserSearchResult {
+    id: Uuid,
+    username: String,
+    display_name: String,
+    image: Option<String>,
+}
+
+/// Finds users whose username or display name starts with `q`, for `@` autocomplete.
+///
+/// An exact username match comes first, then users the caller has interacted with
+/// (mentioned, replied to or reposted), then the most mentioned users.
+pub async fn search_users(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Query(query): Query<UserSearchQuery>
+) -> Result<Json<Vec<UserSearchResult>>> {
+    let q = query.q.trim().trim_start_matches('@');
+
+    if q.is_empty() {
+        return Err(Error::unprocessable_entity([("q", "must not be empty")]));
+    }
+
+    // `_` is common in usernames but is also a `like` wildcard.
+    let pattern = format!(
+        "{}%",
+        q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+    );
+    let limit = Pagination { limit: query.limit, offset: None }.limit();
+
+    // Checked at runtime, the query macros take minutes to analyze this query.
+    let users = sqlx::query_as::<_, UserSearchResult>(
+        r#"
+            with interacted (id) as (
+                select message_mention.user_id
+                from message_mention
+                inner join message on message.id = message_mention.message_id
+                where message.author_id = $2
+                union
+                select message.author_id
+                from repost
+                inner join message on message.id = repost.message_id
+                where repost.user_id = $2
+                union
+                select parent.author_id
+                from message as reply
+                inner join message as parent on parent.id = reply.message_parent_id
+                where reply.author_id = $2
+            ),
+            candidate as (
+                select id, username, display_name, image
+                from user
+                where id in (select id from interacted)
+                    and (username like $1 escape '\' or display_name like $1 escape '\')
+                    and id != $2
+                union
+                select * from (
+                    select id, username, display_name, image
+                    from user
+                    where (username like $1 escape '\' or display_name like $1 escape '\')
+                        and id != $2
+                    order by username = $3 collate nocase desc, length(username)
+                    limit $4
+                )
+            )
+            select
+                candidate.id,
+                candidate.username,
+                candidate.display_name,
+                candidate.image
+            from candidate
+            order by
+                candidate.username = $3 collate nocase desc,
+                (
+                    select count(*)
+                    from message_mention
+                    inner join message on message.id = message_mention.message_id
+                    where message_mention.user_id = candidate.id and message.author_id = $2
+                ) + (
+                    select count(*)
+                    from repost
+                    inner join message on message.id = repost.message_id
+                    where repost.user_id = $2 and message.author_id = candidate.id
+                ) + (
+                    select count(*)
+                    from message as reply
+                    inner join message as parent on parent.id = reply.message_parent_id
+                    where reply.author_id = $2 and parent.author_id = candidate.id
+                ) desc,
+                (
+                    select count(*)
+                    from message_mention
+                    where message_mention.user_id = candidate.id
+                ) desc,
+                candidate.username
+            limit $5
+        "#
+    )
+    .bind(pattern)
+    .bind(auth_user.user_id)
+    .bind(q)
+    .bind(MAX_CANDIDATES)
+    .bind(limit)
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(users))
+}
@@ -0,0 +1,30 @@
+use crate::{
+    Result,
+    Error,
+    router::server::ApiContext,
+    storage::INCOMING_PREFIX,
+};
+use axum::{
+    extract::{Extension, Path},
+    response::Redirect,
+};
+use time::Duration;
+
+// Long enough for slow clients, short enough that a shared link stops working soon.
+const DOWNLOAD_URL_TTL: Duration = Duration::hours(1);
+
+/// Redirects to a presigned URL of the file stored under `key`.
+///
+/// This is how files are served from a private bucket when `Config::media_url` isn't set.
+pub async fn download_file(
+    ctx: Extension<ApiContext>,
+    Path(key): Path<String>,
+) -> Result<Redirect> {
+    if key.starts_with(INCOMING_PREFIX) {
+        return Err(Error::NotFound);
+    }
+
+    let url = ctx.storage.presign_download(&key, DOWNLOAD_URL_TTL).await?;
+
+    Ok(Redirect::temporary(&url))
+}
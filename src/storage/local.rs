@@ -0,0 +1,153 @@
+use crate::config::Config;
+use crate::storage::{Storage, DEFAULT_URL_PREFIX};
+use anyhow::Context;
+use axum::async_trait;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use time::{Duration, OffsetDateTime};
+
+/// Presigned uploads are sent to this server, see `storage::routes`.
+pub const UPLOAD_PATH: &str = "/api/storage";
+
+/// Stores files in a directory on the local filesystem.
+///
+/// Files are served back by the static route in `storage::routes`.
+pub struct LocalStorage {
+    root: PathBuf,
+    url_prefix: String,
+    // Presigned uploads are disabled without a key.
+    signing_key: Option<Vec<u8>>,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>, url_prefix: &str, signing_key: Option<&str>) -> Self {
+        Self {
+            root: root.into(),
+            url_prefix: url_prefix.trim_end_matches('/').to_string(),
+            signing_key: signing_key.map(|key| key.as_bytes().to_vec()),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            &config.storage_path,
+            config.media_url.as_deref().unwrap_or(DEFAULT_URL_PREFIX),
+            config.storage_signing_key.as_deref(),
+        )
+    }
+
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let key = Path::new(key);
+
+        // Keys are generated by us, but never let one escape the storage directory.
+        if !key.components().all(|c| matches!(c, Component::Normal(_))) {
+            anyhow::bail!("invalid storage key: {:?}", key);
+        }
+
+        Ok(self.root.join(key))
+    }
+
+    fn mac(&self, key: &str, content_type: &str, expires: i64) -> Option<Hmac<Sha256>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.signing_key.as_deref()?)
+            .expect("HMAC-SHA-256 can accept any key length");
+
+        mac.update(format!("PUT\n{}\n{}\n{}", key, content_type, expires).as_bytes());
+        Some(mac)
+    }
+
+    /// Checks a presigned upload URL issued by `presign_upload` for `key` and `content_type`.
+    pub fn verify_upload(&self, key: &str, content_type: &str, expires: i64, signature: &str) -> bool {
+        if expires < OffsetDateTime::now_utc().unix_timestamp() {
+            return false;
+        }
+
+        let signature: Option<Vec<u8>> = (0..signature.len())
+            .step_by(2)
+            .map(|i| signature.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect();
+
+        match (signature, self.mac(key, content_type, expires)) {
+            (Some(signature), Some(mac)) => mac.verify_slice(&signature).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, _content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("failed to create directory {:?}", parent))?;
+        }
+
+        tokio::fs::write(&path, bytes)
+            .await
+            .with_context(|| format!("failed to write {:?}", path))
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        let path = self.path(key)?;
+
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(Some(bytes.into())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read {:?}", path)),
+        }
+    }
+
+    async fn size(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        let path = self.path(key)?;
+
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read the metadata of {:?}", path)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("failed to remove {:?}", path))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.url_prefix, key)
+    }
+
+    async fn presign_upload(&self, key: &str, content_type: &str, expires_in: Duration) -> anyhow::Result<String> {
+        self.path(key)?;
+
+        let expires = (OffsetDateTime::now_utc() + expires_in).unix_timestamp();
+
+        let signature: String = self
+            .mac(key, content_type, expires)
+            .context("STORAGE_SIGNING_KEY must be set for presigned uploads")?
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        Ok(format!("{}/{}?expires={}&signature={}", UPLOAD_PATH, key, expires, signature))
+    }
+
+    // Everything under the storage directory is served publicly, there is nothing to sign.
+    async fn presign_download(&self, key: &str, _expires_in: Duration) -> anyhow::Result<String> {
+        self.path(key)?;
+
+        Ok(self.url(key))
+    }
+}
@@ -0,0 +1,83 @@
+pub mod local;
+pub mod s3;
+pub mod routes;
+pub mod uploads;
+pub mod downloads;
+
+use crate::config::{Config, StorageBackend};
+use axum::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+use time::Duration;
+
+/// Stored files are served under this path unless `Config::media_url` says otherwise.
+pub const DEFAULT_URL_PREFIX: &str = "/media";
+
+/// Files uploaded straight to the storage through a presigned URL are kept under this prefix
+/// until the media pipeline processes them, and are never served.
+pub const INCOMING_PREFIX: &str = "incoming/";
+
+/// Where uploaded files end up.
+///
+/// Keys are relative paths like `users/<id>/avatar-<id>.png`, and it is up to
+/// the implementation to map them to wherever the file is actually stored.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores `bytes` under `key`, replacing any existing file.
+    async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()>;
+
+    /// Reads the file stored under `key`, returns `None` if there is none.
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>>;
+
+    /// The size in bytes of the file stored under `key`, returns `None` if there is none.
+    async fn size(&self, key: &str) -> anyhow::Result<Option<u64>>;
+
+    /// Removes the file stored under `key`. Removing a missing file is not an error.
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+
+    /// The URL clients can download the file stored under `key` from.
+    fn url(&self, key: &str) -> String;
+
+    /// The inverse of `url`, returns `None` for URLs that don't point to this storage.
+    fn key(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.url(""))
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+    }
+
+    /// A URL clients can `PUT` a file to directly for the next `expires_in`, without
+    /// going through this server. The request must be sent with `content_type` as its
+    /// `Content-Type` header.
+    async fn presign_upload(&self, key: &str, content_type: &str, expires_in: Duration) -> anyhow::Result<String>;
+
+    /// A URL the file stored under `key` can be downloaded from for the next `expires_in`,
+    /// even if the storage itself isn't publicly readable.
+    async fn presign_download(&self, key: &str, expires_in: Duration) -> anyhow::Result<String>;
+}
+
+/// Creates the storage selected by `config.storage_backend`.
+pub fn from_config(config: &Config) -> anyhow::Result<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match config.storage_backend {
+        StorageBackend::Local => Arc::new(local::LocalStorage::from_config(config)),
+        StorageBackend::S3 => Arc::new(s3::S3Storage::new(config)?),
+    };
+
+    Ok(storage)
+}
+
+/// Returns the path this server serves stored files under, the path of `Config::media_url`.
+///
+/// When `media_url` points to a CDN, the CDN is expected to pull from the same path here.
+pub fn serve_path(config: &Config) -> String {
+    let url = config.media_url.as_deref().unwrap_or(DEFAULT_URL_PREFIX);
+
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+        None => url,
+    };
+
+    match path.trim_end_matches('/') {
+        "" => DEFAULT_URL_PREFIX.to_string(),
+        path => path.to_string(),
+    }
+}
\ No newline at end of file
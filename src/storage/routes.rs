@@ -0,0 +1,50 @@
+use crate::config::{Config, StorageBackend};
+use crate::storage::{self, local::{self, LocalStorage}, downloads, uploads, INCOMING_PREFIX};
+use axum::{
+    extract::{DefaultBodyLimit, Extension},
+    http::StatusCode,
+    routing::{get, get_service, put},
+    Router,
+};
+use std::sync::Arc;
+use tower_http::services::ServeDir;
+
+pub fn router(config: &Config) -> Router {
+    let serve_path = storage::serve_path(config);
+
+    match config.storage_backend {
+        StorageBackend::Local => local_router(config, &serve_path),
+        // With a `media_url`, files are served from there and this server isn't involved.
+        StorageBackend::S3 if config.media_url.is_some() => Router::new(),
+        StorageBackend::S3 => Router::new()
+            .route(
+                &format!("{}/*key", serve_path),
+                get(downloads::download_file)
+            ),
+    }
+}
+
+fn local_router(config: &Config, serve_path: &str) -> Router {
+    let storage = Arc::new(LocalStorage::from_config(config));
+
+    Router::new()
+        .nest_service(
+            serve_path,
+            get_service(ServeDir::new(&config.storage_path))
+                .handle_error(|e: std::io::Error| async move {
+                    log::error!("failed to serve file: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })
+        )
+        // Presigned uploads are only served once processed, under another key.
+        .route(
+            &format!("{}/{}*key", serve_path, INCOMING_PREFIX),
+            get(|| async { StatusCode::NOT_FOUND })
+        )
+        .route(
+            &format!("{}/*key", local::UPLOAD_PATH),
+            put(uploads::upload_file)
+                .layer(DefaultBodyLimit::max(uploads::MAX_UPLOAD_SIZE))
+        )
+        .layer(Extension(storage))
+}
\ No newline at end of file
@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::storage::{Storage, DEFAULT_URL_PREFIX};
+use anyhow::Context;
+use axum::async_trait;
+use axum::http::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use bytes::Bytes;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::{Bucket, Region};
+use time::Duration;
+
+/// Stores files in a bucket of an S3-compatible object storage, like AWS S3 or MinIO.
+pub struct S3Storage {
+    bucket: Bucket,
+    url_prefix: String,
+}
+
+impl S3Storage {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let bucket_name = config
+            .s3_bucket
+            .as_deref()
+            .context("S3_BUCKET must be set when using the S3 storage backend")?;
+
+        let region = match &config.s3_endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.s3_region.clone(),
+                endpoint: endpoint.trim_end_matches('/').to_string(),
+            },
+            None => config
+                .s3_region
+                .parse()
+                .with_context(|| format!("invalid S3 region {:?}", config.s3_region))?,
+        };
+
+        // Falls back to the usual `AWS_*` environment variables and profile when not set.
+        let credentials = Credentials::new(
+            config.s3_access_key_id.as_deref(),
+            config.s3_secret_access_key.as_deref(),
+            None,
+            None,
+            None,
+        )
+        .context("failed to load S3 credentials")?;
+
+        let mut bucket = Bucket::new(bucket_name, region, credentials)
+            .with_context(|| format!("invalid S3 bucket {:?}", bucket_name))?;
+
+        // MinIO and most self-hosted implementations don't support virtual-hosted buckets.
+        if config.s3_path_style {
+            bucket = bucket.with_path_style();
+        }
+
+        // Buckets are private by default, so without a CDN in front, files are downloaded
+        // through the presigned URLs `storage::routes` redirects to.
+        let url_prefix = config
+            .media_url
+            .as_deref()
+            .unwrap_or(DEFAULT_URL_PREFIX)
+            .trim_end_matches('/')
+            .to_string();
+
+        Ok(Self {
+            bucket,
+            url_prefix,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+        self.bucket
+            .put_object_with_content_type(key, &bytes, content_type)
+            .await
+            .with_context(|| format!("failed to upload {:?} to S3", key))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        match self.bucket.get_object(key).await {
+            Ok(response) => Ok(Some(response.bytes().clone())),
+            Err(S3Error::Http(404, _)) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to download {:?} from S3", key)),
+        }
+    }
+
+    async fn size(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        match self.bucket.head_object(key).await {
+            Ok((head, _)) => Ok(head.content_length.and_then(|size| u64::try_from(size).ok())),
+            Err(S3Error::Http(404, _)) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read the metadata of {:?} from S3", key)),
+        }
+    }
+
+    // S3 already treats deleting a missing object as a success.
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.bucket
+            .delete_object(key)
+            .await
+            .with_context(|| format!("failed to delete {:?} from S3", key))?;
+
+        Ok(())
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.url_prefix, key)
+    }
+
+    async fn presign_upload(&self, key: &str, content_type: &str, expires_in: Duration) -> anyhow::Result<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_str(content_type).context("invalid content type")?,
+        );
+
+        self.bucket
+            .presign_put(key, expiry_seconds(expires_in), Some(headers))
+            .with_context(|| format!("failed to presign upload of {:?}", key))
+    }
+
+    async fn presign_download(&self, key: &str, expires_in: Duration) -> anyhow::Result<String> {
+        self.bucket
+            .presign_get(key, expiry_seconds(expires_in), None)
+            .with_context(|| format!("failed to presign download of {:?}", key))
+    }
+}
+
+// S3 rejects presigned URLs valid for more than a week.
+fn expiry_seconds(expires_in: Duration) -> u32 {
+    expires_in.whole_seconds().clamp(1, 7 * 24 * 60 * 60) as u32
+}
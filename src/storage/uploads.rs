@@ -0,0 +1,46 @@
+use crate::{
+    Result,
+    Error,
+    storage::{Storage, local::LocalStorage},
+};
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::sync::Arc;
+
+// Presigned uploads are attachments waiting to be processed, so keep them within the biggest attachment size.
+pub const MAX_UPLOAD_SIZE: usize = 40 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct PresignedQuery {
+    expires: i64,
+    signature: String,
+}
+
+/// Receives a file sent to a URL issued by `LocalStorage::presign_upload`.
+///
+/// This is the local counterpart of a presigned S3 `PUT`, so it doesn't require
+/// authentication, the signature is what allows the upload.
+pub async fn upload_file(
+    Extension(storage): Extension<Arc<LocalStorage>>,
+    Path(key): Path<String>,
+    Query(query): Query<PresignedQuery>,
+    headers: HeaderMap,
+    bytes: Bytes,
+) -> Result<StatusCode> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    if !storage.verify_upload(&key, content_type, query.expires, &query.signature) {
+        return Err(Error::Forbidden);
+    }
+
+    storage.put(&key, content_type, bytes).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
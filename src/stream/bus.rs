@@ -0,0 +1,290 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+// Events buffered for each subscriber before it starts lagging behind.
+const CHANNEL_CAPACITY: usize = 256;
+// Recent events kept to resume streams from `Last-Event-ID`.
+const HISTORY_SIZE: usize = 1024;
+// Events waiting to be written to a client connection. Once full, the subscription stops
+// reading the bus and relies on the history to catch up, so a slow client never slows
+// down publishers.
+const CLIENT_BUFFER: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Message,
+    Reply,
+    Like,
+    Notification,
+    // A message was edited, with the message as it is now.
+    Edit,
+    // A message was deleted, with only its `id`.
+    Delete,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Message => "message",
+            Self::Reply => "reply",
+            Self::Like => "like",
+            Self::Notification => "notification",
+            Self::Edit => "edit",
+            Self::Delete => "delete",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "message" => Some(Self::Message),
+            "reply" => Some(Self::Reply),
+            "like" => Some(Self::Like),
+            "notification" => Some(Self::Notification),
+            "edit" => Some(Self::Edit),
+            "delete" => Some(Self::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Who an event is delivered to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Audience {
+    Everyone,
+    User(Uuid),
+}
+
+#[derive(Debug)]
+pub struct StreamEvent {
+    // Increases by one with each event, clients send back the last one they saw to resume.
+    pub id: u64,
+    pub kind: EventKind,
+    pub audience: Audience,
+    pub data: serde_json::Value,
+}
+
+/// What a subscription hands over to the connection.
+#[derive(Debug)]
+pub enum Outgoing {
+    Event(Arc<StreamEvent>),
+    // Events were missed and can't be replayed, the client has to refetch what it displays.
+    Resync,
+}
+
+#[derive(Default)]
+struct History {
+    last_id: u64,
+    events: VecDeque<Arc<StreamEvent>>,
+}
+
+impl History {
+    /// The events after `last_id`, or `None` if some of them are no longer kept.
+    ///
+    /// An id from the future means the server restarted since, which can't be resumed either.
+    fn since(&self, last_id: u64) -> Option<Vec<Arc<StreamEvent>>> {
+        let first_id = self.events.front().map_or(self.last_id + 1, |event| event.id);
+
+        if last_id > self.last_id || last_id + 1 < first_id {
+            return None;
+        }
+
+        Some(self.events.iter().filter(|event| event.id > last_id).cloned().collect())
+    }
+}
+
+/// In-process broadcast of the events pushed to streaming clients.
+///
+/// Handlers publish once their change is committed, and each connection gets its own
+/// subscription filtered down to the events its user should see.
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<StreamEvent>>,
+    history: Mutex<History>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        Self {
+            sender,
+            history: Mutex::new(History::default()),
+        }
+    }
+
+    pub fn publish(&self, kind: EventKind, audience: Audience, data: impl Serialize) {
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("failed to serialize {} event: {}", kind.as_str(), e);
+                return;
+            }
+        };
+
+        // Sending while holding the lock keeps the history and the channel in the same
+        // order, so a new subscription sees every event exactly once.
+        let mut history = self.history.lock().expect("BUG: event history lock poisoned");
+
+        history.last_id += 1;
+
+        let event = Arc::new(StreamEvent {
+            id: history.last_id,
+            kind,
+            audience,
+            data,
+        });
+
+        if history.events.len() == HISTORY_SIZE {
+            history.events.pop_front();
+        }
+        history.events.push_back(event.clone());
+
+        // Fails when nobody is listening, which is fine.
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribes `user_id` to the events of `kinds`, starting after `last_event_id` if given.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        user_id: Uuid,
+        kinds: Vec<EventKind>,
+        last_event_id: Option<u64>,
+    ) -> mpsc::Receiver<Outgoing> {
+        let (receiver, replay, last_id) = {
+            let history = self.history.lock().expect("BUG: event history lock poisoned");
+            let replay = last_event_id.map(|last_id| history.since(last_id));
+
+            // Without a usable `last_event_id`, the stream starts with the next event.
+            let last_id = match (&replay, last_event_id) {
+                (Some(Some(_)), Some(last_id)) => last_id,
+                _ => history.last_id,
+            };
+
+            (self.sender.subscribe(), replay, last_id)
+        };
+
+        let (tx, rx) = mpsc::channel(CLIENT_BUFFER);
+        let subscription = Subscription {
+            bus: self.clone(),
+            user_id,
+            kinds,
+            last_id,
+            tx,
+        };
+
+        tokio::spawn(subscription.run(receiver, replay));
+
+        rx
+    }
+
+    fn since(&self, last_id: u64) -> Option<Vec<Arc<StreamEvent>>> {
+        self.history
+            .lock()
+            .expect("BUG: event history lock poisoned")
+            .since(last_id)
+    }
+
+    fn last_id(&self) -> u64 {
+        self.history
+            .lock()
+            .expect("BUG: event history lock poisoned")
+            .last_id
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Subscription {
+    bus: Arc<EventBus>,
+    user_id: Uuid,
+    kinds: Vec<EventKind>,
+    // The last event handed to the connection, anything up to it is skipped.
+    last_id: u64,
+    tx: mpsc::Sender<Outgoing>,
+}
+
+impl Subscription {
+    async fn run(
+        mut self,
+        mut receiver: broadcast::Receiver<Arc<StreamEvent>>,
+        replay: Option<Option<Vec<Arc<StreamEvent>>>>,
+    ) {
+        let replayed = match replay {
+            Some(Some(events)) => self.forward_all(events).await,
+            Some(None) => self.tx.send(Outgoing::Resync).await.is_ok(),
+            None => true,
+        };
+
+        if !replayed {
+            return;
+        }
+
+        loop {
+            let event = tokio::select! {
+                event = receiver.recv() => event,
+                // Stop as soon as the client goes away, even if no event comes.
+                _ = self.tx.closed() => return,
+            };
+
+            let delivered = match event {
+                Ok(event) => self.forward(event).await,
+                // The connection couldn't keep up, catch up from the history if we still can.
+                Err(RecvError::Lagged(skipped)) => {
+                    log::debug!("stream subscriber lagged behind by {} events", skipped);
+
+                    match self.bus.since(self.last_id) {
+                        Some(events) => self.forward_all(events).await,
+                        None => {
+                            // After a resync, only events published from now on are of interest.
+                            self.last_id = self.bus.last_id();
+                            self.tx.send(Outgoing::Resync).await.is_ok()
+                        }
+                    }
+                }
+                Err(RecvError::Closed) => return,
+            };
+
+            if !delivered {
+                return;
+            }
+        }
+    }
+
+    async fn forward_all(&mut self, events: Vec<Arc<StreamEvent>>) -> bool {
+        for event in events {
+            if !self.forward(event).await {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Hands `event` over to the connection if it is meant for this user.
+    /// Returns `false` once the connection is gone.
+    async fn forward(&mut self, event: Arc<StreamEvent>) -> bool {
+        if event.id <= self.last_id {
+            return true;
+        }
+        self.last_id = event.id;
+
+        let visible = match event.audience {
+            Audience::Everyone => true,
+            Audience::User(user_id) => user_id == self.user_id,
+        };
+
+        if !visible || !self.kinds.contains(&event.kind) {
+            return true;
+        }
+
+        self.tx.send(Outgoing::Event(event)).await.is_ok()
+    }
+}
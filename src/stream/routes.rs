@@ -0,0 +1,17 @@
+use crate::stream::subscriptions;
+use axum::{
+    routing::get,
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/stream",
+            get(subscriptions::stream_sse)
+        )
+        .route(
+            "/api/stream/ws",
+            get(subscriptions::stream_ws)
+        )
+}
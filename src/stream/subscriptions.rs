@@ -0,0 +1,193 @@
+use crate::{
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::{AuthUser, StreamAuthUser},
+    },
+    stream::bus::{EventKind, Outgoing},
+};
+use axum::{
+    extract::{Extension, Query},
+    extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+    http::HeaderMap,
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// WebSocket clients that don't answer pings for this long are disconnected.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(45);
+// A client that can't take a single frame within this delay is too slow to keep.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+// How often the token of an open stream is checked again, so that revoking it also
+// closes the streams it opened.
+const TOKEN_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct StreamQuery {
+    // Comma-separated event kinds to receive, all of them if missing.
+    types: Option<String>,
+    // For WebSocket clients, which can't send `Last-Event-ID`.
+    last_event_id: Option<u64>,
+}
+
+impl StreamQuery {
+    fn kinds(&self) -> Vec<EventKind> {
+        match &self.types {
+            Some(types) => types.split(',').filter_map(|kind| EventKind::parse(kind.trim())).collect(),
+            None => vec![
+                EventKind::Message,
+                EventKind::Reply,
+                EventKind::Like,
+                EventKind::Notification,
+                EventKind::Edit,
+                EventKind::Delete,
+            ],
+        }
+    }
+}
+
+/// Streams events as Server-Sent Events.
+///
+/// `EventSource` sends back the id of the last event it received in `Last-Event-ID`
+/// when it reconnects, and the stream resumes from there.
+pub async fn stream_sse(
+    StreamAuthUser(auth_user): StreamAuthUser,
+    ctx: Extension<ApiContext>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let last_event_id = headers
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or(query.last_event_id);
+
+    let events = ctx.events.subscribe(auth_user.user_id, query.kinds(), last_event_id);
+    let events = until_revoked(ctx.0, auth_user, events);
+
+    let stream = ReceiverStream::new(events).map(|outgoing| {
+        Ok(match outgoing {
+            Outgoing::Event(event) => Event::default()
+                .id(event.id.to_string())
+                .event(event.kind.as_str())
+                .json_data(&event.data)
+                .expect("BUG: stream events should always serialize"),
+            Outgoing::Resync => Event::default().event("resync").data("{}"),
+        })
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(HEARTBEAT_INTERVAL))
+}
+
+/// Streams events over a WebSocket, as JSON text frames of `{ id, type, data }`.
+///
+/// Resuming is done with the `last_event_id` query parameter.
+pub async fn stream_ws(
+    StreamAuthUser(auth_user): StreamAuthUser,
+    ctx: Extension<ApiContext>,
+    Query(query): Query<StreamQuery>,
+    upgrade: WebSocketUpgrade
+) -> impl IntoResponse {
+    let events = ctx.events.subscribe(auth_user.user_id, query.kinds(), query.last_event_id);
+    let events = until_revoked(ctx.0, auth_user, events);
+
+    upgrade.on_upgrade(move |socket| forward_ws(socket, events))
+}
+
+/// Passes `events` on until the token of `auth_user` is revoked, which then ends the stream.
+fn until_revoked(
+    ctx: ApiContext,
+    auth_user: AuthUser,
+    mut events: mpsc::Receiver<Outgoing>
+) -> mpsc::Receiver<Outgoing> {
+    let (tx, rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        let mut token_check = tokio::time::interval(TOKEN_CHECK_INTERVAL);
+        // The first tick is immediate, and the token was just checked when connecting.
+        token_check.tick().await;
+
+        loop {
+            tokio::select! {
+                outgoing = events.recv() => match outgoing {
+                    Some(outgoing) => {
+                        if tx.send(outgoing).await.is_err() {
+                            return;
+                        }
+                    }
+                    None => return,
+                },
+                _ = token_check.tick() => match auth_user.ensure_current(&ctx).await {
+                    Ok(()) => (),
+                    Err(Error::Unauthorized) => {
+                        log::debug!("closing stream of a revoked token");
+                        return;
+                    }
+                    // The database may only be briefly unavailable, the next check decides.
+                    Err(e) => log::error!("failed to check the token of a stream: {:?}", e),
+                },
+                _ = tx.closed() => return,
+            }
+        }
+    });
+
+    rx
+}
+
+async fn forward_ws(mut socket: WebSocket, mut events: mpsc::Receiver<Outgoing>) {
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_seen = tokio::time::Instant::now();
+
+    loop {
+        let frame = tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return,
+                // Pongs and anything else the client sends only prove it's still there.
+                Some(Ok(_)) => {
+                    last_seen = tokio::time::Instant::now();
+                    continue;
+                }
+            },
+            outgoing = events.recv() => match outgoing {
+                Some(Outgoing::Event(event)) => WsMessage::Text(
+                    json!({
+                        "id": event.id,
+                        "type": event.kind.as_str(),
+                        "data": event.data,
+                    })
+                    .to_string(),
+                ),
+                Some(Outgoing::Resync) => WsMessage::Text(json!({ "type": "resync" }).to_string()),
+                None => return,
+            },
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > HEARTBEAT_TIMEOUT {
+                    log::debug!("closing unresponsive WebSocket stream");
+                    return;
+                }
+                WsMessage::Ping(Vec::new())
+            }
+        };
+
+        match tokio::time::timeout(SEND_TIMEOUT, socket.send(frame)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(_)) => return,
+            Err(_) => {
+                log::debug!("closing WebSocket stream of a client too slow to keep up");
+                return;
+            }
+        }
+    }
+}
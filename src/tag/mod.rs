@@ -0,0 +1,2 @@
+pub mod tags;
+pub mod routes;
@@ -0,0 +1,17 @@
+use crate::tag::tags;
+use axum::{
+    routing::get,
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/tags/:tag/messages",
+            get(tags::get_tag_messages)
+        )
+        .route(
+            "/api/trends/tags",
+            get(tags::get_trending_tags)
+        )
+}
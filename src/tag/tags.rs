@@ -0,0 +1,295 @@
+use crate::{
+    Result,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+    message::{
+        messages::{self, Message, MessageRow},
+        expand::Expand,
+    },
+};
+use uuid::Uuid;
+use serde::Serialize;
+use axum::{
+    Json,
+    extract::{Path, Extension, Query},
+};
+use sqlx::{Sqlite, Transaction};
+use std::collections::{HashMap, HashSet};
+
+// Longer hashtags are ignored rather than truncated, they are almost never intentional.
+const MAX_TAG_LENGTH: usize = 64;
+
+// Uses of a tag count for less the older they are, `(window, weight)` from the most recent.
+// Each use only counts in the first window it falls in.
+const TREND_WINDOWS: [(time::Duration, f64); 3] = [
+    (time::Duration::hours(1), 1.0),
+    (time::Duration::hours(6), 0.5),
+    (time::Duration::hours(24), 0.2),
+];
+
+#[derive(Debug, Serialize)]
+pub struct TrendingTag {
+    name: String,
+    score: f64,
+    // Uses and distinct authors over the whole trend period.
+    uses: i64,
+    authors: i64,
+}
+
+/// Extracts the normalized hashtags of a message, without duplicates, in order of appearance.
+///
+/// A hashtag is a `#` followed by letters, digits or underscores, and must not be only digits
+/// so that things like "issue #42" don't count. It must also start a word, which rules out
+/// URL fragments like `example.com/page#section`.
+pub fn extract(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let mut previous = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let starts_word = previous.is_none_or(|p: char| !(p.is_alphanumeric() || p == '_' || p == '#'));
+        previous = Some(c);
+
+        if c != '#' || !starts_word {
+            continue;
+        }
+
+        let mut end = start + c.len_utf8();
+        while let Some(&(i, next)) = chars.peek() {
+            if !(next.is_alphanumeric() || next == '_') {
+                break;
+            }
+            end = i + next.len_utf8();
+            previous = Some(next);
+            chars.next();
+        }
+
+        if let Some(tag) = normalize(&text[start + 1..end]) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
+
+    tags
+}
+
+/// Lowercases a tag, with or without its leading `#`, or returns `None` if it isn't a valid tag.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.strip_prefix('#').unwrap_or(tag);
+
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LENGTH
+        && tag.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !tag.chars().all(|c| c.is_numeric());
+
+    valid.then(|| tag.to_lowercase())
+}
+
+/// Replaces the tags of a message with the hashtags found in `text`.
+///
+/// Called on create and edit, tags that are still in the text keep their original date
+/// so that editing a message doesn't bump it in the trends.
+pub async fn index(tx: &mut Transaction<'_, Sqlite>, message_id: Uuid, text: &str) -> Result<()> {
+    let names = extract(text);
+
+    let current = sqlx::query!(
+        r#"
+            select tag.name as "name!"
+            from message_tag
+            inner join tag on tag.id = message_tag.tag_id
+            where message_tag.message_id = $1
+        "#,
+        message_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for row in current.iter().filter(|row| !names.contains(&row.name)) {
+        sqlx::query!(
+            r#"
+                delete from message_tag
+                where message_id = $1 and tag_id = (select id from tag where name = $2)
+            "#,
+            message_id,
+            row.name
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    for name in names.iter().filter(|name| !current.iter().any(|row| &row.name == *name)) {
+        let new_id = Uuid::new_v4();
+
+        let tag_id = sqlx::query_scalar!(
+            r#"
+                insert into tag (id, name)
+                values ($1, $2)
+                on conflict (name) do update set name = excluded.name
+                returning id as "id!: Uuid"
+            "#,
+            new_id,
+            name
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "insert into message_tag (message_id, tag_id) values ($1, $2)",
+            message_id,
+            tag_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Lists the messages using a hashtag, most recent first.
+///
+/// `tag` is normalized, so `/api/tags/Rust/messages` and `/api/tags/rust/messages` are the same.
+pub async fn get_tag_messages(
+    auth_user: Option<AuthUser>,
+    ctx: Extension<ApiContext>,
+    Path(tag): Path<String>,
+    Query(page): Query<Pagination>,
+    Query(expansions): Query<Expand>
+) -> Result<Json<Vec<Message>>> {
+    let Some(tag) = normalize(&tag) else {
+        return Ok(Json(Vec::new()));
+    };
+
+    let rows = sqlx::query_as::<_, MessageRow>(&format!(
+        r#"
+            select {}
+            from message
+            inner join message_tag on message_tag.message_id = message.id
+            inner join tag on tag.id = message_tag.tag_id
+            where tag.name = $1 and message.deleted_at is null
+            order by message.created_at desc, message.rowid desc
+            limit $2 offset $3
+        "#,
+        messages::MESSAGE_COLUMNS
+    ))
+    .bind(tag)
+    .bind(page.limit())
+    .bind(page.offset())
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let messages = messages::hydrate(&ctx.db, rows).await?;
+    let viewer_id = auth_user.map(|auth_user| auth_user.user_id);
+
+    Ok(Json(messages::embed(&ctx.db, messages, expansions, viewer_id).await?))
+}
+
+/// Ranks the tags used over the last `TREND_WINDOWS`, weighting recent uses more.
+///
+/// Only the first page is meaningful, `offset` is ignored.
+pub async fn get_trending_tags(
+    ctx: Extension<ApiContext>,
+    Query(page): Query<Pagination>
+) -> Result<Json<Vec<TrendingTag>>> {
+    let (period, _) = TREND_WINDOWS[TREND_WINDOWS.len() - 1];
+    let since = format!("-{} seconds", period.whole_seconds());
+
+    let uses = sqlx::query!(
+        r#"
+            select
+                tag.name as "name!",
+                message_tag.created_at as "created_at!: time::PrimitiveDateTime",
+                message.author_id as "author_id!: Uuid"
+            from message_tag
+            inner join tag on tag.id = message_tag.tag_id
+            inner join message on message.id = message_tag.message_id
+            where message_tag.created_at >= datetime('now', $1) and message.deleted_at is null
+        "#,
+        since
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let now = time::OffsetDateTime::now_utc();
+    let mut trends: HashMap<String, (f64, i64, HashSet<Uuid>)> = HashMap::new();
+
+    for tag_use in uses {
+        let age = now - tag_use.created_at.assume_utc();
+
+        let weight = TREND_WINDOWS
+            .iter()
+            .find(|(window, _)| age <= *window)
+            .map_or(0.0, |(_, weight)| *weight);
+
+        let (score, uses, authors) = trends.entry(tag_use.name).or_default();
+        *score += weight;
+        *uses += 1;
+        authors.insert(tag_use.author_id);
+    }
+
+    let mut trends: Vec<TrendingTag> = trends
+        .into_iter()
+        .map(|(name, (score, uses, authors))| TrendingTag {
+            name,
+            score,
+            uses,
+            authors: authors.len() as i64,
+        })
+        .collect();
+
+    trends.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+    trends.truncate(page.limit() as usize);
+
+    Ok(Json(trends))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_finds_hashtags_in_order_without_duplicates() {
+        assert_eq!(
+            extract("Trying #Rust and #axum, #rust again"),
+            vec!["rust".to_string(), "axum".to_string()]
+        );
+    }
+
+    #[test]
+    fn extract_stops_at_punctuation() {
+        assert_eq!(extract("#kiwi! #web-dev"), vec!["kiwi".to_string(), "web".to_string()]);
+    }
+
+    #[test]
+    fn extract_ignores_numbers_and_url_fragments() {
+        assert!(extract("see issue #42").is_empty());
+        assert!(extract("https://example.com/page#section").is_empty());
+        assert!(extract("a##b").is_empty());
+    }
+
+    #[test]
+    fn extract_keeps_unicode_letters() {
+        assert_eq!(extract("#Café au lait"), vec!["café".to_string()]);
+    }
+
+    #[test]
+    fn normalize_lowercases_and_strips_the_hash() {
+        assert_eq!(normalize("#Rust"), Some("rust".to_string()));
+        assert_eq!(normalize("rust_lang"), Some("rust_lang".to_string()));
+        assert_eq!(normalize("2023rust"), Some("2023rust".to_string()));
+    }
+
+    #[test]
+    fn normalize_rejects_invalid_tags() {
+        assert_eq!(normalize(""), None);
+        assert_eq!(normalize("#"), None);
+        assert_eq!(normalize("123"), None);
+        assert_eq!(normalize("web-dev"), None);
+        assert_eq!(normalize(&"a".repeat(MAX_TAG_LENGTH + 1)), None);
+        assert_eq!(normalize(&"a".repeat(MAX_TAG_LENGTH)), Some("a".repeat(MAX_TAG_LENGTH)));
+    }
+}
\ No newline at end of file
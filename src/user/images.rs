@@ -0,0 +1,165 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+    },
+    user::users::{self, User},
+    media::processing::{self, Sniffed},
+};
+use axum::{
+    Json,
+    extract::{Extension, Multipart},
+};
+use bytes::Bytes;
+use uuid::Uuid;
+
+// Maximum sizes of uploaded profile images.
+pub const MAX_AVATAR_SIZE: usize = 2 * 1024 * 1024;
+pub const MAX_BANNER_SIZE: usize = 5 * 1024 * 1024;
+
+#[derive(Clone, Copy)]
+enum ProfileImage {
+    Avatar,
+    Banner,
+}
+
+impl ProfileImage {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Avatar => "avatar",
+            Self::Banner => "banner",
+        }
+    }
+
+    fn max_size(self) -> usize {
+        match self {
+            Self::Avatar => MAX_AVATAR_SIZE,
+            Self::Banner => MAX_BANNER_SIZE,
+        }
+    }
+}
+
+pub async fn upload_avatar(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    multipart: Multipart
+) -> Result<Json<User>> {
+    upload(auth_user, ctx, multipart, ProfileImage::Avatar).await
+}
+
+pub async fn upload_banner(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    multipart: Multipart
+) -> Result<Json<User>> {
+    upload(auth_user, ctx, multipart, ProfileImage::Banner).await
+}
+
+/// Stores the `image` field of the multipart body and points the user's
+/// avatar or banner to it, removing the image it replaces.
+async fn upload(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    multipart: Multipart,
+    kind: ProfileImage,
+) -> Result<Json<User>> {
+    let bytes = read_image(multipart, kind.max_size()).await?;
+
+    let Some(Sniffed::Image(format)) = processing::sniff(&bytes) else {
+        return Err(Error::unprocessable_entity([("image", "must be a PNG, JPEG, GIF or WebP image")]));
+    };
+
+    // Strips the metadata, profile images don't need thumbnails.
+    let image = processing::process_image(bytes, format, &[]).await?;
+
+    let key = format!("users/{}/{}-{}.{}", auth_user.user_id, kind.name(), Uuid::new_v4(), image.extension);
+    ctx.storage.put(&key, image.content_type, image.bytes).await?;
+
+    let url = ctx.storage.url(&key);
+
+    let previous = match set_image(&ctx, auth_user.user_id, kind, &url).await {
+        Ok(previous) => previous,
+        Err(e) => {
+            // Nothing points to the new image, don't leave it behind.
+            if let Err(e) = ctx.storage.delete(&key).await {
+                log::warn!("failed to remove unused {}: {:?}", kind.name(), e);
+            }
+            return Err(e);
+        }
+    };
+
+    // Images set through `update_user` may be hosted elsewhere, only remove our own.
+    if let Some(key) = previous.and_then(|url| ctx.storage.key(&url)) {
+        if let Err(e) = ctx.storage.delete(&key).await {
+            log::warn!("failed to remove previous {}: {:?}", kind.name(), e);
+        }
+    }
+
+    users::get_current_user(auth_user, ctx).await
+}
+
+/// Points the user's avatar or banner to `url`, returns the URL it replaces.
+async fn set_image(ctx: &ApiContext, user_id: Uuid, kind: ProfileImage, url: &str) -> Result<Option<String>> {
+    let kind_name = kind.name();
+
+    let mut tx = ctx.db.begin().await?;
+
+    let previous = sqlx::query!(
+        "select image, banner from user where id = $1",
+        user_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            update user
+            set image = case when $3 = 'avatar' then $1 else image end,
+                banner = case when $3 = 'banner' then $1 else banner end,
+                updated_at = current_timestamp
+            where id = $2
+        "#,
+        url,
+        user_id,
+        kind_name
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(match kind {
+        ProfileImage::Avatar => previous.image,
+        ProfileImage::Banner => previous.banner,
+    })
+}
+
+async fn read_image(mut multipart: Multipart, max_size: usize) -> Result<Bytes> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| Error::unprocessable_entity([("image", "invalid multipart body")]))?
+    {
+        if field.name() != Some("image") {
+            continue;
+        }
+
+        let bytes = field
+            .bytes()
+            .await
+            .map_err(|_| Error::unprocessable_entity([("image", "failed to read upload")]))?;
+
+        if bytes.len() > max_size {
+            return Err(Error::unprocessable_entity([(
+                "image",
+                format!("must be smaller than {} MiB", max_size / 1024 / 1024),
+            )]));
+        }
+
+        return Ok(bytes);
+    }
+
+    Err(Error::unprocessable_entity([("image", "is missing")]))
+}
@@ -0,0 +1,371 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::{AdminUser, RequestMeta},
+    },
+    audit::events::{self, Event},
+};
+use uuid::Uuid;
+use serde::Serialize;
+use axum::{
+    Json,
+    extract::{Path, Extension},
+};
+use sqlx::SqlitePool;
+use std::net::IpAddr;
+use time::PrimitiveDateTime;
+
+// Number of failed attempts allowed before we start slowing the client down.
+const FREE_ATTEMPTS: i64 = 3;
+// Upper bound of the exponential backoff applied between failed attempts.
+const MAX_BACKOFF_SECS: i64 = 5 * 60;
+// How long an account (or IP) is locked once it reaches its threshold.
+const LOCKOUT_SECS: i64 = 60 * 60;
+// Failed attempts on a single account before it gets locked.
+const ACCOUNT_LOCKOUT_THRESHOLD: i64 = 10;
+// Failed attempts from a single IP before it gets locked.
+// This is higher than for accounts since many users can share the same IP behind a NAT.
+const IP_LOCKOUT_THRESHOLD: i64 = 50;
+
+#[derive(Debug, Serialize)]
+pub struct LockoutEvent {
+    id: Uuid,
+    user_id: Option<Uuid>,
+    key: String,
+    ip: String,
+    locked_until: PrimitiveDateTime,
+    created_at: PrimitiveDateTime,
+    unlocked_at: Option<PrimitiveDateTime>,
+    unlocked_by: Option<Uuid>,
+}
+
+/// Keeps track of failed login attempts for both the account and the IP of a login request.
+///
+/// Accounts are keyed by email rather than by user id so that unknown emails get throttled
+/// exactly like registered ones, which avoids leaking which emails exist.
+pub struct LoginThrottle {
+    account_key: String,
+    ip_key: String,
+    ip: IpAddr,
+}
+
+impl LoginThrottle {
+    pub fn new(email: &str, ip: IpAddr) -> Self {
+        Self {
+            account_key: account_key(email),
+            ip_key: format!("ip:{}", ip),
+            ip,
+        }
+    }
+
+    /// Returns `429 Too Many Requests` if either the account or the IP is currently locked.
+    pub async fn check(&self, db: &SqlitePool) -> Result<()> {
+        let retry_after = sqlx::query_scalar!(
+            r#"
+                select
+                    max(cast((julianday(locked_until) - julianday('now')) * 86400 as integer) + 1)
+                        as "retry_after: i64"
+                from login_failure
+                where key in ($1, $2) and locked_until > current_timestamp
+            "#,
+            self.account_key,
+            self.ip_key
+        )
+        .fetch_one(db)
+        .await?;
+
+        match retry_after {
+            Some(retry_after) => Err(Error::TooManyRequests { retry_after }),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a failed attempt and applies the backoff or lockout if needed.
+    pub async fn fail(&self, db: &SqlitePool, user_id: Option<Uuid>) -> Result<()> {
+        let mut tx = db.begin().await?;
+
+        for (key, threshold) in [
+            (&self.account_key, ACCOUNT_LOCKOUT_THRESHOLD),
+            (&self.ip_key, IP_LOCKOUT_THRESHOLD),
+        ] {
+            // The counter is reset if the last failure is more than an hour old.
+            let failed_count = sqlx::query_scalar!(
+                r#"
+                    insert into login_failure (key, failed_count)
+                    values ($1, 1)
+                    on conflict (key) do update
+                    set failed_count = case
+                            when last_failed_at < datetime('now', '-1 hour') then 1
+                            else failed_count + 1
+                        end,
+                        last_failed_at = current_timestamp
+                    returning failed_count as "failed_count!: i64"
+                "#,
+                key
+            )
+            .fetch_one(&mut tx)
+            .await?;
+
+            let Some(lock_secs) = lock_duration(failed_count, threshold) else {
+                continue;
+            };
+
+            let locked_until = sqlx::query_scalar!(
+                r#"
+                    update login_failure
+                    set locked_until = datetime('now', '+' || $2 || ' seconds')
+                    where key = $1
+                    returning locked_until as "locked_until!: PrimitiveDateTime"
+                "#,
+                key,
+                lock_secs
+            )
+            .fetch_one(&mut tx)
+            .await?;
+
+            if failed_count == threshold {
+                let id = Uuid::new_v4();
+                let ip = self.ip.to_string();
+                // Only account lockouts are tied to a user.
+                let user_id = if key == &self.account_key { user_id } else { None };
+
+                sqlx::query!(
+                    r#"
+                        insert into lockout_event (id, user_id, key, ip, locked_until)
+                        values ($1, $2, $3, $4, $5)
+                    "#,
+                    id,
+                    user_id,
+                    key,
+                    ip,
+                    locked_until
+                )
+                .execute(&mut tx)
+                .await?;
+
+                log::warn!("login locked for {} until {}", key, locked_until);
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Clears the account's failure counter after a successful login.
+    ///
+    /// The IP's counter is kept, otherwise logging into an account of one's own now and then
+    /// would reset the budget for guessing the passwords of other accounts.
+    pub async fn succeed(&self, db: &SqlitePool) -> Result<()> {
+        sqlx::query!(
+            "delete from login_failure where key = $1",
+            self.account_key
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+pub async fn get_lockouts(
+    _: AdminUser,
+    ctx: Extension<ApiContext>
+) -> Result<Json<Vec<LockoutEvent>>> {
+    let lockouts = sqlx::query_as!(
+        LockoutEvent,
+        r#"
+            select
+                id as "id!: Uuid",
+                user_id as "user_id: Uuid",
+                key,
+                ip,
+                locked_until as "locked_until!: PrimitiveDateTime",
+                created_at as "created_at!: PrimitiveDateTime",
+                unlocked_at as "unlocked_at: PrimitiveDateTime",
+                unlocked_by as "unlocked_by: Uuid"
+            from lockout_event
+            where unlocked_at is null and locked_until > current_timestamp
+            order by created_at desc
+        "#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(lockouts))
+}
+
+pub async fn unlock_user(
+    admin: AdminUser,
+    ctx: Extension<ApiContext>,
+    meta: RequestMeta,
+    Path(id): Path<Uuid>
+) -> Result<()> {
+    let email = sqlx::query_scalar!("select email from user where id = $1", id)
+        .fetch_optional(&ctx.db)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let key = account_key(&email);
+
+    let mut tx = ctx.db.begin().await?;
+
+    sqlx::query!("delete from login_failure where key = $1", key)
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query!(
+        r#"
+            update lockout_event
+            set unlocked_at = current_timestamp,
+                unlocked_by = $1
+            where key = $2 and unlocked_at is null
+        "#,
+        admin.user_id,
+        key
+    )
+    .execute(&mut tx)
+    .await?;
+
+    events::record(&mut tx, &meta, Event::AccountUnlocked, Some(id), Some(admin.user_id)).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+fn account_key(email: &str) -> String {
+    format!("email:{}", email.to_lowercase())
+}
+
+/// Returns for how many seconds a key should be locked after `failed_count` failures.
+fn lock_duration(failed_count: i64, threshold: i64) -> Option<i64> {
+    if failed_count >= threshold {
+        Some(LOCKOUT_SECS)
+    } else if failed_count > FREE_ATTEMPTS {
+        // 2, 4, 8, ... seconds.
+        let exponent = (failed_count - FREE_ATTEMPTS).min(32) as u32;
+        Some(2i64.pow(exponent).min(MAX_BACKOFF_SECS))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use sqlx::Executor;
+
+    async fn database() -> SqlitePool {
+        // A single connection, since every connection to `:memory:` gets its own database.
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        db.execute(include_str!("../../migrations/login_failure.sql")).await.unwrap();
+        db.execute(include_str!("../../migrations/lockout_event.sql")).await.unwrap();
+
+        db
+    }
+
+    async fn lockout_events(db: &SqlitePool, key: &str) -> i64 {
+        sqlx::query_scalar("select count(*) from lockout_event where key = $1")
+            .bind(key)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn delay_doubles_after_the_free_attempts() {
+        for failed_count in 1..=FREE_ATTEMPTS {
+            assert_eq!(lock_duration(failed_count, ACCOUNT_LOCKOUT_THRESHOLD), None);
+        }
+
+        assert_eq!(lock_duration(4, ACCOUNT_LOCKOUT_THRESHOLD), Some(2));
+        assert_eq!(lock_duration(5, ACCOUNT_LOCKOUT_THRESHOLD), Some(4));
+        assert_eq!(lock_duration(6, ACCOUNT_LOCKOUT_THRESHOLD), Some(8));
+        assert_eq!(lock_duration(9, ACCOUNT_LOCKOUT_THRESHOLD), Some(64));
+    }
+
+    #[test]
+    fn delay_is_capped_below_the_threshold() {
+        assert_eq!(lock_duration(12, IP_LOCKOUT_THRESHOLD), Some(MAX_BACKOFF_SECS));
+        assert_eq!(lock_duration(IP_LOCKOUT_THRESHOLD - 1, IP_LOCKOUT_THRESHOLD), Some(MAX_BACKOFF_SECS));
+    }
+
+    #[test]
+    fn threshold_locks_for_the_full_duration() {
+        assert_eq!(lock_duration(ACCOUNT_LOCKOUT_THRESHOLD, ACCOUNT_LOCKOUT_THRESHOLD), Some(LOCKOUT_SECS));
+        assert_eq!(lock_duration(ACCOUNT_LOCKOUT_THRESHOLD + 1, ACCOUNT_LOCKOUT_THRESHOLD), Some(LOCKOUT_SECS));
+        assert_eq!(lock_duration(IP_LOCKOUT_THRESHOLD, IP_LOCKOUT_THRESHOLD), Some(LOCKOUT_SECS));
+    }
+
+    #[tokio::test]
+    async fn account_is_locked_at_its_tenth_failure() {
+        let db = database().await;
+        let key = account_key("alice@example.com");
+
+        // Each attempt comes from another IP, so only the account counter adds up.
+        for attempt in 1..ACCOUNT_LOCKOUT_THRESHOLD {
+            LoginThrottle::new("alice@example.com", ip(attempt as u8)).fail(&db, None).await.unwrap();
+        }
+        assert_eq!(lockout_events(&db, &key).await, 0);
+
+        let throttle = LoginThrottle::new("Alice@example.com", ip(100));
+        throttle.fail(&db, None).await.unwrap();
+
+        assert_eq!(lockout_events(&db, &key).await, 1);
+        assert!(matches!(
+            throttle.check(&db).await,
+            Err(Error::TooManyRequests { retry_after }) if retry_after > MAX_BACKOFF_SECS
+        ));
+    }
+
+    #[tokio::test]
+    async fn ip_is_locked_at_its_fiftieth_failure() {
+        let db = database().await;
+        let key = format!("ip:{}", ip(1));
+
+        // Each attempt targets another account, so only the IP counter adds up.
+        for attempt in 1..IP_LOCKOUT_THRESHOLD {
+            LoginThrottle::new(&format!("user{}@example.com", attempt), ip(1)).fail(&db, None).await.unwrap();
+        }
+        assert_eq!(lockout_events(&db, &key).await, 0);
+
+        LoginThrottle::new("last@example.com", ip(1)).fail(&db, None).await.unwrap();
+
+        assert_eq!(lockout_events(&db, &key).await, 1);
+        assert!(matches!(
+            LoginThrottle::new("someone@example.com", ip(1)).check(&db).await,
+            Err(Error::TooManyRequests { retry_after }) if retry_after > MAX_BACKOFF_SECS
+        ));
+    }
+
+    #[tokio::test]
+    async fn success_clears_only_the_account() {
+        let db = database().await;
+        let throttle = LoginThrottle::new("alice@example.com", ip(1));
+
+        for _ in 0..2 {
+            throttle.fail(&db, None).await.unwrap();
+        }
+        throttle.succeed(&db).await.unwrap();
+
+        let keys: Vec<(String, i64)> = sqlx::query_as("select key, failed_count from login_failure")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+
+        assert_eq!(keys, vec![(format!("ip:{}", ip(1)), 2)]);
+    }
+}
@@ -1,2 +1,4 @@
-mod users;
+pub mod users;
+mod lockouts;
+mod images;
 pub mod routes;
\ No newline at end of file
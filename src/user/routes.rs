@@ -1,10 +1,24 @@
-use crate::user::users;
+use crate::user::{users, lockouts, images};
 use axum::{
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    routing::{get, post, put},
     Router,
 };
 
 pub fn router() -> Router {
+    // Each upload gets a body limit matching its maximum size, leaving some room for the multipart overhead.
+    let uploads = Router::new()
+        .route(
+            "/api/user/avatar",
+            put(images::upload_avatar)
+                .layer(DefaultBodyLimit::max(images::MAX_AVATAR_SIZE + 64 * 1024))
+        )
+        .route(
+            "/api/user/banner",
+            put(images::upload_banner)
+                .layer(DefaultBodyLimit::max(images::MAX_BANNER_SIZE + 64 * 1024))
+        );
+
     Router::new()
         .route(
             "/api/users",
@@ -19,8 +33,21 @@ pub fn router() -> Router {
             get(users::get_current_user)
             .put(users::update_user)
         )
+        .route(
+            "/api/user/tokens/revoke",
+            post(users::revoke_tokens)
+        )
         .route(
             "/api/user/:id",
             get(users::get_user)
         )
+        .route(
+            "/api/admin/lockouts",
+            get(lockouts::get_lockouts)
+        )
+        .route(
+            "/api/admin/users/:id/unlock",
+            post(lockouts::unlock_user)
+        )
+        .merge(uploads)
 }
\ No newline at end of file
@@ -4,8 +4,10 @@ use crate::{
     ResultExt,
     router::{
         server::ApiContext,
-        extractor::AuthUser,
+        extractor::{AuthUser, RequestMeta},
     },
+    user::lockouts::LoginThrottle,
+    audit::events::{self, Event},
 };
 use anyhow::Context;
 use uuid::Uuid;
@@ -15,23 +17,29 @@ use axum::{
     extract::{Path, Extension},
 };
 use time::PrimitiveDateTime;
+use sqlx::SqlitePool;
+use tokio::sync::OnceCell;
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHash};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, Version};
 
 #[derive(Debug, Serialize)]
 pub struct User {
     username: String,
+    display_name: String,
     email: String,
     token: String,
     bio: String,
     image: Option<String>,
+    banner: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct UserProfile {
     username: String,
+    display_name: String,
     bio: String,
     image: Option<String>,
+    banner: Option<String>,
     created_at: PrimitiveDateTime,
 }
 
@@ -54,16 +62,20 @@ pub struct UpdateUser {
     username: Option<String>,
     email: Option<String>,
     password: Option<String>,
+    display_name: Option<String>,
     bio: Option<String>,
     image: Option<String>,
 }
 
 pub async fn create_user(
     ctx: Extension<ApiContext>,
+    meta: RequestMeta,
     Json(req): Json<UserRequest>
 ) -> Result<Json<User>> {
+    validate_username(&req.username)?;
+
     let id = Uuid::new_v4();
-    let password_hash = hash_password(req.password).await?;
+    let password_hash = hash_password(&ctx, req.password).await?;
 
     let user_id = sqlx::query_scalar!(
         r#"
@@ -86,58 +98,125 @@ pub async fn create_user(
         Error::unprocessable_entity([("email", "email taken")])
     })?;
 
+    events::record(&ctx.db, &meta, Event::TokenCreated, Some(user_id), Some(user_id)).await?;
+
     Ok(Json(
         User {
             username: req.username,
             email: req.email,
-            token: AuthUser { user_id }.to_jwt(&ctx),
+            token: AuthUser { user_id, token_version: 0, token_id: None }.to_jwt(&ctx),
+            display_name: "".to_string(),
             bio: "".to_string(),
             image: None,
+            banner: None,
         }
     ))
 }
 
 pub async fn login_user(
     ctx: Extension<ApiContext>,
+    meta: RequestMeta,
     Json(req): Json<LoginUser>
 ) -> Result<Json<User>> {
+    let auth_user = authenticate(&ctx, &meta, req.email, req.password).await?;
+
+    events::record(&ctx.db, &meta, Event::TokenCreated, Some(auth_user.user_id), Some(auth_user.user_id)).await?;
+
     let user = sqlx::query!(
         r#"
-            select 
-                id as "id!: Uuid",
-                username,
-                email,
-                bio,
-                image,
-                password_hash
-            from user where email = $1
+            select username, display_name, email, bio, image, banner
+            from user where id = $1
         "#,
-        req.email
+        auth_user.user_id
     )
-    .fetch_optional(&ctx.db)
-    .await?
-    .ok_or(Error::unprocessable_entity([("email", "does not exist")]))?;
-
-    verify_password(req.password, user.password_hash).await?;
+    .fetch_one(&ctx.db)
+    .await?;
 
     Ok(Json(
         User {
             username: user.username,
+            display_name: user.display_name,
             email: user.email,
-            token: AuthUser { user_id: user.id }.to_jwt(&ctx),
+            token: auth_user.to_jwt(&ctx),
             bio: user.bio,
             image: user.image,
+            banner: user.banner,
         }
     ))
 }
 
+/// Checks an email and password, with the throttling and audit events of a login.
+///
+/// Shared by `login_user` and the OAuth routes of the Mastodon API, which issue their own tokens.
+pub async fn authenticate(ctx: &ApiContext, meta: &RequestMeta, email: String, password: String) -> Result<AuthUser> {
+    let throttle = LoginThrottle::new(&email, meta.ip);
+    throttle.check(&ctx.db).await?;
+
+    let user = sqlx::query!(
+        r#"
+            select 
+                id as "id!: Uuid",
+                password_hash,
+                token_version as "token_version!: i64"
+            from user
+            where email = $1
+            -- Users federated from other servers have no password here.
+            and not exists(select 1 from remote_actor where remote_actor.user_id = user.id)
+        "#,
+        email
+    )
+    .fetch_optional(&ctx.db)
+    .await?;
+
+    // Unknown emails and wrong passwords get the exact same response,
+    // so the login route can't be used to find out which emails are registered.
+    let Some(user) = user else {
+        // Still verify against a hash made with the current parameters,
+        // so unknown emails take as long to reject as wrong passwords.
+        let _ = verify_password(ctx, password, dummy_hash(ctx).await?.clone()).await;
+
+        throttle.fail(&ctx.db, None).await?;
+        events::record(&ctx.db, meta, Event::LoginFailed { email }, None, None).await?;
+        return Err(invalid_credentials());
+    };
+
+    match verify_password(ctx, password.clone(), user.password_hash).await {
+        Ok(needs_rehash) => {
+            throttle.succeed(&ctx.db).await?;
+
+            // Now that we know the password, we can upgrade hashes
+            // made with outdated parameters transparently.
+            // The old hash still works, so a failure here shouldn't fail the login.
+            if needs_rehash {
+                if let Err(e) = rehash_password(ctx, user.id, password).await {
+                    log::error!("failed to rehash the password of user {}: {:?}", user.id, e);
+                }
+            }
+        }
+        Err(Error::Unauthorized) => {
+            throttle.fail(&ctx.db, Some(user.id)).await?;
+            events::record(&ctx.db, meta, Event::LoginFailed { email }, Some(user.id), None).await?;
+            return Err(invalid_credentials());
+        }
+        Err(e) => return Err(e),
+    }
+
+    events::record(&ctx.db, meta, Event::LoginSucceeded, Some(user.id), Some(user.id)).await?;
+
+    Ok(AuthUser {
+        user_id: user.id,
+        token_version: user.token_version,
+        token_id: None,
+    })
+}
+
 pub async fn get_current_user(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>
 ) -> Result<Json<User>> {
     let user = sqlx::query!(
         r#"
-            select email, username, bio, image 
+            select email, username, display_name, bio, image, banner
             from user where id = $1
         "#,
         auth_user.user_id
@@ -148,10 +227,12 @@ pub async fn get_current_user(
     Ok(Json(
         User {
             username: user.username,
+            display_name: user.display_name,
             email: user.email,
             token: auth_user.to_jwt(&ctx),
             bio: user.bio,
             image: user.image,
+            banner: user.banner,
         },
     ))
 }
@@ -159,18 +240,32 @@ pub async fn get_current_user(
 pub async fn update_user(
     auth_user: AuthUser,
     ctx: Extension<ApiContext>,
+    meta: RequestMeta,
     Json(req): Json<UpdateUser>
 ) -> Result<Json<User>> {
     if req == UpdateUser::default() {
         return get_current_user(auth_user, ctx).await;
     }
 
+    if let Some(username) = &req.username {
+        validate_username(username)?;
+    }
+
     let password_hash = if let Some(password) = req.password {
-        Some(hash_password(password).await?)
+        Some(hash_password(&ctx, password).await?)
     } else {
         None
     };
 
+    let mut tx = ctx.db.begin().await?;
+
+    let old = sqlx::query!(
+        "select username, email from user where id = $1",
+        auth_user.user_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
     let user = sqlx::query!(
         r#"
             update user
@@ -178,21 +273,26 @@ pub async fn update_user(
                 email = coalesce($2, user.email),
                 password_hash = coalesce($3, user.password_hash),
                 bio = coalesce($4, user.bio),
-                image = coalesce($5, user.image)
-            where id = $6;
-            select username, email, bio, image 
-            from user
-            where id = $6
+                image = coalesce($5, user.image),
+                display_name = coalesce($6, user.display_name)
+            where id = $7
+            returning
+                username as "username!",
+                display_name as "display_name!",
+                email as "email!",
+                bio as "bio!",
+                image,
+                banner
         "#,
         req.username,
         req.email,
         password_hash,
         req.bio,
         req.image,
-        auth_user.user_id,
+        req.display_name,
         auth_user.user_id
     )
-    .fetch_one(&ctx.db)
+    .fetch_one(&mut tx)
     .await
     .on_constraint("user_username_key", |_| {
         Error::unprocessable_entity([("username", "username taken")])
@@ -200,14 +300,86 @@ pub async fn update_user(
     .on_constraint("user_email_key", |_| {
         Error::unprocessable_entity([("email", "email taken")])
     })?;
+
+    let mut changes = Vec::new();
+
+    if password_hash.is_some() {
+        changes.push(Event::PasswordChanged);
+    }
+    if old.email != user.email {
+        changes.push(Event::EmailChanged { old: old.email, new: user.email.clone() });
+    }
+    if old.username != user.username {
+        changes.push(Event::UsernameChanged { old: old.username, new: user.username.clone() });
+    }
+
+    for event in changes {
+        events::record(&mut tx, &meta, event, Some(auth_user.user_id), Some(auth_user.user_id)).await?;
+    }
+
+    tx.commit().await?;
     
     Ok(Json(
         User {
             username: user.username,
+            display_name: user.display_name,
+            email: user.email,
+            token: auth_user.to_jwt(&ctx),
+            bio: user.bio,
+            image: user.image,
+            banner: user.banner,
+        }
+    ))
+}
+
+/// Invalidates every token issued so far for the current user, including the one used
+/// for this request, and returns the user with a fresh token.
+pub async fn revoke_tokens(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    meta: RequestMeta
+) -> Result<Json<User>> {
+    let mut tx = ctx.db.begin().await?;
+
+    let user = sqlx::query!(
+        r#"
+            update user
+            set token_version = token_version + 1
+            where id = $1
+            returning
+                username as "username!",
+                display_name as "display_name!",
+                email as "email!",
+                bio as "bio!",
+                image,
+                banner,
+                token_version as "token_version!: i64"
+        "#,
+        auth_user.user_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    events::record(&mut tx, &meta, Event::TokensRevoked, Some(auth_user.user_id), Some(auth_user.user_id)).await?;
+    events::record(&mut tx, &meta, Event::TokenCreated, Some(auth_user.user_id), Some(auth_user.user_id)).await?;
+
+    tx.commit().await?;
+
+    let auth_user = AuthUser {
+        user_id: auth_user.user_id,
+        token_version: user.token_version,
+        token_id: None,
+    };
+
+    Ok(Json(
+        User {
+            username: user.username,
+            display_name: user.display_name,
             email: user.email,
             token: auth_user.to_jwt(&ctx),
             bio: user.bio,
             image: user.image,
+            banner: user.banner,
         }
     ))
 }
@@ -222,8 +394,10 @@ pub async fn get_user(
         r#"
             select 
                 username,
+                display_name,
                 bio,
                 image,
+                banner,
                 created_at as "created_at!: PrimitiveDateTime"
             from user
             where id = $1
@@ -237,32 +411,130 @@ pub async fn get_user(
     Ok(Json(user))
 }
 
-async fn hash_password(password: String) -> Result<String> {
+/// Local usernames can't contain `@`, which is kept for the `name@domain` usernames
+/// of users from other servers.
+fn validate_username(username: &str) -> Result<()> {
+    if username.contains('@') {
+        return Err(Error::unprocessable_entity([("username", "must not contain @")]));
+    }
+
+    Ok(())
+}
+
+fn invalid_credentials() -> Error {
+    Error::unprocessable_entity([("email or password", "is invalid")])
+}
+
+fn argon2(ctx: &ApiContext) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, ctx.argon2_params.clone())
+}
+
+async fn hash_password(ctx: &ApiContext, password: String) -> Result<String> {
+    let argon2 = argon2(ctx);
+
+    // Wait for our turn before occupying a blocking thread.
+    let permit = ctx.password_hashing
+        .clone()
+        .acquire_owned()
+        .await
+        .context("password hashing semaphore closed")?;
+
     // Argon2 hashing is designed to be computationally intensive,
     // so we need to do this on a blocking thread.
-    Ok(tokio::task::spawn_blocking(move || -> Result<String> {
+    tokio::task::spawn_blocking(move || -> Result<String> {
+        let _permit = permit;
         let salt = SaltString::generate(rand::thread_rng());
         Ok(
-            PasswordHash::generate(Argon2::default(), password, &salt)
+            PasswordHash::generate(argon2, password, &salt)
                 .map_err(|e| anyhow::anyhow!("failed to generate password hash: {}", e))?
                 .to_string(),
         )
     })
     .await
-    .context("panic in generating password hash")??)
+    .context("panic in generating password hash")?
+}
+
+async fn rehash_password(ctx: &ApiContext, user_id: Uuid, password: String) -> Result<()> {
+    let password_hash = hash_password(ctx, password).await?;
+
+    sqlx::query!(
+        "update user set password_hash = $1 where id = $2",
+        password_hash,
+        user_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
 }
 
-async fn verify_password(password: String, password_hash: String) -> Result<()> {
-    Ok(tokio::task::spawn_blocking(move || -> Result<()> {
+/// Verifies `password` against `password_hash`.
+///
+/// On success, returns `true` if the hash was generated with an algorithm or parameters
+/// other than the ones currently configured, meaning the password should be rehashed.
+async fn verify_password(ctx: &ApiContext, password: String, password_hash: String) -> Result<bool> {
+    let argon2 = argon2(ctx);
+
+    let permit = ctx.password_hashing
+        .clone()
+        .acquire_owned()
+        .await
+        .context("password hashing semaphore closed")?;
+
+    tokio::task::spawn_blocking(move || -> Result<bool> {
+        let _permit = permit;
         let hash = PasswordHash::new(&password_hash)
             .map_err(|e| anyhow::anyhow!("invalid password hash: {}", e))?;
 
-        hash.verify_password(&[&Argon2::default()], password)
+        hash.verify_password(&[&argon2], password)
             .map_err(|e| match e {
                 argon2::password_hash::Error::Password => Error::Unauthorized,
                 _ => anyhow::anyhow!("failed to verify password hash: {}", e).into(),
-            })
+            })?;
+
+        Ok(is_outdated(&argon2, &hash))
     })
     .await
-    .context("panic in verifying password hash")??)
-}
\ No newline at end of file
+    .context("panic in verifying password hash")?
+}
+
+/// Returns a hash of a random password, only ever used to spend the time of a verification.
+async fn dummy_hash(ctx: &ApiContext) -> Result<&'static String> {
+    static DUMMY_HASH: OnceCell<String> = OnceCell::const_new();
+
+    DUMMY_HASH
+        .get_or_try_init(|| hash_password(ctx, Uuid::new_v4().to_string()))
+        .await
+}
+
+/// Sets or clears the `is_admin` flag of the user with `email`, returns `false` if there is none.
+///
+/// Used by the `set-admin` command, there is deliberately no route for it.
+pub async fn set_admin(db: &SqlitePool, email: &str, is_admin: bool) -> Result<bool> {
+    let updated = sqlx::query!(
+        "update user set is_admin = $1 where email = $2",
+        is_admin,
+        email
+    )
+    .execute(db)
+    .await?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
+
+fn is_outdated(argon2: &Argon2, hash: &PasswordHash) -> bool {
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into()) {
+        return true;
+    }
+
+    match Params::try_from(hash) {
+        Ok(params) => {
+            let current = argon2.params();
+            params.m_cost() != current.m_cost()
+                || params.t_cost() != current.t_cost()
+                || params.p_cost() != current.p_cost()
+        }
+        Err(_) => true,
+    }
+}
@@ -0,0 +1,156 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::Url;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Keeps webhooks and federation from reaching this server or its network, which would let
+/// anyone send requests to internal services.
+///
+/// Only used with `Config::webhook_allow_private_addresses` (for webhooks) and
+/// `Config::federation_allow_private_addresses` (for federation) off, which is the default.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    // Checking here rather than only when the webhook is registered also covers hostnames
+    // that get pointed to an internal address afterwards.
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Checks that `url` is an http or https URL only resolving to public addresses,
+/// returns why it isn't otherwise.
+pub async fn check_url(url: &str) -> Result<(), &'static str> {
+    let url = parse(url)?;
+
+    if let Some(ip) = ip_literal(&url) {
+        return if is_public(ip) { Ok(()) } else { Err("must not point to a private address") };
+    }
+
+    let host = url.host_str().ok_or("must have a host")?;
+    let port = url.port_or_known_default().unwrap_or_default();
+
+    let addrs: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| "must have a host that resolves")?
+        .collect();
+
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+        return Err("must not point to a private address");
+    }
+
+    Ok(())
+}
+
+/// Checks `url` before sending to it. Hostnames are checked by `PublicResolver`,
+/// but IP addresses are used as they are, so they are checked here.
+pub fn check_destination(url: &str) -> Result<(), &'static str> {
+    match ip_literal(&parse(url)?) {
+        Some(ip) if !is_public(ip) => Err("destination address is not allowed"),
+        _ => Ok(()),
+    }
+}
+
+fn parse(url: &str) -> Result<Url, &'static str> {
+    let url = Url::parse(url).map_err(|_| "must be an http or https URL")?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("must be an http or https URL");
+    }
+
+    Ok(url)
+}
+
+fn ip_literal(url: &Url) -> Option<IpAddr> {
+    // IPv6 addresses are written between brackets in URLs.
+    url.host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Whether `ip` is reachable from the internet, `IpAddr::is_global` isn't stable yet.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network", shared address space (carrier-grade NAT), IETF protocol assignments,
+        // benchmarking and reserved.
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, link-local and documentation.
+        || segments[0] & 0xfe00 == 0xfc00
+        || segments[0] & 0xffc0 == 0xfe80
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_public_rejects_internal_addresses() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0",
+            "100.64.0.1", "::1", "::", "fe80::1", "fd00::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} should not be public", ip);
+        }
+
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(is_public(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn check_url_rejects_local_urls() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://[::1]:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://localhost:3000/hook",
+            "ftp://example.com/hook",
+        ] {
+            assert!(check_url(url).await.is_err(), "{} should be rejected", url);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{Result, webhook::addresses::{self, PublicResolver}};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+// How often the queue is checked for deliveries that are due.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+// How many due deliveries are sent per poll.
+const BATCH_SIZE: i64 = 50;
+
+// Delivery is abandoned after this many failed attempts, spread over roughly two hours.
+const MAX_ATTEMPTS: i64 = 8;
+
+// The first retry waits this long, every following one twice as long as the one before.
+const RETRY_BASE_SECONDS: i64 = 30;
+
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Queues `event_type` for the webhooks of `owner_id` that subscribe to it,
+/// and for every global webhook that does.
+///
+/// Nothing is sent here, the worker started by `spawn` picks the deliveries up.
+pub async fn enqueue(db: &SqlitePool, event_type: &str, owner_id: Uuid, data: &impl Serialize) -> Result<()> {
+    let webhook_ids = sqlx::query_scalar!(
+        r#"
+            select id as "id!: Uuid"
+            from webhook
+            where (owner_id = $1 or is_global)
+            and exists(select 1 from json_each(webhook.events) where json_each.value = $2)
+        "#,
+        owner_id,
+        event_type
+    )
+    .fetch_all(db)
+    .await?;
+
+    if webhook_ids.is_empty() {
+        return Ok(());
+    }
+
+    let payload = serde_json::json!({
+        "type": event_type,
+        "created_at": OffsetDateTime::now_utc().unix_timestamp(),
+        "data": data,
+    })
+    .to_string();
+
+    let mut tx = db.begin().await?;
+
+    for webhook_id in webhook_ids {
+        let id = Uuid::new_v4();
+
+        sqlx::query!(
+            "insert into webhook_delivery (id, webhook_id, event_type, payload) values ($1, $2, $3, $4)",
+            id,
+            webhook_id,
+            event_type,
+            payload
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Spawns the task that sends queued deliveries every `POLL_INTERVAL`.
+///
+/// Deliveries to private addresses fail unless `allow_private_addresses` is set, see `addresses`.
+pub fn spawn(db: SqlitePool, allow_private_addresses: bool) {
+    tokio::spawn(async move {
+        let client = client(allow_private_addresses);
+
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = deliver_due(&db, &client, allow_private_addresses).await {
+                log::error!("failed to deliver webhooks: {:?}", e);
+            }
+        }
+    });
+}
+
+fn client(allow_private_addresses: bool) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        // A redirect would resend the signed payload somewhere the owner didn't register.
+        .redirect(reqwest::redirect::Policy::none());
+
+    if !allow_private_addresses {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+
+    builder.build().expect("failed to build the webhook HTTP client")
+}
+
+struct Due {
+    id: Uuid,
+    event_type: String,
+    payload: String,
+    attempts: i64,
+    url: String,
+    secret: String,
+}
+
+async fn deliver_due(db: &SqlitePool, client: &reqwest::Client, allow_private_addresses: bool) -> anyhow::Result<()> {
+    let due = sqlx::query_as!(
+        Due,
+        r#"
+            select
+                webhook_delivery.id as "id!: Uuid",
+                webhook_delivery.event_type as "event_type!",
+                webhook_delivery.payload as "payload!",
+                webhook_delivery.attempts as "attempts!: i64",
+                webhook.url as "url!",
+                webhook.secret as "secret!"
+            from webhook_delivery
+            join webhook on webhook.id = webhook_delivery.webhook_id
+            where webhook_delivery.status = 'pending'
+            and webhook_delivery.next_attempt_at <= current_timestamp
+            order by webhook_delivery.next_attempt_at
+            limit $1
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(db)
+    .await?;
+
+    for delivery in due {
+        let attempts = delivery.attempts + 1;
+
+        match send(client, &delivery, allow_private_addresses).await {
+            Ok(status) => {
+                sqlx::query!(
+                    r#"
+                        update webhook_delivery
+                        set status = 'succeeded', attempts = $1, last_status_code = $2, last_error = null,
+                            delivered_at = current_timestamp
+                        where id = $3
+                    "#,
+                    attempts,
+                    status,
+                    delivery.id
+                )
+                .execute(db)
+                .await?;
+            }
+            Err((status, error)) => {
+                let status_name = if attempts >= MAX_ATTEMPTS { "dead" } else { "pending" };
+                let retry_in = format!("+{} seconds", RETRY_BASE_SECONDS << (attempts - 1).min(20));
+
+                sqlx::query!(
+                    r#"
+                        update webhook_delivery
+                        set status = $1, attempts = $2, last_status_code = $3, last_error = $4,
+                            next_attempt_at = datetime('now', $5)
+                        where id = $6
+                    "#,
+                    status_name,
+                    attempts,
+                    status,
+                    error,
+                    retry_in,
+                    delivery.id
+                )
+                .execute(db)
+                .await?;
+
+                if status_name == "dead" {
+                    log::warn!("webhook delivery {} failed {} times, giving up", delivery.id, attempts);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Posts the payload and returns the response status, or the status (if any) and an error
+/// message when the endpoint didn't answer with a 2xx.
+///
+/// The response body is never kept, it would let the owner read whatever the endpoint returns.
+async fn send(
+    client: &reqwest::Client,
+    delivery: &Due,
+    allow_private_addresses: bool,
+) -> std::result::Result<i64, (Option<i64>, String)> {
+    if !allow_private_addresses {
+        addresses::check_destination(&delivery.url).map_err(|message| (None, message.to_string()))?;
+    }
+
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+
+    let response = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Kiwi-Event", &delivery.event_type)
+        .header("X-Kiwi-Delivery", delivery.id.to_string())
+        .header("X-Kiwi-Signature", signature(&delivery.secret, timestamp, &delivery.payload))
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(status.as_u16() as i64);
+    }
+
+    Err((Some(status.as_u16() as i64), format!("endpoint responded with {}", status)))
+}
+
+/// The `X-Kiwi-Signature` header: `t=<unix timestamp>,v1=<hex HMAC-SHA256 of "<timestamp>.<body>">`.
+///
+/// Receivers recompute the HMAC with their secret, and should reject old timestamps
+/// to guard against replays.
+pub fn signature(secret: &str, timestamp: i64, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC-SHA-256 can accept any key length");
+
+    mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!("t={},v1={}", timestamp, signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::{HeaderMap, StatusCode}, routing::post, Router};
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// Starts a webhook endpoint on a local port, recording what it receives.
+    fn receiver() -> (SocketAddr, Received) {
+        let received = Received::default();
+        let recorded = received.clone();
+
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(move |headers: HeaderMap, body: String| async move {
+                    recorded.lock().unwrap().push((headers, body));
+                    "ok"
+                }),
+            )
+            .route(
+                "/failing",
+                post(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "internal details") }),
+            );
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        (addr, received)
+    }
+
+    fn due(url: String) -> Due {
+        Due {
+            id: Uuid::new_v4(),
+            event_type: "message.created".to_string(),
+            payload: r#"{"type":"message.created"}"#.to_string(),
+            attempts: 0,
+            url,
+            secret: "secret".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn local_receivers_are_refused_by_default() {
+        let (addr, received) = receiver();
+
+        for url in [format!("http://{}/hook", addr), format!("http://localhost:{}/hook", addr.port())] {
+            let result = send(&client(false), &due(url.clone()), false).await;
+
+            assert!(matches!(result, Err((None, _))), "{} should be refused", url);
+        }
+
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn local_receivers_get_signed_deliveries_when_allowed() {
+        let (addr, received) = receiver();
+        let delivery = due(format!("http://{}/hook", addr));
+
+        assert_eq!(send(&client(true), &delivery, true).await, Ok(200));
+
+        let received = received.lock().unwrap();
+        let (headers, body) = &received[0];
+        let signature_header = headers["x-kiwi-signature"].to_str().unwrap();
+        let timestamp: i64 = signature_header[2..signature_header.find(',').unwrap()].parse().unwrap();
+
+        assert_eq!(body, &delivery.payload);
+        assert_eq!(headers["x-kiwi-event"], "message.created");
+        assert_eq!(signature_header, signature("secret", timestamp, &delivery.payload));
+    }
+
+    #[tokio::test]
+    async fn error_responses_are_not_kept() {
+        let delivery = due(format!("http://{}/failing", receiver().0));
+
+        let (status, error) = send(&client(true), &delivery, true).await.unwrap_err();
+
+        assert_eq!(status, Some(500));
+        assert_eq!(error, "endpoint responded with 500 Internal Server Error");
+    }
+}
@@ -0,0 +1,4 @@
+pub mod webhooks;
+pub mod delivery;
+pub mod addresses;
+pub mod routes;
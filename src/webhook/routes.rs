@@ -0,0 +1,26 @@
+use crate::webhook::webhooks;
+use axum::{
+    routing::{get, post, delete},
+    Router,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/api/webhooks",
+            post(webhooks::create_webhook)
+                .get(webhooks::get_webhooks)
+        )
+        .route(
+            "/api/webhooks/:id",
+            delete(webhooks::delete_webhook)
+        )
+        .route(
+            "/api/webhooks/:id/deliveries",
+            get(webhooks::get_deliveries)
+        )
+        .route(
+            "/api/webhooks/:id/deliveries/:delivery_id/retry",
+            post(webhooks::retry_delivery)
+        )
+}
@@ -0,0 +1,294 @@
+use crate::{
+    Result,
+    Error,
+    router::{
+        server::ApiContext,
+        extractor::AuthUser,
+        pagination::Pagination,
+    },
+    webhook::addresses,
+};
+use uuid::Uuid;
+use time::PrimitiveDateTime;
+use serde::{Deserialize, Serialize};
+use axum::{
+    Json,
+    extract::{Path, Extension, Query},
+};
+use rand::RngCore;
+use sqlx::types::Json as SqlJson;
+use sqlx::SqlitePool;
+
+pub const MESSAGE_CREATED: &str = "message.created";
+pub const MESSAGE_LIKED: &str = "message.liked";
+
+const EVENT_TYPES: [&str; 2] = [MESSAGE_CREATED, MESSAGE_LIKED];
+
+// A user can't register more endpoints than this.
+const MAX_WEBHOOKS: i64 = 10;
+
+#[derive(Debug, Serialize)]
+pub struct Webhook {
+    id: Uuid,
+    url: String,
+    events: SqlJson<Vec<String>>,
+    is_global: bool,
+    created_at: PrimitiveDateTime,
+}
+
+/// Only returned when the webhook is created, the secret can't be read back afterwards.
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookRequest {
+    url: String,
+    events: Vec<String>,
+    #[serde(default)]
+    is_global: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookDelivery {
+    id: Uuid,
+    event_type: String,
+    payload: SqlJson<serde_json::Value>,
+    status: String,
+    attempts: i64,
+    next_attempt_at: PrimitiveDateTime,
+    last_status_code: Option<i64>,
+    last_error: Option<String>,
+    created_at: PrimitiveDateTime,
+    delivered_at: Option<PrimitiveDateTime>,
+}
+
+/// Registers an endpoint that gets called for the `events` about the current user's messages.
+///
+/// Admins can set `is_global` to receive the events of every user instead.
+pub async fn create_webhook(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Json(req): Json<WebhookRequest>
+) -> Result<Json<CreatedWebhook>> {
+    if !(req.url.starts_with("https://") || req.url.starts_with("http://")) {
+        return Err(Error::unprocessable_entity([("url", "must be an http or https URL")]));
+    }
+
+    if !ctx.config.webhook_allow_private_addresses {
+        addresses::check_url(&req.url)
+            .await
+            .map_err(|message| Error::unprocessable_entity([("url", message)]))?;
+    }
+
+    if req.events.is_empty() {
+        return Err(Error::unprocessable_entity([("events", "must not be empty")]));
+    }
+
+    if let Some(unknown) = req.events.iter().find(|event| !EVENT_TYPES.contains(&event.as_str())) {
+        return Err(Error::unprocessable_entity([("events", format!("unknown event {:?}", unknown))]));
+    }
+
+    if req.is_global {
+        let is_admin = sqlx::query_scalar!(
+            r#"select is_admin as "is_admin!: bool" from user where id = $1"#,
+            auth_user.user_id
+        )
+        .fetch_one(&ctx.db)
+        .await?;
+
+        if !is_admin {
+            return Err(Error::Forbidden);
+        }
+    }
+
+    let count = sqlx::query_scalar!(
+        r#"select count(*) as "count!: i64" from webhook where owner_id = $1"#,
+        auth_user.user_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if count >= MAX_WEBHOOKS {
+        return Err(Error::unprocessable_entity([(
+            "url",
+            format!("cannot register more than {} webhooks", MAX_WEBHOOKS),
+        )]));
+    }
+
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let secret: String = secret.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    let id = Uuid::new_v4();
+    let mut events = req.events;
+    events.sort();
+    events.dedup();
+    let events = SqlJson(events);
+
+    let webhook = sqlx::query_as!(
+        Webhook,
+        r#"
+            insert into webhook (id, owner_id, url, secret, events, is_global)
+            values ($1, $2, $3, $4, $5, $6)
+            returning
+                id as "id!: Uuid",
+                url as "url!",
+                events as "events!: SqlJson<Vec<String>>",
+                is_global as "is_global!: bool",
+                created_at as "created_at!: PrimitiveDateTime"
+        "#,
+        id,
+        auth_user.user_id,
+        req.url,
+        secret,
+        events,
+        req.is_global
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(Json(CreatedWebhook { webhook, secret }))
+}
+
+pub async fn get_webhooks(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>
+) -> Result<Json<Vec<Webhook>>> {
+    let webhooks = sqlx::query_as!(
+        Webhook,
+        r#"
+            select
+                id as "id!: Uuid",
+                url as "url!",
+                events as "events!: SqlJson<Vec<String>>",
+                is_global as "is_global!: bool",
+                created_at as "created_at!: PrimitiveDateTime"
+            from webhook
+            where owner_id = $1
+            order by created_at
+        "#,
+        auth_user.user_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(webhooks))
+}
+
+/// Removes a webhook along with its pending deliveries and delivery log.
+pub async fn delete_webhook(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>
+) -> Result<()> {
+    let mut tx = ctx.db.begin().await?;
+
+    let deleted = sqlx::query!(
+        "delete from webhook where id = $1 and owner_id = $2",
+        id,
+        auth_user.user_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(Error::NotFound);
+    }
+
+    sqlx::query!("delete from webhook_delivery where webhook_id = $1", id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// The delivery log of a webhook, most recent first.
+pub async fn get_deliveries(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path(id): Path<Uuid>,
+    Query(page): Query<Pagination>
+) -> Result<Json<Vec<WebhookDelivery>>> {
+    ensure_owner(&ctx.db, id, auth_user.user_id).await?;
+
+    let (limit, offset) = (page.limit(), page.offset());
+
+    let deliveries = sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+            select
+                id as "id!: Uuid",
+                event_type as "event_type!",
+                payload as "payload!: SqlJson<serde_json::Value>",
+                status as "status!",
+                attempts as "attempts!: i64",
+                next_attempt_at as "next_attempt_at!: PrimitiveDateTime",
+                last_status_code as "last_status_code: i64",
+                last_error,
+                created_at as "created_at!: PrimitiveDateTime",
+                delivered_at as "delivered_at: PrimitiveDateTime"
+            from webhook_delivery
+            where webhook_id = $1
+            order by created_at desc, rowid desc
+            limit $2 offset $3
+        "#,
+        id,
+        limit,
+        offset
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(deliveries))
+}
+
+/// Puts a dead-lettered delivery back in the queue with a fresh set of attempts.
+pub async fn retry_delivery(
+    auth_user: AuthUser,
+    ctx: Extension<ApiContext>,
+    Path((id, delivery_id)): Path<(Uuid, Uuid)>
+) -> Result<()> {
+    ensure_owner(&ctx.db, id, auth_user.user_id).await?;
+
+    let requeued = sqlx::query!(
+        r#"
+            update webhook_delivery
+            set status = 'pending', attempts = 0, next_attempt_at = current_timestamp
+            where id = $1 and webhook_id = $2 and status = 'dead'
+        "#,
+        delivery_id,
+        id
+    )
+    .execute(&ctx.db)
+    .await?
+    .rows_affected();
+
+    if requeued == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(())
+}
+
+async fn ensure_owner(db: &SqlitePool, webhook_id: Uuid, user_id: Uuid) -> Result<()> {
+    let is_owner = sqlx::query_scalar!(
+        r#"select exists(select 1 from webhook where id = $1 and owner_id = $2) as "exists!: bool""#,
+        webhook_id,
+        user_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    if !is_owner {
+        return Err(Error::NotFound);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+// Runs two instances on 127.0.0.1 and has them federate over plain HTTP:
+// a user of one follows a user of the other, gets their message and its edit, and likes it.
+//
+// Activities are delivered by the background task, which polls every few seconds,
+// so this takes a little while.
+
+use clap::Parser;
+use kiwi::config::Config;
+use kiwi::router::server;
+use serde_json::{json, Value};
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Executor;
+use std::future::Future;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+
+// How long each activity gets to reach the other instance.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Instance {
+    url: String,
+    db: SqlitePool,
+    path: PathBuf,
+}
+
+impl Instance {
+    async fn start() -> Instance {
+        // The port is freed again for `serve` to bind it.
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let url = format!("http://127.0.0.1:{}", port);
+
+        let path = std::env::temp_dir().join(format!("kiwi-federation-{}.db", Uuid::new_v4()));
+        let database_url = format!("sqlite://{}?mode=rwc", path.display());
+
+        let db = SqlitePoolOptions::new().connect(&database_url).await.unwrap();
+        migrate(&db).await;
+
+        let config = Config::parse_from([
+            "kiwi",
+            "--database-url", &database_url,
+            "--hmac-key", "test",
+            "--bind-address", &format!("127.0.0.1:{}", port),
+            "--public-url", &url,
+            "--federation-allow-private-addresses",
+        ]);
+
+        tokio::spawn(server::serve(config, db.clone()));
+
+        let http = reqwest::Client::new();
+        wait_for("the server to start", || async {
+            http.get(format!("{}/nodeinfo/2.0", url)).send().await.is_ok()
+        })
+        .await;
+
+        Instance { url, db, path }
+    }
+
+    /// Registers a user and returns their token.
+    async fn create_user(&self, username: &str) -> String {
+        let user = self
+            .post(None, "/api/users", json!({
+                "username": username,
+                "email": format!("{}@example.com", username),
+                "password": "correct horse battery staple",
+            }))
+            .await;
+
+        user["token"].as_str().unwrap().to_string()
+    }
+
+    async fn post(&self, token: Option<&str>, path: &str, body: Value) -> Value {
+        self.send(reqwest::Method::POST, token, path, body).await
+    }
+
+    async fn patch(&self, token: Option<&str>, path: &str, body: Value) -> Value {
+        self.send(reqwest::Method::PATCH, token, path, body).await
+    }
+
+    async fn send(&self, method: reqwest::Method, token: Option<&str>, path: &str, body: Value) -> Value {
+        let mut request = reqwest::Client::new()
+            .request(method.clone(), format!("{}{}", self.url, path))
+            .header("content-type", "application/json")
+            .body(body.to_string());
+
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Token {}", token));
+        }
+
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        let body = response.text().await.unwrap();
+        assert!(status.is_success(), "{} {} failed with {}: {}", method, path, status, body);
+
+        serde_json::from_str(&body).unwrap_or(Value::Null)
+    }
+
+    fn host(&self) -> &str {
+        self.url.trim_start_matches("http://")
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Applies the migrations, `user` and `message` first since the others refer to them.
+async fn migrate(db: &SqlitePool) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("migrations");
+
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| !path.ends_with("user.sql") && !path.ends_with("message.sql"))
+        .collect();
+    files.sort();
+    files.splice(0..0, [dir.join("user.sql"), dir.join("message.sql")]);
+
+    for file in files {
+        let sql = std::fs::read_to_string(&file).unwrap();
+        db.execute(sql.as_str()).await.unwrap();
+    }
+}
+
+async fn wait_for<F, Fut>(what: &str, mut done: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + DELIVERY_TIMEOUT;
+
+    while !done().await {
+        assert!(tokio::time::Instant::now() < deadline, "timed out waiting for {}", what);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn follow_post_and_like_across_instances() {
+    let a = Instance::start().await;
+    let b = Instance::start().await;
+
+    let alice = a.create_user("alice").await;
+    let bob = b.create_user("bob").await;
+
+    // Bob follows alice, her server accepts right away.
+    let following = b
+        .post(Some(&bob), "/api/federation/follows", json!({ "account": format!("alice@{}", a.host()) }))
+        .await;
+    assert_eq!(following["accepted"], false);
+
+    wait_for("the follow to be accepted", || async {
+        sqlx::query_scalar::<_, bool>("select accepted from follow")
+            .fetch_optional(&b.db)
+            .await
+            .unwrap()
+            .unwrap_or(false)
+    })
+    .await;
+
+    let follower_count: i64 = sqlx::query_scalar("select count(*) from follow where accepted")
+        .fetch_one(&a.db)
+        .await
+        .unwrap();
+    assert_eq!(follower_count, 1);
+
+    // Alice's message is delivered to bob's server.
+    let message = a.post(Some(&alice), "/messages", json!({ "message": "hello from a" })).await;
+
+    let remote_message = || {
+        sqlx::query_scalar::<_, Uuid>("select id from message where message = 'hello from a'").fetch_optional(&b.db)
+    };
+    wait_for("the message to arrive", || async { remote_message().await.unwrap().is_some() }).await;
+    let remote_message_id = remote_message().await.unwrap().unwrap();
+
+    // Her edit follows it as an `Update`.
+    let message_id = message["id"].as_str().unwrap();
+    a.patch(Some(&alice), &format!("/message/{}", message_id), json!({ "message": "hello again from a" })).await;
+
+    wait_for("the edit to arrive", || async {
+        sqlx::query_scalar::<_, String>("select message from message where id = $1")
+            .bind(remote_message_id)
+            .fetch_one(&b.db)
+            .await
+            .unwrap()
+            == "hello again from a"
+    })
+    .await;
+
+    // Bob likes it on his server, and the like is counted on alice's.
+    b.post(Some(&bob), &format!("/message/{}/like", remote_message_id), Value::Null).await;
+
+    wait_for("the like to arrive", || async {
+        sqlx::query_scalar::<_, i64>(r#"select count(*) from "like""#)
+            .fetch_one(&a.db)
+            .await
+            .unwrap()
+            == 1
+    })
+    .await;
+}
@@ -0,0 +1,96 @@
+// Runs the S3 storage against a local MinIO, started for example with
+//
+//     docker run -p 9000:9000 minio/minio server /data
+//     mc alias set local http://localhost:9000 minioadmin minioadmin && mc mb local/kiwi-test
+//
+// and then `cargo test --test s3_storage -- --ignored`.
+// `S3_TEST_ENDPOINT`, `S3_TEST_BUCKET`, `S3_TEST_ACCESS_KEY_ID` and `S3_TEST_SECRET_ACCESS_KEY`
+// override the defaults above.
+
+use bytes::Bytes;
+use clap::Parser;
+use kiwi::config::Config;
+use kiwi::storage::{s3::S3Storage, Storage};
+use time::Duration;
+use uuid::Uuid;
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+fn storage() -> S3Storage {
+    let config = Config::parse_from([
+        "kiwi",
+        "--database-url", "sqlite::memory:",
+        "--hmac-key", "test",
+        "--storage-backend", "s3",
+        "--s3-endpoint", &env_or("S3_TEST_ENDPOINT", "http://localhost:9000"),
+        "--s3-bucket", &env_or("S3_TEST_BUCKET", "kiwi-test"),
+        "--s3-access-key-id", &env_or("S3_TEST_ACCESS_KEY_ID", "minioadmin"),
+        "--s3-secret-access-key", &env_or("S3_TEST_SECRET_ACCESS_KEY", "minioadmin"),
+        "--s3-path-style",
+    ]);
+
+    S3Storage::new(&config).unwrap()
+}
+
+#[tokio::test]
+#[ignore = "needs a local MinIO"]
+async fn put_get_and_delete() {
+    let storage = storage();
+    let key = format!("test/{}.txt", Uuid::new_v4());
+    let bytes = Bytes::from_static(b"hello from kiwi");
+
+    storage.put(&key, "text/plain", bytes.clone()).await.unwrap();
+
+    assert_eq!(storage.get(&key).await.unwrap(), Some(bytes.clone()));
+    assert_eq!(storage.size(&key).await.unwrap(), Some(bytes.len() as u64));
+    assert_eq!(storage.key(&storage.url(&key)), Some(key.clone()));
+
+    storage.delete(&key).await.unwrap();
+
+    assert_eq!(storage.get(&key).await.unwrap(), None);
+    assert_eq!(storage.size(&key).await.unwrap(), None);
+    // Deleting a missing file is not an error.
+    storage.delete(&key).await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs a local MinIO"]
+async fn presigned_upload_and_download() {
+    let storage = storage();
+    let http = reqwest::Client::new();
+    let key = format!("test/{}.bin", Uuid::new_v4());
+    let bytes = Bytes::from_static(b"\x00\x01\x02 presigned");
+
+    let upload_url = storage
+        .presign_upload(&key, "application/octet-stream", Duration::minutes(5))
+        .await
+        .unwrap();
+
+    // The signature covers the content type.
+    let rejected = http
+        .put(&upload_url)
+        .header("content-type", "text/plain")
+        .body(bytes.clone())
+        .send()
+        .await
+        .unwrap();
+    assert!(rejected.status().is_client_error());
+
+    let uploaded = http
+        .put(&upload_url)
+        .header("content-type", "application/octet-stream")
+        .body(bytes.clone())
+        .send()
+        .await
+        .unwrap();
+    assert!(uploaded.status().is_success(), "upload failed with {}", uploaded.status());
+
+    let download_url = storage.presign_download(&key, Duration::minutes(5)).await.unwrap();
+    let downloaded = http.get(&download_url).send().await.unwrap();
+    assert!(downloaded.status().is_success());
+    assert_eq!(downloaded.bytes().await.unwrap(), bytes);
+
+    storage.delete(&key).await.unwrap();
+}